@@ -47,17 +47,97 @@ end
 local function cyclefn(fn)
     return function (n)
         for i = 1, n, 1 do
-            if not fn() then
-                return false
+            local ok, err = fn()
+            if not ok then
+                return false, err
             end
         end
     end
 end
 
+-- error messages from the turtle api, as the server's FailureReason
+local failures = {
+    ["Movement obstructed"] = "Obstructed",
+    ["Out of fuel"] = "NoFuel",
+    ["No space for items"] = "InventoryFull",
+    ["No items to drop"] = "NoItems",
+    ["No items to place"] = "NoItems",
+    ["No items to combust"] = "NoItems",
+    ["No items to take"] = "NoItems",
+    ["Nothing to dig here"] = "NothingToDig",
+    ["Cannot break unbreakable block"] = "Denied",
+    ["Cannot break protected block"] = "Denied",
+    ["Cannot place block here"] = "Denied",
+    ["Cannot place in protected area"] = "Denied",
+    ["Timed out"] = "TimedOut",
+}
+
+local function failure(err)
+    return { ["Failure"] = failures[err] or { ["Other"] = tostring(err or "") } }
+end
+
+-- select a slot then act, in one round trip
+local function fromslot(fn)
+    return function (slot)
+        turtle.select(slot)
+        return fn()
+    end
+end
+
+local function refuelfrom(args)
+    turtle.select(args[1])
+    return turtle.refuel(args[2])
+end
+
+local function transferto(args)
+    return turtle.transferTo(args[1], args[2])
+end
+
+-- only crafty turtles can
+local function craft(limit)
+    if not turtle.craft then
+        return false, "No crafting table"
+    end
+    return turtle.craft(limit)
+end
+
 local function iteminfo(slot)
     return { ["Item"] = turtle.getItemDetail(slot) }
 end
 
+local function itemdetail(slot)
+    return { ["Item"] = turtle.getItemDetail(slot, true) }
+end
+
+-- name and properties of a block, with properties as strings for the server
+local function inspectfn(inspect)
+    return function()
+        local present, block = inspect()
+        if not present then
+            return { ["BlockState"] = { name = "minecraft:air", properties = {} } }
+        end
+        local properties = {}
+        for key, value in pairs(block.state or {}) do
+            properties[key] = tostring(value)
+        end
+        return { ["BlockState"] = { name = block.name, properties = properties } }
+    end
+end
+
+-- getEquippedLeft and friends only exist on newer versions
+local function equipped(side)
+    return function()
+        local get = turtle["getEquipped" .. side]
+        if not get then
+            return false, "Not supported"
+        end
+        local item = get()
+        if item then
+            return { ["Item"] = item }
+        end
+    end
+end
+
 local function restartfront()
     local front = peripheral.wrap("front")
     if not front or not front.shutdown then
@@ -80,10 +160,62 @@ local function name()
     return { ["Name"] = os.computerLabel() }
 end
 
+-- one step of a staircase, stopping halfway if the second move fails
+local function stairfn(vertical)
+    return function()
+        local ok, err = turtle.forward()
+        if not ok then
+            return false, err
+        end
+        return vertical()
+    end
+end
+
+-- needs a wireless modem and gps hosts in range
+local function locate()
+    local x, y, z = gps.locate(2)
+    if not x then
+        return false, "No GPS fix"
+    end
+    -- the server wants whole blocks
+    return { ["Location"] = { math.floor(x + 0.5), math.floor(y + 0.5), math.floor(z + 0.5) } }
+end
+
 local function inventoryinfo()
     return { ["Inventory"] = peripheral.wrap("front").list() }
 end
 
+-- things to wait for, as the server's Condition
+local conditions = {
+    ["FrontClear"] = function()
+        return not turtle.detect()
+    end,
+    ["FrontNot"] = function(name)
+        local present, block = turtle.inspect()
+        return not present or not string.find(block.name, name, 1, true)
+    end,
+    ["ItemsAhead"] = function()
+        local front = peripheral.wrap("front")
+        return front ~= nil and front.list ~= nil and next(front.list()) ~= nil
+    end,
+}
+
+local function waituntil(args)
+    local condition, timeout = args[1], args[2]
+    local condargs = nil
+    if type(condition) == "table" then
+        condition, condargs = pairs(condition)(condition)
+    end
+    local deadline = os.clock() + timeout
+    while not conditions[condition](condargs) do
+        if os.clock() >= deadline then
+            return false, "Timed out"
+        end
+        sleep(0.5)
+    end
+    return true
+end
+
 local function drawui(command, args, backoff)
     term.setTextColor(colors.white)
     local name = os.getComputerLabel()
@@ -127,8 +259,57 @@ local function drawui(command, args, backoff)
     term.setCursorPos(1,10)
 end
 
+-- a command's return values as the server's TurtleCommandResponse
+local function result(ret, err)
+    if type(ret) == "boolean" then
+        if ret then
+            return "Success"
+        end
+        term.setCursorPos(1,11)
+        term.clearLine()
+        term.setTextColor(colors.white)
+        term.write("error: ")
+        term.setTextColor(colors.red)
+        print(err)
+        return failure(err)
+    elseif ret then
+        return ret
+    end
+    return "None"
+end
+
+-- what the server hears after a command: fuel, the blocks around and the result
+local function report(ret_table)
+    local ahead = "minecraft:air"
+    local above = "minecraft:air"
+    local below = "minecraft:air"
+
+    local a,b = turtle.inspect()
+    if a then
+        ahead = b.name
+    end
+
+    local a,b = turtle.inspectUp()
+    if a then
+        above = b.name
+    end
+
+    local a,b = turtle.inspectDown()
+    if a then
+        below = b.name
+    end
+    return {
+        fuel = turtle.getFuelLevel(),
+        ahead = ahead,
+        above = above,
+        below = below,
+        ret = ret_table,
+    }
+end
+
 local commands = {
     ["Wait"] = sleep,
+    ["WaitUntil"] = waituntil,
     ["Forward"] = cyclefn(turtle.forward),
     ["Backward"] = cyclefn(turtle.back),
     ["Up"] = cyclefn(turtle.up),
@@ -140,8 +321,16 @@ local commands = {
     ["SuckUp"] = turtle.suckUp,
     ["SuckDown"] = turtle.suckDown,
     ["Select"] = turtle.select,
+    ["EquipLeft"] = turtle.equipLeft,
+    ["EquipRight"] = turtle.equipRight,
+    ["EquippedLeft"] = equipped("Left"),
+    ["EquippedRight"] = equipped("Right"),
     ["Refuel"] = turtle.refuel,
+    ["RefuelFrom"] = refuelfrom,
+    ["TransferTo"] = transferto,
+    ["Craft"] = craft,
     ["ItemInfo"] = iteminfo,
+    ["ItemDetail"] = itemdetail,
     ["InventoryInfo"] = inventoryinfo,
     ["Left"] = turtle.turnLeft,
     ["Right"] = turtle.turnRight,
@@ -151,14 +340,40 @@ local commands = {
     ["PlaceUp"] = turtle.placeUp,
     ["Place"] = turtle.place,
     ["PlaceDown"] = turtle.placeDown,
+    ["PlaceFrom"] = fromslot(turtle.place),
+    ["PlaceUpFrom"] = fromslot(turtle.placeUp),
+    ["PlaceDownFrom"] = fromslot(turtle.placeDown),
     ["Update"] = update,
     ["CycleFront"] = restartfront,
     ["Poweroff"] = os.shutdown,
     ["GetFuelLimit"] = turtle.getFuelLimit,
     ["Name"] = name,
     ["NameFront"] = namefront,
+    ["Inspect"] = inspectfn(turtle.inspect),
+    ["InspectUp"] = inspectfn(turtle.inspectUp),
+    ["InspectDown"] = inspectfn(turtle.inspectDown),
+    ["Locate"] = locate,
+    ["ForwardUp"] = stairfn(turtle.up),
+    ["ForwardDown"] = stairfn(turtle.down),
 };
 
+-- several commands in one round trip, reporting after each and stopping at the first to fail
+commands["Batch"] = function(list)
+    local steps = {}
+    for _, command in ipairs(list) do
+        local args = nil
+        if type(command) == "table" then
+            command, args = pairs(command)(command)
+        end
+        local step = report(result(commands[command](args)))
+        table.insert(steps, step)
+        if type(step.ret) == "table" and step.ret["Failure"] then
+            break
+        end
+    end
+    return { ["Batch"] = steps }
+end
+
 if not ipaddr then
     if fs.exists("/disk/ip") then
         local ipfile = fs.open("/disk/ip")
@@ -174,7 +389,29 @@ local idfile = fs.open("id", "r")
 
 local id = nil
 local command = nil
+local token = nil
 local backoff = 0;
+local ws = nil
+
+-- next command pushed over the websocket, nil if none came in time or it closed
+local function receive(timeout)
+    while true do
+        local open, msg = pcall(ws.receive, timeout)
+        if not open then
+            ws = nil
+            return nil
+        end
+        if not msg then
+            return nil
+        end
+        local pushed = textutils.unserialiseJSON(msg)
+        -- a resend of what was already carried out
+        if pushed.token ~= token then
+            token = pushed.token
+            return pushed.command
+        end
+    end
+end
 
 if not idfile then
     local fuel = turtle.getFuelLevel()
@@ -198,6 +435,7 @@ if not idfile then
     end
 
     local info = {
+        label = os.getComputerLabel(),
         fuel = fuel,
         fuellimit = maxfuel,
         position = startpos.pos,
@@ -240,9 +478,22 @@ else
     end
 end
 
+-- commands come as soon as there are any over a websocket, otherwise keep polling
+ws = http.websocket((endpoint:gsub("^http", "ws")) .. "/turtle/" .. id .. "/ws") or nil
+
 term.clear()
 
 repeat
+    -- waiting for the server to come up with something, which it can send straight away
+    if ws and type(command) == "table" and command["Wait"] then
+        local pushed = receive(command["Wait"])
+        if pushed then
+            command = pushed
+        elseif ws then
+            command = nil
+        end
+    end
+
     local args = nil
     if type(command) == "table" then
         command, args = pairs(command)(command)
@@ -263,58 +514,28 @@ repeat
 
     command = nil
 
-    local ret_table = nil
-    if type(ret) == "boolean" then
-        if ret then
-            ret_table = "Success"
-        else
-            ret_table = "Failure"
-            term.setCursorPos(1,11)
-            term.clearLine()
-            term.setTextColor(colors.white)
-            term.write("error: ")
-            term.setTextColor(colors.red)
-            print(err)
-        end
-    elseif ret then
-        ret_table = ret
-    else
-        ret_table = "None"
-    end
+    local info = report(result(ret, err))
+    info.token = token
 
-    local ahead = "minecraft:air"
-    local above = "minecraft:air"
-    local below = "minecraft:air"
-
-    local a,b = turtle.inspect()
-    if a then
-        ahead = b.name
-    end
-
-    local a,b = turtle.inspectUp()
-    if a then
-        above = b.name
+    if ws and pcall(ws.send, textutils.serializeJSON(info)) then
+        command = receive()
+    else
+        ws = nil
     end
 
-    local a,b = turtle.inspectDown()
-    if a then
-        below = b.name
+    local rsp = nil
+    if not ws then
+        rsp = http.post(
+            endpoint .. "/turtle/" .. id  .. "/update" ,
+            textutils.serializeJSON(info),
+            { ["Content-Type"] = "application/json" }
+        )
     end
-    local info = {
-        fuel = turtle.getFuelLevel(),
-        ahead = ahead,
-        above = above,
-        below = below,
-        ret = ret_table,
-    }
-
-    local rsp = http.post(
-        endpoint .. "/turtle/" .. id  .. "/update" ,
-        textutils.serializeJSON(info),
-        { ["Content-Type"] = "application/json" }
-    )
-    if rsp then
+    if ws then
+        backoff = 0
+    elseif rsp then
         backoff = 0
+        token = tonumber(rsp.getResponseHeaders()["x-command-token"])
         command = textutils.unserialiseJSON(rsp.readAll())
     else
         sleep(backoff)
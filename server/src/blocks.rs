@@ -1,106 +1,478 @@
 extern crate test;
-use std::{sync::Arc, ops::Sub, collections::HashMap};
+use std::{sync::Arc, ops::{Sub, Deref, DerefMut}, collections::{HashMap, BTreeMap}, fmt};
 
 use anyhow::{Ok, anyhow};
 use nalgebra::Vector3;
 use rstar::{PointDistance, RTree, RTreeObject, AABB, Envelope};
+use rustmatica::{Litematic, Region, mcdata::{GenericBlockState, util::BlockPos}};
 use serde::{Deserialize, Serialize};
 use tokio::sync::{RwLock, OwnedRwLockReadGuard, OwnedRwLockWriteGuard};
+use tracing::error;
 
-use crate::{turtle::TurtleCommand, paths::{self, TRANSPARENT}};
+use crate::{turtle::TurtleCommand, paths::{self, RouteCache, Protection, BlockConfig}, updates::Publisher};
 
+/// Edge length of a chunk, bigger chunks mean fewer allocations but more empty space.
+/// Saves record the size they were written with and are re-chunked on load if it changes
 const CHUNK_SIZE: usize = 8;
-const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
-const CHUNK_VEC: Vec3  = Vec3::new(CHUNK_SIZE as i32, CHUNK_SIZE as i32, CHUNK_SIZE as i32);
 
-#[derive(Serialize, Deserialize)]
+/// Starts every save that records its layout, which the first few didn't
+const SAVE_MAGIC: [u8; 8] = *b"avarus\0w";
+/// Layout of `World` on disk, bumped whenever it changes
+///
+/// 1 was `LegacyWorld` and 2 `StatelessWorld`, both only ever saved untagged
+const SAVE_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct World { // TODO: make r-trees faster than this, for my sanity
+    chunk_size: usize,
     index: HashMap<Vec3, usize>,
     data: Vec<Chunk>,
     last: Option<usize>,
+    /// Properties of the few blocks that have been inspected, such as facing
+    states: HashMap<Vec3, BTreeMap<String, String>>,
+    /// Blocks seen so far, counted as they are set rather than on every look
+    #[serde(skip)]
+    known: usize,
 }
 
 impl World {
     pub fn new() -> Self {
+        Self::sized(CHUNK_SIZE)
+    }
+
+    /// Empty world with chunks `chunk_size` blocks on a side, rather than the usual size
+    fn sized(chunk_size: usize) -> Self {
         World{
+           chunk_size,
            index:  HashMap::new(),
            data: Vec::new(),
            last: None,
+           states: HashMap::new(),
+           known: 0,
         }
     }
     pub fn get(&self, block: Vec3) -> Option<Block> {
         let chunk = self.get_chunk(block)?;
-        Some(chunk.get(block)?)
+        Some(Block { state: self.states.get(&block).cloned(), ..chunk.get(block, self.chunk_size)? })
     }
 
-    pub fn set(&mut self, block: Block) {
-        let chunk_coords = block.pos.map(|n| i32::div_floor(n,CHUNK_SIZE as i32));
+    pub fn set(&mut self, mut block: Block) {
+        match block.state.take() {
+            Some(state) => { self.states.insert(block.pos, state); },
+            // a name alone doesn't say the state changed, unless it is a different block
+            None if self.states.contains_key(&block.pos) => {
+                if self.get(block.pos).is_some_and(|b| b.name != block.name) {
+                    self.states.remove(&block.pos);
+                }
+            },
+            None => {},
+        }
+
+        let size = self.chunk_size;
+        let chunk_coords = self.chunk_of(block.pos);
 
         let chunk = self.last
-            .filter(|n| self.data[*n].contains(&block.pos))
+            .filter(|n| self.data[*n].contains(&block.pos, size))
             .or_else(|| {
                 self.index.get(&chunk_coords).map(|c| *c)
             })
             .map(|n| *self.last.insert(n));
 
-        match chunk {
-            Some(chunk) => {
-                self.data[chunk].set(block).unwrap();
-            },
+        let placed = match chunk {
+            Some(chunk) => self.data[chunk].set(block, size),
             None => {
-                let mut new_chunk = Chunk::new(chunk_coords);
-                new_chunk.set(block).unwrap();
+                let mut new_chunk = Chunk::new(chunk_coords, size);
+                let placed = new_chunk.set(block, size);
                 self.data.push(new_chunk);
                 self.index.insert(chunk_coords, self.data.len() - 1);
+                placed
+            },
+        };
+
+        // a bad map update isn't worth taking the server down over
+        match placed {
+            Result::Ok(true) => self.known += 1,
+            Result::Ok(false) => {},
+            Err(e) => {
+                error!("dropped block: {e}");
+                self.last = None;
             },
         }
     }
 
+    /// The world as saved to disk, tagged with the layout it was written in
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(&(SAVE_MAGIC, SAVE_VERSION, self))?)
+    }
+
+    /// Load a world saved with `to_bytes`, re-chunking it if the chunk size has changed
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let world = match bytes.strip_prefix(SAVE_MAGIC.as_slice()) {
+            Some(saved) => match bincode::deserialize::<u32>(saved)? {
+                SAVE_VERSION => bincode::deserialize::<(u32, World)>(saved)?.1,
+                version => return Err(anyhow!("world saved in layout {version}, this server only reads up to {SAVE_VERSION}")),
+            },
+            None => Self::unversioned(bytes)?,
+        };
+        Ok(world.rechunked())
+    }
+
+    /// Saves from before the layout was recorded, told apart by which one they parse as
+    fn unversioned(bytes: &[u8]) -> anyhow::Result<Self> {
+        if let Result::Ok(world) = bincode::deserialize::<World>(bytes) {
+            return Ok(world);
+        }
+        // saves from before block states were kept, which run out where they would be
+        if let Result::Ok(world) = bincode::deserialize::<StatelessWorld>(bytes) {
+            return Ok(World {
+                chunk_size: world.chunk_size,
+                index: world.index,
+                data: world.data,
+                last: world.last,
+                states: HashMap::new(),
+                known: 0,
+            });
+        }
+        // saves from before the chunk size was recorded
+        let mut world = World::new();
+        for block in bincode::deserialize::<LegacyWorld>(bytes)?.data.into_iter().flat_map(LegacyChunk::blocks) {
+            world.set(block);
+        }
+        Ok(world)
+    }
+
+    /// Re-chunk a loaded world if it was saved with another chunk size, counting its blocks either way
+    fn rechunked(self) -> Self {
+        if self.chunk_size == CHUNK_SIZE {
+            return self.counted();
+        }
+        let size = self.chunk_size;
+        let mut world = World::new();
+        for block in self.data.into_iter().flat_map(|c| c.blocks(size)) {
+            world.set(block);
+        }
+        world.states = self.states;
+        world
+    }
+
+    /// Every known block, air included
+    pub fn blocks(&self) -> impl Iterator<Item = Block> + '_ {
+        self.data.iter()
+            .flat_map(|c| c.clone().blocks(self.chunk_size))
+            .map(|b| Block { state: self.states.get(&b.pos).cloned(), ..b })
+    }
+
+    /// Every known block in a box, air included, looking only at chunks that overlap it
+    pub fn blocks_in(&self, aabb: AABB<[i32; 3]>) -> Vec<Block> {
+        let (lower, upper) = (Vec3::from(aabb.lower()), Vec3::from(aabb.upper()));
+        let (first, last) = (self.chunk_of(lower), self.chunk_of(upper));
+
+        // a box over more chunks than are known is cheaper to check against each of them
+        let span = (last - first).map(|n| n as i64 + 1).product();
+        let chunks: Vec<&Chunk> = match span < self.data.len() as i64 {
+            true => (first.x..=last.x).flat_map(|x| (first.y..=last.y).flat_map(move |y| (first.z..=last.z).map(move |z| Vec3::new(x, y, z))))
+                .filter_map(|c| self.index.get(&c).map(|i| &self.data[*i]))
+                .collect(),
+            false => self.data.iter().filter(|c| c.bounds(self.chunk_size).intersects(&aabb)).collect(),
+        };
+
+        let mut blocks = Vec::new();
+        for chunk in chunks {
+            let bounds = chunk.bounds(self.chunk_size);
+            let (from, to) = (lower.sup(&bounds.lower().into()), upper.inf(&bounds.upper().into()));
+            for x in from.x..=to.x {
+                for y in from.y..=to.y {
+                    for z in from.z..=to.z {
+                        let pos = Vec3::new(x, y, z);
+                        if let Some(block) = chunk.get(pos, self.chunk_size) {
+                            blocks.push(Block { state: self.states.get(&pos).cloned(), ..block });
+                        }
+                    }
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Known blocks other than air, only those in `within` if given
+    fn solid(&self, within: Option<AABB<[i32; 3]>>) -> Vec<Block> {
+        let mut blocks = match within {
+            Some(aabb) => self.blocks_in(aabb),
+            None => self.blocks().collect(),
+        };
+        blocks.retain(|b| b.name != "minecraft:air");
+        blocks
+    }
+
+    /// Lowest and highest corners of the box around known blocks other than air, only those in `within` if given
+    pub fn bounds(&self, within: Option<AABB<[i32; 3]>>) -> Option<(Vec3, Vec3)> {
+        self.solid(within).into_iter()
+            .fold(None, |bounds, b| Some(match bounds {
+                Some((lower, upper)) => (b.pos.inf(&lower), b.pos.sup(&upper)),
+                None => (b.pos, b.pos),
+            }))
+    }
+
+    /// Blocks in the box between two corners, saturating rather than overflowing
+    pub fn volume(lower: Vec3, upper: Vec3) -> i64 {
+        (upper.map(i64::from) - lower.map(i64::from)).iter()
+            .fold(1, |volume, n| volume.saturating_mul(n.abs() + 1))
+    }
+
+    /// The box between two corners as a Litematica schematic, with air and unknown blocks left empty
+    ///
+    /// Every block in the box takes space, so check its volume first
+    pub fn to_litematic(&self, lower: Vec3, upper: Vec3) -> anyhow::Result<Litematic<'static>> {
+        let (lower, upper) = (lower.inf(&upper), lower.sup(&upper));
+        let side = |a: i32, b: i32| i32::try_from(i64::from(b) - i64::from(a) + 1)
+            .map_err(|_| anyhow!("the box is too wide for a schematic"));
+        let mut region = Region::new(
+            "world".into(),
+            BlockPos::new(lower.x, lower.y, lower.z),
+            BlockPos::new(side(lower.x, upper.x)?, side(lower.y, upper.y)?, side(lower.z, upper.z)?),
+        );
+
+        for block in self.solid(Some(AABB::from_corners(lower.into(), upper.into()))) {
+            let local = block.pos - lower;
+            region.set_block(BlockPos::new(local.x, local.y, local.z), GenericBlockState {
+                name: block.name.into(),
+                properties: block.state.unwrap_or_default().into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            });
+        }
+
+        let mut litematic = Litematic::new("avarus".into(), "Blocks mapped by turtles".into(), "avarus".into());
+        litematic.regions.push(region);
+        Ok(litematic)
+    }
+
+    /// Blocks seen so far, air included
+    pub fn known(&self) -> usize {
+        self.known
+    }
+
+    /// Count the blocks of a world that was loaded whole
+    fn counted(mut self) -> Self {
+        self.known = self.data.iter().map(Chunk::known).sum();
+        self
+    }
+
+    /// Drop chunks with nothing but air in them, returning how many were removed
+    ///
+    /// The air is forgotten, so pathfinding treats it as uncharted afterwards
+    pub fn compact(&mut self) -> usize {
+        let before = self.data.len();
+        let forgotten: usize = self.data.iter().filter(|c| c.empty()).map(Chunk::known).sum();
+        self.known -= forgotten;
+        self.data.retain(|c| !c.empty());
+        self.index = self.data.iter().enumerate().map(|(i, c)| (c.pos, i)).collect();
+        let (index, size) = (&self.index, self.chunk_size as i32);
+        self.states.retain(|pos, _| index.contains_key(&pos.map(|n| i32::div_floor(n, size))));
+        self.last = None;
+        before - self.data.len()
+    }
+
     fn get_chunk(&self, block: Vec3) -> Option<&Chunk> {
-        let block = block.map(|n| i32::div_floor(n,CHUNK_SIZE as i32));
         if let Some(last) = self.last {
-            if self.data[last].contains(&block) {
+            if self.data[last].contains(&block, self.chunk_size) {
                 return Some(&self.data[last])
             }
         }
-        self.index.get(&block).map(|i| &self.data[*i])
+        self.index.get(&self.chunk_of(block)).map(|i| &self.data[*i])
     }
+
+    /// Position of the chunk holding a block, in chunk coordinates
+    fn chunk_of(&self, block: Vec3) -> Vec3 {
+        block.map(|n| i32::div_floor(n, self.chunk_size as i32))
+    }
+}
+
+/// What is known to be at a point
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Occupancy {
+    /// A block that can't be moved through
+    Occupied,
+    /// Air, fluids, or anything else that can be moved through
+    Clear,
+    /// Never seen
+    Unknown,
 }
 
 #[derive(Clone)]
 pub struct SharedWorld {
     state: Arc<RwLock<World>>, // interior mutability to get around the 
                               // questionable architecture of this project
+    updates: Publisher,
+    routes: RouteCache,
+    protection: Protection,
+    /// Block lists in use, the defaults until some are loaded
+    blocks: Arc<std::sync::RwLock<Arc<BlockConfig>>>,
 }
 
 impl SharedWorld {
-    pub fn new() -> Self { Self { state: Arc::new(RwLock::new(World::new())) } }
-    pub fn from_world(tree: World) -> Self { Self { state: Arc::new(RwLock::new(tree)) } }
+    pub fn new() -> Self { Self::from_world(World::new()) }
+    pub fn from_world(tree: World) -> Self {
+        Self { state: Arc::new(RwLock::new(tree)), updates: Publisher::new(), routes: RouteCache::default(), protection: Protection::default(), blocks: Default::default() }
+    }
+
+    /// A copy of the blocks as they are now, with the same block lists as this world
+    pub async fn detached(&self) -> Self {
+        Self { protection: self.protection.clone(), blocks: self.blocks.clone(), ..Self::from_world(self.state.read().await.clone()) }
+    }
+
+    /// Where changes are sent for live viewers
+    pub fn updates(&self) -> &Publisher {
+        &self.updates
+    }
+
+    /// Blocks no turtle digs here
+    pub fn protection(&self) -> &Protection {
+        &self.protection
+    }
+
+    /// Block lists as they are now, unchanged by any later reload
+    pub fn block_config(&self) -> Arc<BlockConfig> {
+        self.blocks.read().unwrap().clone()
+    }
+
+    pub fn set_block_config(&self, blocks: BlockConfig) {
+        *self.blocks.write().unwrap() = Arc::new(blocks);
+        self.routes.clear();
+    }
+
+    /// Replace the protected blocks, which routes may have gone through
+    pub fn set_protected(&self, blocks: Vec<String>) {
+        self.protection.set(blocks);
+        self.routes.clear();
+    }
+
+    /// Routes found through this world that are still good
+    pub fn routes(&self) -> &RouteCache {
+        &self.routes
+    }
 
     pub async fn get(&self, block: Vec3) -> Option<Block> {
         Some(self.state.read().await.get(block)?.clone())
     }
 
     pub async fn set(&self, block: Block) {
-        self.state.write().await.set(block);
+        self.updates.block(&block);
+        let pos = block.pos;
+        let mut world = self.state.write().await;
+        let changed = world.get(pos).map(|b| b.name).as_ref() != Some(&block.name);
+        world.set(block);
+        drop(world);
+        if changed {
+            self.routes.invalidate(pos);
+        }
     }
 
     /// Returns true if a known non-traversable block exists at the point
+    ///
+    /// Optimistic, unknown points are assumed to be clear
     pub async fn occupied(&self, block: Vec3) -> bool {
-        self.get(block).await.is_some_and(|b| !TRANSPARENT.contains(&b.name.as_str()))
+        self.occupancy(block).await == Occupancy::Occupied
+    }
+
+    pub async fn occupancy(&self, block: Vec3) -> Occupancy {
+        match self.get(block).await {
+            Some(b) if self.block_config().transparent(&b.name) => Occupancy::Clear,
+            Some(_) => Occupancy::Occupied,
+            None => Occupancy::Unknown,
+        }
+    }
+
+    /// Returns true if every point from `lower` up to but not including `upper` is known and matches,
+    /// the same bounds a quarry takes
+    ///
+    /// An empty region has nothing known in it, so is false
+    pub async fn all_known(&self, lower: Vec3, upper: Vec3, matches: impl Fn(&Block) -> bool) -> bool {
+        if lower.iter().zip(upper.iter()).any(|(l, u)| l >= u) {
+            return false;
+        }
+        let world = self.state.read().await;
+        (lower.x..upper.x).all(|x| (lower.y..upper.y).all(|y| (lower.z..upper.z).all(|z| {
+            world.get(Vec3::new(x, y, z)).is_some_and(|b| matches(&b))
+        })))
     }
 
     /// Returns true if a "garbage" block exists at the given point which you are free to destroy
     pub async fn garbage(&self, block: Vec3) -> bool {
-        self.get(block).await.is_some_and(|b| paths::difficulty(&b.name).is_some())
+        self.get(block).await.is_some_and(|b| paths::difficulty(&b.name, &self.block_config(), &self.protection.blocks()).is_some())
+    }
+
+    /// Whether the fluid at a point is a source block
+    ///
+    /// An inspected fluid says so itself, sources being level 0. Otherwise
+    /// this goes by the neighbors: only fluid with nothing above or beside it
+    /// to flow from has to be a source, and that takes all of them being known.
+    /// When in doubt it is considered flowing, as a solid block stops both.
+    ///
+    /// Returns None if the point is not a known fluid
+    pub async fn fluid_source(&self, block: Vec3) -> Option<bool> {
+        let blocks = self.block_config();
+        let world = self.state.read().await;
+        let fluid = world.get(block)?;
+        if !blocks.fluid(&fluid.name) {
+            return None;
+        }
+
+        if let Some(level) = fluid.state.as_ref().and_then(|s| s.get("level")) {
+            return Some(level == "0");
+        }
+
+        let unfed = [Vec3::y(), Vec3::x(), -Vec3::x(), Vec3::z(), -Vec3::z()].into_iter()
+            .all(|d| world.get(block + d).is_some_and(|b| b.name != fluid.name));
+
+        Some(unfed)
+    }
+
+    pub async fn known(&self) -> usize {
+        self.state.read().await.known()
+    }
+
+    pub async fn blocks_in(&self, aabb: AABB<[i32; 3]>) -> Vec<Block> {
+        self.state.read().await.blocks_in(aabb)
+    }
+
+    pub async fn compact(&self) -> usize {
+        let dropped = self.state.write().await.compact();
+        self.routes.clear();
+        dropped
     }
 
     pub async fn lock(self) -> OwnedRwLockReadGuard<World> {
         self.state.read_owned().await
     }
 
-    pub async fn lock_mut(self) -> OwnedRwLockWriteGuard<World> {
-        self.state.write_owned().await
+    /// Changes made through this aren't tracked, so every route is forgotten once it is dropped
+    pub async fn lock_mut(self) -> WorldMut {
+        WorldMut { world: self.state.write_owned().await, routes: self.routes }
+    }
+}
+
+/// Write access to the world that forgets cached routes when done
+pub struct WorldMut {
+    world: OwnedRwLockWriteGuard<World>,
+    routes: RouteCache,
+}
+
+impl Deref for WorldMut {
+    type Target = World;
+
+    fn deref(&self) -> &World {
+        &self.world
+    }
+}
+
+impl DerefMut for WorldMut {
+    fn deref_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+}
+
+impl Drop for WorldMut {
+    fn drop(&mut self) {
+        self.routes.clear();
     }
 }
 
@@ -108,53 +480,123 @@ impl SharedWorld {
 pub struct Block {
     pub name: String,
     pub pos: Vec3,
+    /// Properties such as facing or waterlogged, if the block has been inspected
+    #[serde(default)]
+    pub state: Option<BTreeMap<String, String>>,
+}
+
+impl Block {
+    /// A block known only by name, as most are
+    pub fn new(name: impl Into<String>, pos: Vec3) -> Self {
+        Self { name: name.into(), pos, state: None }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Chunk {
-    pos: Vec3, /// position in chunk coordinates (world/16)
-    data: [[[Option<String>;CHUNK_SIZE];CHUNK_SIZE];CHUNK_SIZE]
+    pos: Vec3, /// position in chunk coordinates (world/chunk size)
+    data: Vec<Option<String>>, // x, then y, then z
 }
 
+/// Chunks don't record their size, the world they are in does
 impl Chunk {
-    fn new(pos: Vec3) -> Self {
-        let data :[[[Option<String>;CHUNK_SIZE];CHUNK_SIZE];CHUNK_SIZE]= Default::default();
+    fn new(pos: Vec3, size: usize) -> Self {
         Self {
             pos,
-            data
+            data: vec![None; size * size * size],
         }
     }
 
-    fn set(&mut self, pos: Block) -> anyhow::Result<()> {
-        let chunk = self.pos.component_mul(&CHUNK_VEC);
-        if !self.contains(&pos.pos) {
-            return Err(anyhow!("out of bounds"));
+    fn index(local: Vector3<usize>, size: usize) -> usize {
+        (local.x * size + local.y) * size + local.z
+    }
+
+    /// Lowest corner, in world coordinates
+    fn origin(&self, size: usize) -> Vec3 {
+        self.pos * size as i32
+    }
+
+    /// Every known block, for a chunk saved with the given size
+    fn blocks(self, size: usize) -> impl Iterator<Item = Block> {
+        let origin = self.pos * size as i32;
+        self.data.into_iter().enumerate().filter_map(move |(i, name)| {
+            let local = Vec3::new((i / size / size) as i32, (i / size % size) as i32, (i % size) as i32);
+            Some(Block::new(name?, origin + local))
+        })
+    }
+
+    /// Returns true if nothing was known at the block before
+    fn set(&mut self, pos: Block, size: usize) -> anyhow::Result<bool> {
+        if !self.contains(&pos.pos, size) {
+            return Err(anyhow!("{} out of bounds of chunk {}", Coords(pos.pos), Coords(self.pos)));
         }
-        let local: Vector3<usize> = (pos.pos - chunk).map(|n| n as usize);
+        let local: Vector3<usize> = (pos.pos - self.origin(size)).map(|n| n as usize);
 
-        self.data[local.x][local.y][local.z] = Some(pos.name);
+        Ok(self.data[Self::index(local, size)].replace(pos.name).is_none())
+    }
 
-        Ok(())
+    fn known(&self) -> usize {
+        self.data.iter().flatten().count()
     }
 
-    fn get(&self, pos: Vec3) -> Option<Block> {
-        let chunk = self.pos.component_mul(&CHUNK_VEC);
-        let local = pos - chunk;
-        if !self.contains(&pos) {
+    fn get(&self, pos: Vec3, size: usize) -> Option<Block> {
+        if !self.contains(&pos, size) {
             return None;
         }
-        let local = local.map(|n| n as usize);
+        let local = (pos - self.origin(size)).map(|n| n as usize);
 
-        Some(Block {
-            name: self.data[local.x][local.y][local.z].clone()?,
-            pos,
-        })
+        Some(Block::new(self.data[Self::index(local, size)].clone()?, pos))
+    }
+
+    /// Blocks the chunk covers, in world coordinates
+    fn bounds(&self, size: usize) -> AABB<[i32; 3]> {
+        let lower = self.origin(size);
+        AABB::from_corners(lower.into(), (lower + Vec3::repeat(size as i32 - 1)).into())
     }
 
-    fn contains(&self, pos:&Vec3) -> bool {
-        let chunk = self.pos.component_mul(&CHUNK_VEC);
-        let local = pos - chunk;
-        local >= Vec3::zeros() && local < CHUNK_VEC
+    /// True if no block other than air is known
+    fn empty(&self) -> bool {
+        self.data.iter().flatten().all(|name| name == "minecraft:air")
+    }
+
+    fn contains(&self, pos:&Vec3, size: usize) -> bool {
+        let local = pos - self.origin(size);
+        local >= Vec3::zeros() && local < Vec3::repeat(size as i32)
+    }
+}
+
+/// Worlds saved before block states were kept
+#[derive(Deserialize)]
+struct StatelessWorld {
+    chunk_size: usize,
+    index: HashMap<Vec3, usize>,
+    data: Vec<Chunk>,
+    last: Option<usize>,
+}
+
+/// Worlds saved before `CHUNK_SIZE` was recorded
+#[derive(Deserialize)]
+struct LegacyWorld {
+    #[allow(unused)]
+    index: HashMap<Vec3, usize>,
+    data: Vec<LegacyChunk>,
+    #[allow(unused)]
+    last: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct LegacyChunk {
+    pos: Vec3,
+    data: [[[Option<String>;8];8];8],
+}
+
+impl LegacyChunk {
+    fn blocks(self) -> impl Iterator<Item = Block> {
+        let origin = self.pos * 8;
+        self.data.into_iter().flatten().flatten().enumerate().filter_map(move |(i, name)| {
+            let local = Vec3::new(i as i32 / 64, i as i32 / 8 % 8, i as i32 % 8);
+            Some(Block::new(name?, origin + local))
+        })
     }
 }
 
@@ -204,6 +646,10 @@ impl Position {
                 Some(Up(1))
             } else if to.pos == self.pos - Vec3::y() {
                 Some(Down(1))
+            } else if to.pos == self.pos + self.dir.unit() + Vec3::y() {
+                Some(ForwardUp)
+            } else if to.pos == self.pos + self.dir.unit() - Vec3::y() {
+                Some(ForwardDown)
             } else {
                 None
             }
@@ -224,7 +670,7 @@ impl Position {
     }
 
     /// Command to dig 
-    /// Assumes that "to" can be dug from your position
+    /// None if "to" isn't above, below, or directly ahead
     pub fn dig(&self, to: Vec3) -> Option<TurtleCommand> {
 
         // manhattan distance of 1 required to dig
@@ -232,21 +678,42 @@ impl Position {
             return None;
         }
 
-        // not covered: pointing away from to
-
         Some(match self.pos.y - to.y {
-            0 => TurtleCommand::Dig,
+            0 if to == self.pos + self.dir.unit() => TurtleCommand::Dig,
             1 => TurtleCommand::DigDown,
             -1 => TurtleCommand::DigUp,
             _ => None?
         })
     }
 
+    /// Block passed through on the way to a position a stair away
+    pub fn corner(self, to: Position) -> Option<Vec3> {
+        let ahead = self.pos + self.dir.unit();
+        (to.dir == self.dir && (to.pos - ahead).abs() == Vec3::y()).then_some(ahead)
+    }
+
     pub fn manhattan(self, other: Self) -> i32 {
         self.pos.sub(other.pos).abs().sum()
     }
 }
 
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", Coords(self.pos), self.dir)
+    }
+}
+
+/// Single line display for a Vec3, `(x,y,z)`
+///
+/// nalgebra's Display draws a multi-line matrix
+pub struct Coords(pub Vec3);
+
+impl fmt::Display for Coords {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({},{},{})", self.0.x, self.0.y, self.0.z)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Hash, PartialEq, Eq, Copy, Debug)]
 pub enum Direction {
     North,
@@ -283,7 +750,20 @@ impl Direction {
     }
 }
 
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Direction::North => "N",
+            Direction::South => "S",
+            Direction::East => "E",
+            Direction::West => "W",
+        })
+    }
+}
+
 /// closest valid state to the given point from where you are
+///
+/// Faces along whichever horizontal axis is further to go, z when they are even
 pub fn nearest(from: Vec3, to: Vec3) -> Position {
     let diff = to.xz()-from.xz();
     
@@ -297,7 +777,7 @@ pub fn nearest(from: Vec3, to: Vec3) -> Position {
         if diff.y > 0 {
             Direction::South
         } else {
-            Direction::South
+            Direction::North
         }
     };
     Position {
@@ -316,7 +796,7 @@ mod tests {
 
     fn single_point(point: Vec3) {
         let mut world = World::new();
-        world.set(Block { name: "a".to_string(), pos: point});
+        world.set(Block::new("a", point));
 
         assert_eq!("a", world.get(point).unwrap().name);
     }
@@ -325,7 +805,7 @@ mod tests {
         let mut world = World::new();
         for i in 0..size.product() {
             let block = fill(size, i) + point;
-            world.set(Block { name: i.to_string(), pos: block});
+            world.set(Block::new(i.to_string(), block));
         }
 
         for i in 0..size.product() {
@@ -334,6 +814,160 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bounds() {
+        let mut world = World::new();
+        assert!(world.bounds(None).is_none());
+        world.set(Block::new("minecraft:air", Vec3::new(100, 100, 100)));
+        assert!(world.bounds(None).is_none());
+
+        let facing = BTreeMap::from([("facing".to_string(), "north".to_string())]);
+        world.set(Block::new("minecraft:stone", Vec3::new(-3, 5, 9)));
+        world.set(Block { name: "minecraft:chest".into(), pos: Vec3::new(12, -1, 0), state: Some(facing.clone()) });
+        assert_eq!(world.bounds(None), Some((Vec3::new(-3, -1, 0), Vec3::new(12, 5, 9))));
+        let within = AABB::from_corners([-10, -10, -10], [0, 10, 10]);
+        assert_eq!(world.bounds(Some(within)), Some((Vec3::new(-3, 5, 9), Vec3::new(-3, 5, 9))));
+        assert_eq!(World::volume(Vec3::new(-3, -1, 0), Vec3::new(12, 5, 9)), 16 * 7 * 10);
+
+        let blocks: Vec<_> = world.blocks().collect();
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks.iter().any(|b| b.name == "minecraft:chest" && b.state.as_ref() == Some(&facing)));
+    }
+
+    #[test]
+    fn volume() {
+        // the far corners of the world would overflow an i32 several times over
+        let (lower, upper) = (Vec3::repeat(i32::MIN), Vec3::repeat(i32::MAX));
+        assert_eq!(World::volume(lower, upper), World::volume(upper, lower));
+        assert!(World::volume(lower, upper) > i64::from(i32::MAX));
+        assert!(World::new().to_litematic(lower, upper).is_err());
+    }
+
+    #[test]
+    fn blocks_in() {
+        let mut world = World::new();
+        for (name, pos) in [
+            ("minecraft:iron_ore", Vec3::new(-1, -1, -1)),
+            ("minecraft:stone", Vec3::new(0, 0, 0)),
+            ("minecraft:deepslate_iron_ore", Vec3::new(9, 3, 17)),
+            ("minecraft:coal_ore", Vec3::new(40, 3, 17)),
+        ] {
+            world.set(Block::new(name, pos));
+        }
+
+        let found = |a: Vec3, b: Vec3| {
+            let mut names: Vec<_> = world.blocks_in(AABB::from_corners(a.into(), b.into())).into_iter().map(|b| b.name).collect();
+            names.sort();
+            names
+        };
+        // corners in either order, across chunk boundaries
+        assert_eq!(found(Vec3::new(9, 3, 17), Vec3::new(-1, -1, -1)), ["minecraft:deepslate_iron_ore", "minecraft:iron_ore", "minecraft:stone"]);
+        assert_eq!(found(Vec3::new(-1, -1, -1), Vec3::new(-1, -1, -1)), ["minecraft:iron_ore"]);
+        // a box over far more chunks than are known
+        assert_eq!(found(Vec3::new(-1000, -1000, -1000), Vec3::new(1000, 1000, 1000)).len(), 4);
+        assert!(found(Vec3::new(10, 0, 0), Vec3::new(39, 10, 30)).is_empty());
+    }
+
+    #[test]
+    fn display() {
+        let pos = Position::new(Vec3::new(1, -2, 3), Direction::North);
+        assert_eq!("(1,-2,3)N", pos.to_string());
+        assert_eq!("(4,5,6)", Coords(Vec3::new(4,5,6)).to_string());
+    }
+
+    #[test]
+    fn dig_facing() {
+        let pos = Position::new(Vec3::zeros(), Direction::North);
+        let ahead = Direction::North.unit();
+        assert!(matches!(pos.dig(ahead), Some(TurtleCommand::Dig)));
+        assert!(matches!(pos.dig(Vec3::y()), Some(TurtleCommand::DigUp)));
+        assert!(matches!(pos.place(-Vec3::y()), Some(TurtleCommand::PlaceDown)));
+        assert!(pos.dig(-ahead).is_none());
+        assert!(pos.dig(Direction::East.unit()).is_none());
+        assert!(pos.place(Direction::West.unit()).is_none());
+    }
+
+    #[test]
+    fn stairs() {
+        let pos = Position::new(Vec3::zeros(), Direction::North);
+        let ahead = Direction::North.unit();
+        let down = Position::new(ahead - Vec3::y(), Direction::North);
+        assert!(matches!(pos.difference(down), Some(TurtleCommand::ForwardDown)));
+        assert!(matches!(pos.difference(Position::new(ahead + Vec3::y(), Direction::North)), Some(TurtleCommand::ForwardUp)));
+        assert_eq!(pos.corner(down), Some(ahead));
+        assert_eq!(pos.corner(Position::new(ahead, Direction::North)), None);
+        assert!(pos.difference(Position::new(ahead - Vec3::y(), Direction::East)).is_none());
+    }
+
+    #[test]
+    fn nearest_side() {
+        let to = Vec3::new(10, 64, 10);
+        let facing = |dx, dz| nearest(to - Vec3::new(dx, 0, dz), to);
+
+        assert_eq!(facing(5, 0), Position::new(Vec3::new(9, 64, 10), Direction::East));
+        assert_eq!(facing(-5, 0), Position::new(Vec3::new(11, 64, 10), Direction::West));
+        assert_eq!(facing(0, 5), Position::new(Vec3::new(10, 64, 9), Direction::South));
+        assert_eq!(facing(0, -5), Position::new(Vec3::new(10, 64, 11), Direction::North));
+
+        // the longer way decides
+        assert_eq!(facing(6, -5).dir, Direction::East);
+        assert_eq!(facing(-5, 6).dir, Direction::South);
+        assert_eq!(facing(-6, -5).dir, Direction::West);
+        assert_eq!(facing(5, -6).dir, Direction::North);
+
+        // even diagonals go along z
+        assert_eq!(facing(3, 3).dir, Direction::South);
+        assert_eq!(facing(3, -3).dir, Direction::North);
+        assert_eq!(facing(-3, 3).dir, Direction::South);
+        assert_eq!(facing(-3, -3).dir, Direction::North);
+
+        for (dx, dz) in [(5, 0), (-5, 0), (0, 5), (0, -5), (3, -3)] {
+            let near = facing(dx, dz);
+            assert_eq!(near.pos + near.dir.unit(), to);
+        }
+    }
+
+    #[tokio::test]
+    async fn occupancy() {
+        let world = SharedWorld::new();
+        world.set(Block::new("minecraft:stone", Vec3::x())).await;
+        world.set(Block::new("minecraft:air", Vec3::y())).await;
+
+        assert_eq!(world.occupancy(Vec3::x()).await, Occupancy::Occupied);
+        assert_eq!(world.occupancy(Vec3::y()).await, Occupancy::Clear);
+        assert_eq!(world.occupancy(Vec3::z()).await, Occupancy::Unknown);
+        assert!(!world.occupied(Vec3::z()).await);
+    }
+
+    #[tokio::test]
+    async fn fluids() {
+        let world = SharedWorld::new();
+        let water = |pos| Block::new("minecraft:water", pos);
+        world.set(water(Vec3::zeros())).await;
+        world.set(water(Vec3::y())).await;
+        world.set(water(Vec3::x())).await;
+        world.set(Block::new("minecraft:stone", Vec3::z())).await;
+
+        assert_eq!(world.fluid_source(Vec3::zeros()).await, Some(false)); // falling
+        assert_eq!(world.fluid_source(Vec3::y()).await, Some(false)); // sides unknown
+        assert_eq!(world.fluid_source(Vec3::z()).await, None);
+
+        let stone = |pos| Block::new("minecraft:stone", pos);
+        let pool = Vec3::new(10, 0, 0);
+        world.set(water(pool)).await;
+        for d in [Vec3::y(), Vec3::x(), -Vec3::x(), Vec3::z(), -Vec3::z()] {
+            world.set(stone(pool + d)).await;
+        }
+        assert_eq!(world.fluid_source(pool).await, Some(true)); // unfed
+
+        // inspected state wins over the neighbors
+        let level = |n: &str| Some(BTreeMap::from([("level".to_string(), n.to_string())]));
+        world.set(Block { state: level("0"), ..water(Vec3::zeros()) }).await;
+        assert_eq!(world.fluid_source(Vec3::zeros()).await, Some(true));
+        world.set(Block { state: level("3"), ..water(pool) }).await;
+        assert_eq!(world.fluid_source(pool).await, Some(false));
+    }
+
     #[test]
     fn origin() {
         single_point(Vec3::zeros())
@@ -357,6 +991,199 @@ mod tests {
         many(Vec3::new(-1212,100,-1292), Vec3::new(100, 100, 100))
     }
 
+    #[test]
+    fn negative_boundaries() {
+        let size = CHUNK_SIZE as i32;
+        let edges = [-2 * size, -size - 1, -size, -1, 0, size - 1, size];
+        let mut world = World::new();
+        let mut blocks = Vec::new();
+        for x in edges {
+            for y in edges {
+                for z in edges {
+                    let pos = Vec3::new(x, y, z);
+                    world.set(Block::new(format!("{pos:?}"), pos));
+                    blocks.push(pos);
+                }
+            }
+        }
+
+        for pos in blocks {
+            let chunk = world.index[&pos.map(|n| n.div_floor(size))];
+            let block = world.data[chunk].get(pos, CHUNK_SIZE).unwrap();
+            assert_eq!(format!("{pos:?}"), block.name);
+        }
+    }
+
+    #[test]
+    fn cached_chunk() {
+        let size = CHUNK_SIZE as i32;
+        let edges = [-1, 0, 1, size - 1, size, size + 1];
+        let mut points = Vec::new();
+        for x in edges {
+            for y in edges {
+                for z in edges {
+                    points.push(Vec3::new(x, y, z));
+                }
+            }
+        }
+
+        let mut world = World::new();
+        for pos in &points {
+            world.set(Block::new(format!("{pos:?}"), *pos));
+        }
+
+        for primed in &points {
+            // leaves `last` pointing at the chunk of `primed`
+            world.set(Block::new(format!("{primed:?}"), *primed));
+            for pos in &points {
+                assert_eq!(format!("{pos:?}"), world.get(*pos).unwrap().name, "primed with {primed:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rechunk() {
+        // a chunk saved with chunks twice as large
+        let size = CHUNK_SIZE * 2;
+        let mut chunk = Chunk { pos: Vec3::new(-1, 0, 1), data: vec![None; size * size * size] };
+        chunk.data[(size + 2) * size + 3] = Some("a".to_string());
+        chunk.data[size * size * size - 1] = Some("b".to_string());
+        let world = World {
+            chunk_size: size,
+            index: HashMap::from([(chunk.pos, 0)]),
+            data: vec![chunk],
+            last: None,
+            states: HashMap::new(),
+            known: 0,
+        };
+        let bytes = world.to_bytes().unwrap();
+
+        let world = World::from_bytes(&bytes).unwrap();
+        let size = size as i32;
+        assert_eq!(world.chunk_size, CHUNK_SIZE);
+        assert_eq!("a", world.get(Vec3::new(1 - size, 2, size + 3)).unwrap().name);
+        assert_eq!("b", world.get(Vec3::new(-1, size - 1, 2 * size - 1)).unwrap().name);
+        assert!(world.get(Vec3::new(-size, 0, size)).is_none());
+        assert_eq!(world.known(), 2);
+    }
+
+    #[test]
+    fn block_states() {
+        let pos = Vec3::new(3, 64, -2);
+        let stairs = |state| Block { name: "minecraft:oak_stairs".to_string(), pos, state };
+        let facing = BTreeMap::from([("facing".to_string(), "north".to_string())]);
+
+        // saves from before states were kept have the same layout, minus them
+        let mut old = World::new();
+        old.set(stairs(None));
+        let bytes = bincode::serialize(&(old.chunk_size, &old.index, &old.data, old.last)).unwrap();
+        let mut world = World::from_bytes(&bytes).unwrap();
+        assert_eq!(world.get(pos).unwrap().state, None);
+        assert_eq!(world.known(), 1);
+
+        world.set(stairs(Some(facing.clone())));
+        // turtles passing by only see the name
+        world.set(stairs(None));
+        assert_eq!(world.get(pos).unwrap().state.as_ref(), Some(&facing));
+
+        // as do saves from before the layout was recorded
+        let untagged = World::from_bytes(&bincode::serialize(&world).unwrap()).unwrap();
+        assert_eq!(untagged.get(pos).unwrap().state.as_ref(), Some(&facing));
+        let world = World::from_bytes(&world.to_bytes().unwrap()).unwrap();
+        assert_eq!(world.get(pos).unwrap().state.as_ref(), Some(&facing));
+
+        let mut world = world;
+        world.set(Block::new("minecraft:air", pos));
+        world.set(stairs(None));
+        assert_eq!(world.get(pos).unwrap().state, None);
+    }
+
+    #[test]
+    fn air_saved() {
+        // a dug out chunk is only forgotten when compacted, never by a save
+        let mut world = World::new();
+        world.set(Block::new("minecraft:air", Vec3::new(0, 0, 0)));
+        let world = World::from_bytes(&world.to_bytes().unwrap()).unwrap();
+        assert_eq!("minecraft:air", world.get(Vec3::new(0, 0, 0)).unwrap().name);
+        assert_eq!(world.known(), 1);
+    }
+
+    #[test]
+    fn save_version() {
+        let mut world = World::new();
+        world.set(Block::new("minecraft:stone", Vec3::new(1, 2, 3)));
+        let bytes = world.to_bytes().unwrap();
+        assert!(bytes.starts_with(&SAVE_MAGIC));
+        assert_eq!(World::from_bytes(&bytes).unwrap().known(), 1);
+
+        // a layout from a newer server isn't guessed at
+        let newer = bincode::serialize(&(SAVE_MAGIC, SAVE_VERSION + 1, &world)).unwrap();
+        assert!(World::from_bytes(&newer).unwrap_err().to_string().contains("layout"));
+    }
+
+    #[test]
+    fn compact() {
+        let mut world = World::new();
+        let size = CHUNK_SIZE as i32;
+        world.set(Block::new("minecraft:air", Vec3::new(-1, 0, 0)));
+        world.set(Block::new("a", Vec3::new(size, 0, 0)));
+        world.set(Block::new("minecraft:air", Vec3::new(size + 1, 0, 0)));
+        world.set(Block::new("minecraft:air", Vec3::new(0, size, 0)));
+        world.get(Vec3::new(0, size, 0)).unwrap(); // cache a chunk that will be dropped
+        let waterlogged = BTreeMap::from([("waterlogged".to_string(), "false".to_string())]);
+        world.set(Block { state: Some(waterlogged), ..Block::new("minecraft:air", Vec3::new(0, size, 0)) });
+        // the same block again is nothing new
+        world.set(Block::new("minecraft:air", Vec3::new(-1, 0, 0)));
+        assert_eq!(world.known(), 4);
+
+        assert_eq!(world.compact(), 2);
+        assert_eq!(world.known(), 2);
+        assert_eq!(world.data.len(), 1);
+        assert!(world.get(Vec3::new(-1, 0, 0)).is_none());
+        assert!(world.get(Vec3::new(0, size, 0)).is_none());
+        assert!(world.states.is_empty());
+        assert_eq!("a", world.get(Vec3::new(size, 0, 0)).unwrap().name);
+        assert_eq!("minecraft:air", world.get(Vec3::new(size + 1, 0, 0)).unwrap().name);
+
+        world.set(Block::new("b", Vec3::new(0, size, 0)));
+        assert_eq!("b", world.get(Vec3::new(0, size, 0)).unwrap().name);
+        assert_eq!(world.known(), 3);
+        assert_eq!("a", world.get(Vec3::new(size, 0, 0)).unwrap().name);
+    }
+
+    /// A 50 block cube of distinct blocks, in chunks `chunk_size` on a side
+    fn cube(chunk_size: usize) -> World {
+        let point = Vec3::new(1212,100,1292);
+        let size = Vec3::new(50,50,50);
+        let mut world = World::sized(chunk_size);
+        for i in 0..size.product() {
+            let block = fill(size, i) + point;
+            world.set(Block::new(i.to_string(), block));
+        }
+        world
+    }
+
+    fn save_size(b: &mut Bencher, chunk_size: usize) {
+        let world = cube(chunk_size);
+        b.bytes = bincode::serialize(&world).unwrap().len() as u64;
+        b.iter(|| bincode::serialize(&world).unwrap());
+    }
+
+    #[bench]
+    fn save_size_4(b: &mut Bencher) {
+        save_size(b, 4)
+    }
+
+    #[bench]
+    fn save_size_8(b: &mut Bencher) {
+        save_size(b, 8)
+    }
+
+    #[bench]
+    fn save_size_16(b: &mut Bencher) {
+        save_size(b, 16)
+    }
+
     #[bench]
     fn positive_several(b: &mut Bencher) {
         b.iter(||many(Vec3::new(1212,100,1292), Vec3::new(100, 1, 30)));
@@ -367,15 +1194,10 @@ mod tests {
         b.iter(||many(Vec3::new(1212,100,1292), Vec3::new(50, 50, 50)));
     }
 
-    #[bench]
-    fn positive_many_get(b: &mut Bencher) {
+    fn many_get(b: &mut Bencher, chunk_size: usize) {
         let point = Vec3::new(1212,100,1292);
         let size = Vec3::new(50,50,50);
-        let mut world = World::new();
-        for i in 0..size.product() {
-            let block = fill(size, i) + point;
-            world.set(Block { name: i.to_string(), pos: block});
-        }
+        let world = cube(chunk_size);
 
         b.iter(|| {
             for i in 0..size.product() {
@@ -385,6 +1207,21 @@ mod tests {
         });
     }
 
+    #[bench]
+    fn positive_many_get_4(b: &mut Bencher) {
+        many_get(b, 4)
+    }
+
+    #[bench]
+    fn positive_many_get_8(b: &mut Bencher) {
+        many_get(b, 8)
+    }
+
+    #[bench]
+    fn positive_many_get_16(b: &mut Bencher) {
+        many_get(b, 16)
+    }
+
     #[bench]
     fn positive_many_set(b: &mut Bencher) {
         let point = Vec3::new(1212,100,1292);
@@ -393,7 +1230,7 @@ mod tests {
             for i in 0..size.product() {
                 let mut world = World::new();
                 let block = fill(size, i) + point;
-                world.set(Block { name: i.to_string(), pos: block});
+                world.set(Block::new(i.to_string(), block));
             }
         });
     }
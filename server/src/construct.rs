@@ -1,41 +1,175 @@
-use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering, AtomicI32}, Arc}, borrow::Cow};
+use std::{sync::{atomic::{AtomicBool, AtomicUsize, Ordering, AtomicI32}, Arc}, borrow::Cow, collections::{BTreeMap, HashSet}};
 
 use anyhow::{Context, Ok};
 use serde::{Serialize, Deserialize};
 use swarmbot_interfaces::types::BlockState;
 use tokio::task::AbortHandle;
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 use typetag::serde;
 
-use crate::{blocks::{Vec3, Position, World, Block, SharedWorld, Direction}, mine::{ChunkedTask, fill}, turtle::{TurtleCommander, TurtleCommandResponse, TurtleCommand}, tasks::{Task, TaskState}, vendored::schematic::Schematic};
+use crate::{blocks::{Vec3, Position, World, Block, SharedWorld, Direction, Coords}, mine::{ChunkedTask, fill}, turtle::{TurtleCommander, TurtleCommandResponse, TurtleCommand, DryRun, FailureReason, InventorySlot}, tasks::{Task, TaskState, Role, Timing, Progress, Needs}, vendored::schematic::Schematic};
 
-fn schematic2world(region: &Schematic) -> anyhow::Result<World> {
+/// Fuel to have on hand before starting a layer
+const LAYER_FUEL: usize = 5000;
+
+/// Legacy block ids left out of builds on purpose
+const SKIPPED: [u32; 5] = [
+    0, // Air
+    20, // Glass
+    95, // Stained glass
+    102, // Glass pane
+    160, // Stained glass pane
+];
+
+/// Blocks that can be placed, by legacy block id and metadata
+///
+/// Coloured and wooden blocks are named in `block_name` instead
+const PALETTE: [(u32, u8, &str); 41] = [
+    (1, 0, "minecraft:stone"),
+    (1, 1, "minecraft:granite"),
+    (1, 2, "minecraft:polished_granite"),
+    (1, 3, "minecraft:diorite"),
+    (1, 4, "minecraft:polished_diorite"),
+    (1, 5, "minecraft:andesite"),
+    (1, 6, "minecraft:polished_andesite"),
+    (2, 0, "minecraft:grass_block"),
+    (3, 0, "minecraft:dirt"),
+    (3, 1, "minecraft:coarse_dirt"),
+    (3, 2, "minecraft:podzol"),
+    (4, 0, "minecraft:cobblestone"),
+    (12, 0, "minecraft:sand"),
+    (12, 1, "minecraft:red_sand"),
+    (13, 0, "minecraft:gravel"),
+    (24, 0, "minecraft:sandstone"),
+    (24, 1, "minecraft:chiseled_sandstone"),
+    (24, 2, "minecraft:cut_sandstone"),
+    (43, 0, "minecraft:smooth_stone"),
+    (45, 0, "minecraft:bricks"),
+    (48, 0, "minecraft:mossy_cobblestone"),
+    (49, 0, "minecraft:obsidian"),
+    (80, 0, "minecraft:snow_block"),
+    (82, 0, "minecraft:clay"),
+    (87, 0, "minecraft:netherrack"),
+    (98, 0, "minecraft:stone_bricks"),
+    (98, 1, "minecraft:mossy_stone_bricks"),
+    (98, 2, "minecraft:cracked_stone_bricks"),
+    (98, 3, "minecraft:chiseled_stone_bricks"),
+    (112, 0, "minecraft:nether_bricks"),
+    (121, 0, "minecraft:end_stone"),
+    (155, 0, "minecraft:quartz_block"),
+    (155, 1, "minecraft:chiseled_quartz_block"),
+    (155, 2, "minecraft:quartz_pillar"),
+    (155, 3, "minecraft:quartz_pillar"),
+    (155, 4, "minecraft:quartz_pillar"),
+    (168, 0, "minecraft:prismarine"),
+    (168, 1, "minecraft:prismarine_bricks"),
+    (168, 2, "minecraft:dark_prismarine"),
+    (172, 0, "minecraft:terracotta"),
+    (174, 0, "minecraft:packed_ice"),
+];
+
+/// Wool and stained clay colours, by metadata
+const COLOURS: [&str; 16] = [
+    "white", "orange", "magenta", "light_blue", "yellow", "lime", "pink", "gray",
+    "light_gray", "cyan", "purple", "blue", "brown", "green", "red", "black",
+];
+
+/// Planks by metadata, with logs split between ids 17 and 162
+const WOODS: [&str; 6] = ["oak", "spruce", "birch", "jungle", "acacia", "dark_oak"];
+
+/// Name of the block to place, None if nothing is placed, or the id and metadata if it can't be mapped
+fn block_name(block: BlockState) -> Result<Option<Cow<'static, str>>, (u32, u8)> {
+    let (id, meta) = (block.id(), (block.0 & 0xF) as u8);
+    if SKIPPED.contains(&id) {
+        return Result::Ok(None);
+    }
+    let name = match (id, meta) {
+        (5, 0..=5) => format!("minecraft:{}_planks", WOODS[meta as usize]).into(),
+        // the upper bits of a log are only which way it lies
+        (17, _) => format!("minecraft:{}_log", WOODS[(meta & 0b11) as usize]).into(),
+        (162, _) if meta & 0b10 == 0 => format!("minecraft:{}_log", WOODS[4 + (meta & 0b1) as usize]).into(),
+        (35, _) => format!("minecraft:{}_wool", COLOURS[meta as usize]).into(),
+        (159, _) => format!("minecraft:{}_terracotta", COLOURS[meta as usize]).into(),
+        _ => PALETTE.iter()
+            .find(|(i, m, _)| (*i, *m) == (id, meta))
+            .map(|(.., name)| Cow::Borrowed(*name))
+            .ok_or((id, meta))?,
+    };
+    Result::Ok(Some(name))
+}
+
+/// The schematic as a world, and how many blocks of each unmapped id and metadata were left out
+fn schematic2world(region: &Schematic) -> anyhow::Result<(World, BTreeMap<(u32, u8), usize>)> {
     let mut world = World::new();
+    let mut unmapped = BTreeMap::new();
 
     let min = region.origin().context("bad schematic")?;
 
     for (position, block) in region.blocks() {
-
-        let name = match block {
-                BlockState::AIR => None,
-                BlockState(20) => None, // Glass
-                BlockState(102) => None, // Glass pane
-                BlockState(95) => None, // Stained glass
-                BlockState(160) => None, // Stained glass pane
-                // who cares
-                _ => Some("terrestria:hemlock_planks")
-            }.map(|s| s.to_string());
+        let name = match block_name(block) {
+            Result::Ok(name) => name,
+            Err(state) => {
+                *unmapped.entry(state).or_default() += 1;
+                continue;
+            },
+        };
 
         if let Some(name) = name {
-            let block = Block {
-                name,
-                pos: position - min,
-            };
-            world.set(block);
+            world.set(Block::new(name, position - min));
         }
     }
 
-    Ok(world)
+    Ok((world, unmapped))
+}
+
+/// Blocks placed from an item of another name, beyond wall-mounted variants
+const ITEMS: [(&str, &str); 14] = [
+    ("minecraft:redstone_wire", "minecraft:redstone"),
+    ("minecraft:tripwire", "minecraft:string"),
+    ("minecraft:water", "minecraft:water_bucket"),
+    ("minecraft:lava", "minecraft:lava_bucket"),
+    ("minecraft:powder_snow", "minecraft:powder_snow_bucket"),
+    ("minecraft:wheat", "minecraft:wheat_seeds"),
+    ("minecraft:carrots", "minecraft:carrot"),
+    ("minecraft:potatoes", "minecraft:potato"),
+    ("minecraft:beetroots", "minecraft:beetroot_seeds"),
+    ("minecraft:cocoa", "minecraft:cocoa_beans"),
+    ("minecraft:pumpkin_stem", "minecraft:pumpkin_seeds"),
+    ("minecraft:melon_stem", "minecraft:melon_seeds"),
+    ("minecraft:sweet_berry_bush", "minecraft:sweet_berries"),
+    ("minecraft:bamboo_sapling", "minecraft:bamboo"),
+];
+
+/// Items burnt for fuel, which a builder holds on to
+const FUELS: [&str; 3] = ["coal", "lava_bucket", "blaze_rod"];
+
+/// Name of the item that places the block
+fn item_for(block: &str) -> Cow<'_, str> {
+    if let Some((_, item)) = ITEMS.iter().find(|(b, _)| *b == block) {
+        return Cow::Borrowed(item);
+    }
+    // torches, signs, banners, heads and coral fans on a wall
+    match block.contains("wall_") {
+        true => Cow::Owned(block.replacen("wall_", "", 1)),
+        false => Cow::Borrowed(block),
+    }
+}
+
+/// First slot holding the block, counting from 1
+fn slot_with(inventory: &[Option<InventorySlot>], name: &str) -> Option<u32> {
+    inventory.iter()
+        .position(|s| s.as_ref().is_some_and(|s| s.name == name && s.count > 0))
+        .map(|i| i as u32 + 1)
+}
+
+/// Places to stand to place a block, from the sides then above and below
+fn faces(at: Vec3) -> impl Iterator<Item = Position> {
+    [Direction::North, Direction::East, Direction::South, Direction::West].into_iter()
+        .map(move |dir| Position::new(at - dir.unit(), dir))
+        .chain([
+            Position::new(at - Vec3::y(), Direction::North),
+            Position::new(at + Vec3::y(), Direction::North),
+        ])
 }
 
 #[derive(Serialize, Deserialize,Clone)]
@@ -44,12 +178,27 @@ pub struct BuildSimple {
     size: Vec3,
     #[serde(skip)]
     region: Option<SharedWorld>,
-    /// Input chest with the block to use, assumed infinite
+    /// Input chest with the blocks to use, assumed infinite
     input: Position,
     #[serde(skip_deserializing)]
     miners: Arc<AtomicUsize>,
     progress: Arc<AtomicI32>,
     height: i32,
+    /// Blocks that couldn't be placed from any side
+    #[serde(default)]
+    unplaceable: Arc<std::sync::Mutex<Vec<Vec3>>>,
+    #[serde(default)]
+    timing: Arc<std::sync::Mutex<Timing>>,
+    /// Only simulate the work, logging a report once complete
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(skip)]
+    report: Arc<DryRun>,
+    /// Blocks of each legacy id and metadata that the palette has no name for, so are skipped
+    ///
+    /// Renamed from `unmapped`, which was by id alone and is ignored in older saves
+    #[serde(default)]
+    unmapped_states: BTreeMap<(u32, u8), usize>,
 }
 
 impl BuildSimple {
@@ -59,40 +208,144 @@ impl BuildSimple {
             schematic.height() as i32,
             schematic.length() as i32,
         );
+        let (region, unmapped) = schematic2world(schematic).unwrap();
+        for ((id, meta), count) in &unmapped {
+            warn!("{count} blocks of id {id}:{meta} cannot be placed");
+        }
+        Self {
+            unmapped_states: unmapped,
+            ..Self::from_region(position, size, region, input)
+        }
+    }
+
+    /// Build a region already in world coordinates relative to its corner
+    pub(crate) fn from_region(position: Vec3, size: Vec3, region: World, input: Position) -> Self {
         Self {
             pos: position,
             size,
-            region: Some(SharedWorld::from_world(schematic2world(schematic).unwrap())),
+            region: Some(SharedWorld::from_world(region)),
             input,
             miners: Default::default(),
             progress: Default::default(),
             height: size.y,
+            unplaceable: Default::default(),
+            timing: Default::default(),
+            dry_run: false,
+            report: Default::default(),
+            unmapped_states: Default::default(),
         }
     }
 
-    async fn place_block(&self, turtle: TurtleCommander, at: Vec3) -> Option<()> {
+    /// Blocks of the schematic that will be left out
+    pub fn omitted(&self) -> Vec<String> {
+        self.unmapped_states.iter()
+            .map(|((id, meta), count)| format!("{count} blocks of id {id}:{meta} cannot be placed"))
+            .collect()
+    }
+
+    /// Simulate against a copy of the world instead of running the turtle
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Place a block, returning false if it couldn't be placed from any side or there are none to place
+    async fn place_block(&self, turtle: TurtleCommander, at: Vec3, name: &str, needed: &HashSet<String>) -> Option<bool> {
+        let Some(mut slot) = self.supply(&turtle, name, turtle.inventory().await, needed).await else {
+            error!("no {name} to place at {}", Coords(at));
+            return Some(false);
+        };
         let mut near = turtle.goto_adjacent(at).await?;
-        while let TurtleCommandResponse::Failure = turtle.execute(near.place(at)?).await.ret {
+        let mut faces = faces(at);
+        loop {
+            let reason = match turtle.execute(near.place(at)?.from_slot(slot)?).await.ret {
+                TurtleCommandResponse::Failure(reason) => reason,
+                _ => return Some(true),
+            };
+            // every response reports the blocks around the turtle
             if turtle.world().occupied(at).await {
-                trace!("{at} already filled");
-                return None;
+                trace!("{} already filled", Coords(at));
+                return Some(true);
             };
-            trace!("failed, looking for blocks");
-            if let Some(slot) = turtle.inventory().await.iter().enumerate()
-                .filter(|n| n.1.clone().is_some_and(|s| s.count > 0))
-                    .map(|n| n.0).next() {
-                turtle.execute(TurtleCommand::Select(slot as u32 + 1)).await;
-            } else {
-                trace!("docking");
-                turtle.goto(self.input).await;
-                for _ in 1..=16 {
-                    turtle.execute(TurtleCommand::SuckFront(64)).await;
-                }
-                near = turtle.goto_adjacent(at).await?;
+
+            if !matches!(reason, FailureReason::NoItems) {
+                // probably nothing to place against from here
+                trace!("placing {} from {near} failed: {reason:?}", Coords(at));
+                near = loop {
+                    let Some(face) = faces.next() else {
+                        return Some(false);
+                    };
+                    if face.pos == near.pos || turtle.world().occupied(face.pos).await {
+                        continue;
+                    }
+                    if turtle.goto(face).await.is_some() {
+                        break face;
+                    }
+                };
+                continue;
             }
+
+            // the counts were off, so look again
+            trace!("out of {name}");
+            let Some(found) = self.supply(&turtle, name, turtle.refresh_inventory().await, needed).await else {
+                error!("no {name} to place at {}", Coords(at));
+                return Some(false);
+            };
+            slot = found;
+            near = turtle.goto_adjacent(at).await?;
         }
+    }
 
-        Some(())
+    /// Slot with the item to place the block, restocking from the input chest if there is none
+    ///
+    /// Fuel and anything else in `needed` stays on board. The chest hands out its stacks in order,
+    /// so the rest is held while looking, which reaches only as far in as there are free slots
+    async fn supply(&self, turtle: &TurtleCommander, name: &str, inventory: Vec<Option<InventorySlot>>, needed: &HashSet<String>) -> Option<u32> {
+        let item = item_for(name);
+        if let Some(slot) = slot_with(&inventory, &item) {
+            return Some(slot);
+        }
+        let keep = |name: &str| needed.contains(name) || FUELS.iter().any(|f| name.contains(f));
+
+        trace!("docking for {item}");
+        turtle.goto(self.input).await;
+        // make room, the chest takes back whatever isn't needed right now
+        for (i, slot) in inventory.into_iter().enumerate() {
+            if slot.is_some_and(|s| !keep(&s.name)) {
+                turtle.execute(TurtleCommand::Select(i as u32 + 1)).await;
+                turtle.execute(TurtleCommand::DropFront(64)).await;
+            }
+        }
+
+        let empty: Vec<u32> = turtle.inventory().await.iter().enumerate()
+            .filter(|(_, s)| s.is_none())
+            .map(|(i, _)| i as u32 + 1)
+            .collect();
+        let mut found = None;
+        let mut aside = Vec::new();
+        for slot in empty {
+            turtle.execute(TurtleCommand::Select(slot)).await;
+            if let TurtleCommandResponse::Failure(_) = turtle.execute(TurtleCommand::SuckFront(64)).await.ret {
+                break;
+            }
+            match turtle.execute(TurtleCommand::ItemInfo(slot)).await.ret {
+                TurtleCommandResponse::Item(held) if held.name == item => {
+                    found = Some(slot);
+                    break;
+                },
+                TurtleCommandResponse::Item(held) if keep(&held.name) => {},
+                _ => aside.push(slot),
+            }
+        }
+
+        for slot in aside {
+            turtle.execute(TurtleCommand::Select(slot)).await;
+            turtle.execute(TurtleCommand::DropFront(64)).await;
+        }
+        if found.is_none() {
+            warn!("no {item} within reach in the input chest at {}", self.input);
+        }
+        found
     }
 
     async fn build_layer(&self, turtle: TurtleCommander, layer: i32) -> Option<()> {
@@ -102,20 +355,32 @@ impl BuildSimple {
         let mut world = turtle.world().lock_mut().await;
         for point in (0..layer_size.product()).map(|n| fill(layer_size, n)) {
             if let None = world.get(point) {
-                world.set(Block { name: "minecraft:air".into(), pos: point })
+                world.set(Block::new("minecraft:air", point))
             }
         }
         drop(world);
 
+        // items for the layer, kept on board between restocks
+        let mut needed = HashSet::new();
+        for point in (0..layer_size.product()).map(|n| fill(layer_size, n) + Vec3::y() * layer) {
+            if let Some(block) = self.region.as_ref()?.get(point).await {
+                needed.insert(item_for(&block.name).into_owned());
+            }
+        }
+
+        let mut deferred = Vec::new();
         for point in (0..layer_size.product())
             .map(|n| fill(layer_size, n)) {
+            if turtle.cancelled() {
+                return None;
+            }
             let point = point + Vec3::y() * layer;
-            trace!("block {point}");
+            trace!("block {}", Coords(point));
 
-            if self.region.as_ref()?.get(point).await.is_none() {
-                trace!("empty: {:?}", self.region.as_ref()?.get(point).await);
+            let Some(block) = self.region.as_ref()?.get(point).await else {
+                trace!("empty: {}", Coords(point));
                 continue;
-            }
+            };
 
             let point = point + self.pos;
 
@@ -124,7 +389,20 @@ impl BuildSimple {
                 continue;
             }
 
-            self.place_block(turtle.clone(), point).await;
+            if let Some(false) = self.place_block(turtle.clone(), point, &block.name, &needed).await {
+                deferred.push((point, block.name));
+            }
+        }
+
+        // the rest of the layer may have given these something to attach to
+        for (point, name) in deferred {
+            if turtle.cancelled() {
+                return None;
+            }
+            if let Some(false) = self.place_block(turtle.clone(), point, &name, &needed).await {
+                error!("could not place {}", Coords(point));
+                self.unplaceable.lock().unwrap().push(point);
+            }
         }
         Some(())
     }
@@ -132,29 +410,56 @@ impl BuildSimple {
 
 #[serde]
 impl Task for BuildSimple {
+    fn role(&self) -> Role {
+        Role::Builder
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
     fn run(&mut self,turtle:TurtleCommander) -> AbortHandle {
         let owned = self.clone();
 
         tokio::spawn(async move {
-            if turtle.fuel() < 5000 {
+            let turtle = match owned.dry_run {
+                true => turtle.simulated(owned.report.clone()).await,
+                false => turtle,
+            };
+            if turtle.fuel() < LAYER_FUEL {
                 turtle.dock().await;
             }
+            owned.timing.lock().unwrap().start();
             let layer = owned.progress.fetch_add(1, Ordering::AcqRel);
-            if owned.height < layer {
+            if layer >= owned.height {
+                // poll only hands out layers below the height
                 error!("scheduled layer out of range");
+                owned.progress.fetch_sub(1, Ordering::AcqRel);
+                owned.miners.fetch_sub(1, Ordering::AcqRel);
                 return;
             }
             info!("layer {}", layer);
-            if let None = owned.build_layer(turtle, layer).await {
-                error!("building layer {} failed", layer);
+            if let None = owned.build_layer(turtle.clone(), layer).await {
+                match turtle.cancelled() {
+                    true => info!("building layer {} cancelled", layer),
+                    false => error!("building layer {} failed", layer),
+                }
                 owned.progress.fetch_sub(1, Ordering::AcqRel);
             } else {
                 trace!("building layer {} successful", layer);
+                owned.timing.lock().unwrap().step();
             }
             owned.miners.fetch_sub(1, Ordering::AcqRel);
         }).abort_handle()
     }
 
+    fn progress(&self) -> Option<Progress> {
+        // layers handed out, less the one being built
+        let done = self.progress.load(Ordering::SeqCst) - self.miners.load(Ordering::SeqCst) as i32;
+        let timing = *self.timing.lock().unwrap();
+        Some(Progress::new(done.max(0) as usize, Some(self.height as usize), timing).notes(self.omitted()))
+    }
+
     fn poll(&mut self) -> TaskState {
         if self.region.is_none() {
             error!("attempted to restart schematic printing, which is unimplemented");
@@ -163,7 +468,23 @@ impl Task for BuildSimple {
 
         let layer = self.progress.load(Ordering::SeqCst);
 
-        if layer > self.height {
+        if layer >= self.height {
+            // the last layer is still being built
+            if self.miners.load(Ordering::SeqCst) > 0 {
+                return TaskState::Waiting;
+            }
+
+            let holes: Vec<String> = self.unplaceable.lock().unwrap().iter()
+                .map(|p| Coords(*p).to_string()).collect();
+            if !holes.is_empty() {
+                error!("build at {} left {} holes: {}", Coords(self.pos), holes.len(), holes.join(" "));
+            }
+            for omitted in self.omitted() {
+                warn!("build at {}: {omitted}", Coords(self.pos));
+            }
+            if self.dry_run {
+                info!("dry run of build at {}: {}", Coords(self.pos), self.report);
+            }
             return TaskState::Complete;
         }
 
@@ -176,8 +497,39 @@ impl Task for BuildSimple {
         }).is_ok();
 
         if only {
-            return TaskState::Ready(Position::new(self.pos, Direction::North));
+            let needs = Needs { fuel: Some(LAYER_FUEL), role: None };
+            return TaskState::ReadyFor(Position::new(self.pos, Direction::North), needs);
         }
         TaskState::Waiting
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items() {
+        assert_eq!(item_for("minecraft:stone"), "minecraft:stone");
+        assert_eq!(item_for("minecraft:redstone_wire"), "minecraft:redstone");
+        assert_eq!(item_for("minecraft:wall_torch"), "minecraft:torch");
+        assert_eq!(item_for("minecraft:oak_wall_sign"), "minecraft:oak_sign");
+        assert_eq!(item_for("minecraft:cobblestone_wall"), "minecraft:cobblestone_wall");
+    }
+
+    #[test]
+    fn palette() {
+        let name = |id, meta| block_name(BlockState::from(id, meta)).map(|n| n.map(Cow::into_owned));
+        assert_eq!(name(0, 0), Result::Ok(None));
+        assert_eq!(name(35, 14), Result::Ok(Some("minecraft:red_wool".into())));
+        assert_eq!(name(159, 0), Result::Ok(Some("minecraft:white_terracotta".into())));
+        assert_eq!(name(5, 2), Result::Ok(Some("minecraft:birch_planks".into())));
+        // logs lying on their side are the same wood
+        assert_eq!(name(17, 9), Result::Ok(Some("minecraft:spruce_log".into())));
+        assert_eq!(name(162, 5), Result::Ok(Some("minecraft:dark_oak_log".into())));
+        assert_eq!(name(98, 3), Result::Ok(Some("minecraft:chiseled_stone_bricks".into())));
+        assert_eq!(name(1, 9), Err((1, 9)));
+        assert_eq!(name(5, 7), Err((5, 7)));
+        assert_eq!(name(162, 2), Err((162, 2)));
+    }
+}
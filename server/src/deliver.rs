@@ -0,0 +1,235 @@
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering}};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::AbortHandle;
+use tracing::{error, info, trace, warn};
+use typetag::serde;
+
+use crate::{blocks::Position, mine::MinerSlot, tasks::{Progress, Role, Task, TaskState, Timing}, turtle::{FailureReason, TurtleCommandResponse, TurtleCommander}};
+use crate::turtle::TurtleCommand::*;
+
+/// Move some of one kind of item from the chest in front of one position to the chest in front of another
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Deliver {
+    source: Position,
+    dest: Position,
+    /// Item name, such as minecraft:cobblestone
+    item: String,
+    count: u32,
+    #[serde(default)]
+    delivered: Arc<AtomicU32>,
+    #[serde(skip_deserializing)]
+    couriers: Arc<AtomicUsize>,
+    #[serde(default)]
+    done: Arc<AtomicBool>,
+    /// Given up on after a trip went wrong, as retrying won't empty a full chest
+    #[serde(default)]
+    failed: Arc<AtomicBool>,
+    #[serde(default)]
+    timing: Arc<std::sync::Mutex<Timing>>,
+}
+
+impl Deliver {
+    pub fn new(source: Position, dest: Position, item: String, count: u32) -> Self {
+        Self { source, dest, item, count, delivered: Default::default(), couriers: Default::default(), done: Default::default(), failed: Default::default(), timing: Default::default() }
+    }
+
+    /// Make trips until enough has been delivered, returning false if the source ran out first
+    async fn deliver(&self, turtle: TurtleCommander) -> Option<bool> {
+        let trip = (self.source.pos - self.dest.pos).abs().sum() as usize;
+        // docking empties the turtle, so a second full inventory in a row is stuck
+        let mut docked = false;
+
+        loop {
+            let wanted = self.count.saturating_sub(self.delivered.load(Ordering::SeqCst));
+            if wanted == 0 {
+                return Some(true);
+            }
+            if turtle.cancelled() {
+                return None;
+            }
+
+            if turtle.fuel() < 2 * trip + 1000 {
+                turtle.dock().await;
+            }
+
+            turtle.goto_nodig(self.source).await?;
+            let carried = match self.collect(&turtle, wanted).await {
+                Pickup::Carried(carried) => carried,
+                Pickup::RanOut => return Some(false),
+                Pickup::NoRoom if !docked => {
+                    warn!("no free slots to carry {}, docking first", self.item);
+                    turtle.dock().await;
+                    docked = true;
+                    continue;
+                },
+                Pickup::NoRoom => {
+                    error!("no free slots to carry {}, even after docking", self.item);
+                    return None;
+                },
+                Pickup::Refused => return None,
+                Pickup::Buried => {
+                    error!("{} in the chest ahead of {} is behind more stacks than there are free slots", self.item, self.source);
+                    return None;
+                },
+            };
+            docked = false;
+
+            turtle.goto_nodig(self.dest).await?;
+            for (slot, count) in carried {
+                turtle.execute(Select(slot)).await;
+                let dropped = turtle.execute(DropFront(64)).await.ret;
+                // a nearly full chest takes only part of the stack
+                let left = match turtle.execute(ItemInfo(slot)).await.ret {
+                    TurtleCommandResponse::Item(item) => item.count,
+                    _ => 0,
+                };
+                self.delivered.fetch_add(count.saturating_sub(left), Ordering::SeqCst);
+                if left > 0 {
+                    error!("chest ahead of {} took no more {}: {dropped:?}", self.dest, self.item);
+                    // the cache took the whole stack as dropped
+                    turtle.refresh_inventory().await;
+                    return None;
+                }
+            }
+            self.timing.lock().unwrap().step();
+            trace!("delivered {} of {} {}", self.delivered.load(Ordering::SeqCst), self.count, self.item);
+        }
+    }
+
+    /// Take up to `wanted` of the item from the chest ahead into empty slots
+    ///
+    /// Anything else picked up on the way is held until the search is over,
+    /// so that the next pull reaches further into the chest, then put back
+    async fn collect(&self, turtle: &TurtleCommander, wanted: u32) -> Pickup {
+        let mut carried = 0;
+        let mut slots = Vec::new();
+        let mut aside = Vec::new();
+        let mut ran_out = false;
+
+        let empty: Vec<u32> = turtle.inventory().await.iter().enumerate()
+            .filter(|(_, s)| s.is_none())
+            .map(|(i, _)| i as u32 + 1)
+            .collect();
+
+        if empty.is_empty() {
+            return Pickup::NoRoom;
+        }
+
+        for slot in empty {
+            if carried >= wanted {
+                break;
+            }
+            turtle.execute(Select(slot)).await;
+            // whole stacks, so other items don't take a slot for every few pulled
+            match turtle.execute(SuckFront(64)).await.ret {
+                TurtleCommandResponse::Failure(FailureReason::NoItems) => {
+                    ran_out = true;
+                    break;
+                },
+                TurtleCommandResponse::Failure(reason) => {
+                    warn!("could not take from the chest ahead of {}: {reason:?}", self.source);
+                    break;
+                },
+                _ => {},
+            }
+            match turtle.execute(ItemInfo(slot)).await.ret {
+                TurtleCommandResponse::Item(item) if item.name == self.item => {
+                    let taken = item.count.min(wanted - carried);
+                    if item.count > taken {
+                        turtle.execute(DropFront(item.count - taken)).await;
+                    }
+                    carried += taken;
+                    slots.push((slot, taken));
+                },
+                _ => aside.push(slot),
+            }
+        }
+
+        for slot in aside.iter().copied() {
+            turtle.execute(Select(slot)).await;
+            turtle.execute(DropFront(64)).await;
+        }
+
+        match (slots.is_empty(), ran_out) {
+            (false, _) => Pickup::Carried(slots),
+            // every stack in the chest has been looked at
+            (true, true) => Pickup::RanOut,
+            (true, false) if !aside.is_empty() => Pickup::Buried,
+            (true, false) => Pickup::Refused,
+        }
+    }
+}
+
+/// What a turtle came away from the source chest with
+#[derive(Debug, PartialEq, Eq)]
+enum Pickup {
+    /// Slots filled with the item, and how many each holds
+    Carried(Vec<(u32, u32)>),
+    /// The chest has none of the item left
+    RanOut,
+    /// Every free slot filled up with other items before any of it turned up
+    Buried,
+    /// No free slot to take anything into
+    NoRoom,
+    /// The chest couldn't be taken from
+    Refused,
+}
+
+#[serde]
+impl Task for Deliver {
+    fn role(&self) -> Role {
+        Role::Hauler
+    }
+
+    fn run(&mut self, turtle: TurtleCommander) -> AbortHandle {
+        self.couriers.fetch_add(1, Ordering::SeqCst);
+        let slot = MinerSlot(self.couriers.clone());
+
+        let owned = self.clone();
+
+        tokio::spawn(async move {
+            let _slot = slot;
+            owned.timing.lock().unwrap().start();
+            match owned.deliver(turtle.clone()).await {
+                Some(true) => {
+                    info!("delivered {} {} to {}", owned.count, owned.item, owned.dest);
+                    owned.done.store(true, Ordering::SeqCst);
+                },
+                Some(false) => {
+                    let delivered = owned.delivered.load(Ordering::SeqCst);
+                    warn!("{} ran out of {}, delivered {delivered} of {} ({} short)",
+                        owned.source, owned.item, owned.count, owned.count - delivered);
+                    owned.done.store(true, Ordering::SeqCst);
+                },
+                None if turtle.cancelled() => info!("delivering {} to {} cancelled", owned.item, owned.dest),
+                None => {
+                    error!("delivering {} from {} to {} failed", owned.item, owned.source, owned.dest);
+                    owned.failed.store(true, Ordering::SeqCst);
+                },
+            }
+        }).abort_handle()
+    }
+
+    fn progress(&self) -> Option<Progress> {
+        let delivered = self.delivered.load(Ordering::SeqCst).min(self.count);
+        let timing = *self.timing.lock().unwrap();
+        Some(Progress::new(delivered as usize, Some(self.count as usize), timing))
+    }
+
+    fn poll(&mut self) -> TaskState {
+        if self.done.load(Ordering::SeqCst) {
+            return TaskState::Complete;
+        }
+        if self.failed.load(Ordering::SeqCst) {
+            let delivered = self.delivered.load(Ordering::SeqCst).min(self.count);
+            warn!("gave up delivering {} to {}, delivered {delivered} of {} ({} short)",
+                self.item, self.dest, self.count, self.count - delivered);
+            return TaskState::Complete;
+        }
+        if self.couriers.load(Ordering::SeqCst) < 1 {
+            return TaskState::Ready(self.source);
+        }
+        TaskState::Waiting
+    }
+}
@@ -1,123 +1,618 @@
-use std::sync::Arc;
+use std::sync::{Arc, atomic::{AtomicUsize, AtomicBool, Ordering}};
 
-use tracing::{warn, info, trace};
-use tokio::sync::{Mutex, OwnedMutexGuard, Semaphore, OwnedSemaphorePermit};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::{warn, info, trace, error};
+use tokio::{sync::{Mutex, OwnedMutexGuard, Semaphore, OwnedSemaphorePermit}, task::AbortHandle};
+use typetag::serde;
 
-use crate::{blocks::Position, turtle::TurtleCommander};
+use crate::{blocks::{Position, SharedWorld, Vec3, Coords, Direction}, turtle::{TurtleCommander, FailureReason, Condition, MAX_WAIT}, paths::route, tasks::{Task, TaskState, Role}, mine::MinerSlot};
 use crate::turtle::{TurtleCommand::*, TurtleCommandResponse};
 
 
+/// Times to wait on an empty fuel chest or full output chest before moving on
+const SUPPLY_RETRIES: u32 = 4;
+/// Time (s) to wait each retry, the player may be restocking
+const SUPPLY_WAIT: u32 = MAX_WAIT;
+/// Fuel below which a turtle won't leave a depot, however long it has to wait
+const MIN_FUEL: usize = 1500;
+/// Stacks of room an output chest needs to take everything a turtle carries
+const HEADROOM: u32 = 16;
+
+/// Blocks that can serve as a depot's chests
+const CONTAINERS: [&str; 2] = ["chest", "barrel"];
+
+fn container(name: &str) -> bool {
+    CONTAINERS.iter().any(|c| name.contains(c))
+}
+
+/// Check that a turtle sitting in a depot has both of its chests
+async fn intact(turtle: &TurtleCommander) -> anyhow::Result<Position> {
+    // look again rather than trusting what was last seen
+    let info = turtle.execute(Update).await;
+
+    if !container(&info.below) {
+        return Err(anyhow!("no output chest below {}, found {}", info.pos, info.below));
+    }
+    if !container(&info.ahead) {
+        return Err(anyhow!("no fuel chest ahead of {}, found {}", info.pos, info.ahead));
+    }
+    Ok(info.pos)
+}
+
+/// How full a chest is thought to be
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Chest {
+    /// Stacks it holds, unlimited if unknown
+    #[serde(default)]
+    pub capacity: Option<u32>,
+    /// Stacks put in since a player last emptied it
+    #[serde(default)]
+    pub filled: u32,
+}
+
+impl Chest {
+    /// Too full to be sure of taking a turtle's whole inventory
+    pub fn nearly_full(&self) -> bool {
+        self.capacity.is_some_and(|c| self.filled + HEADROOM > c)
+    }
+}
+
+/// Which of a depot's chests, by what it is for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChestRole {
+    /// Below, where turtles empty their inventory
+    #[default]
+    Output,
+    /// Ahead, where turtles take fuel, and spare pickaxes if there is no tool chest
+    Fuel,
+    /// Above, where spare pickaxes are kept, if the depot has one
+    Tools,
+}
+
+/// A place to dump items and refuel
+///
+/// below the position is an output chest
+/// ahead of the position is a chest of combustibles
+/// above the position may be a chest of spare pickaxes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Depot {
+    pub position: Position,
+    /// Where turtles approach the depot from
+    pub staging: Position,
+    /// Has a chest of spare pickaxes above
+    #[serde(default)]
+    pub tools: bool,
+    /// The chest below, counted so turtles can go elsewhere before it jams
+    #[serde(default)]
+    pub output: Chest,
+    /// The chest ahead, which worn pickaxes and unwanted spares go back into
+    #[serde(default)]
+    pub fuel: Chest,
+    /// The chest above, if there is one
+    #[serde(default)]
+    pub spares: Chest,
+}
+
+impl Depot {
+    /// Depot approached from directly behind
+    pub fn new(position: Position) -> Self {
+        Self::with_staging(position, Position::new(position.pos - position.dir.unit(), position.dir))
+    }
+
+    pub fn with_staging(position: Position, staging: Position) -> Self {
+        Self { position, staging, tools: false, output: Chest::default(), fuel: Chest::default(), spares: Chest::default() }
+    }
+
+    /// One of the depot's chests, None if it has no such chest
+    pub fn chest_mut(&mut self, role: ChestRole) -> Option<&mut Chest> {
+        match role {
+            ChestRole::Output => Some(&mut self.output),
+            ChestRole::Fuel => Some(&mut self.fuel),
+            ChestRole::Tools => self.tools.then_some(&mut self.spares),
+        }
+    }
+
+    /// Depot where the turtle is sitting, if it has chests below and ahead
+    ///
+    /// A chest above is taken to hold spare pickaxes
+    pub async fn here(turtle: &TurtleCommander) -> anyhow::Result<Self> {
+        let position = intact(turtle).await?;
+        let above = turtle.world().get(position.pos + Vec3::y()).await;
+        let tools = above.is_some_and(|b| container(&b.name));
+        Ok(Self { tools, ..Self::new(position) })
+    }
+
+    /// Check that the depot can be entered from its staging position
+    pub async fn validate(&self, world: &SharedWorld) -> anyhow::Result<()> {
+        // devoring places turtles ahead of the staging position
+        if self.staging.pos + self.staging.dir.unit() != self.position.pos {
+            return Err(anyhow!("staging {} does not face the depot at {}", self.staging, self.position));
+        }
+
+        if route(self.staging, self.position, world).await.is_none() {
+            return Err(anyhow!("depot at {} is unreachable from {}", self.position, self.staging));
+        }
+
+        Ok(())
+    }
+}
+
 /// List of available depots
 ///
-/// below the specified position is an output chest of infinite capacity
-/// ahead of the specified position is a chest of combustibles
+/// Depots never move, so only their use is locked
 #[derive(Clone, Debug)]
 pub struct Depots {
-    depots: Arc<Mutex<Vec<Arc<Mutex<Position>>>>>,
+    depots: Arc<Mutex<Vec<(Depot, Arc<Mutex<()>>)>>>,
     depot_semaphore: Arc<Semaphore>,
+    /// Turtles in the middle of docking
+    docking: Arc<AtomicUsize>,
+}
+
+/// Counts a turtle as docking for as long as it is held
+struct Docking(Arc<AtomicUsize>);
+
+impl Docking {
+    fn new(count: &Arc<AtomicUsize>) -> Self {
+        count.fetch_add(1, Ordering::SeqCst);
+        Self(count.clone())
+    }
+}
+
+impl Drop for Docking {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 pub struct DepotGuard {
-    mutex: OwnedMutexGuard<Position>,
+    depot: Depot,
+    #[allow(unused)]
+    mutex: OwnedMutexGuard<()>,
     #[allow(unused)]
     semaphore: OwnedSemaphorePermit, // "dropped in declaration order"
                                      //  - reference chapter 10.8
 }
 
 impl DepotGuard {
-    fn new(mutex: OwnedMutexGuard<Position>, semaphore: OwnedSemaphorePermit) -> Self { Self { mutex, semaphore } }
+    fn new(depot: Depot, mutex: OwnedMutexGuard<()>, semaphore: OwnedSemaphorePermit) -> Self { Self { depot, mutex, semaphore } }
 
     pub fn position(&self) -> &Position {
-        &self.mutex
+        &self.depot.position
+    }
+
+    pub fn staging(&self) -> &Position {
+        &self.depot.staging
+    }
+
+    pub fn tools(&self) -> bool {
+        self.depot.tools
     }
     
 }
 
 impl Depots {
-    /// Nearest depot to the given position
-    pub async fn nearest(&self, pos: Position) -> DepotGuard {
+    /// Nearest depot to the given position, passing over those with full output chests
+    ///
+    /// None if there are no depots left
+    pub async fn nearest(&self, pos: Position) -> Option<DepotGuard> {
+        self.nearest_except(pos, &[]).await
+    }
+
+    /// Nearest depot, only falling back on skipped depots or full output chests if nothing else is free
+    async fn nearest_except(&self, pos: Position, skipped: &[Vec3]) -> Option<DepotGuard> {
+        // there would be no permit to wait for
+        if self.depots.lock().await.is_empty() {
+            error!("there are no depots");
+            return None;
+        }
         let permit = self.depot_semaphore.clone().acquire_owned().await.unwrap();
-        let mutex = self.depots.lock().await
-            .iter().map(|i| i.clone())
-            .filter_map(|i| i.try_lock_owned().ok())
-            .min_by_key(|d| d.manhattan(pos))
-            .map(|d| d);
+        let Some((depot, mutex)) = self.depots.lock().await
+            .iter()
+            .filter_map(|(d, m)| Some((*d, m.clone().try_lock_owned().ok()?)))
+            .min_by_key(|(d, _)| (skipped.contains(&d.position.pos), d.output.nearly_full(), d.position.manhattan(pos)))
+        else {
+            error!("a depot was free but none could be taken");
+            return None;
+        };
 
-        DepotGuard::new(mutex.unwrap(), permit)
+        if depot.output.nearly_full() {
+            warn!("every free depot is close to full, using {}", depot.position);
+        }
+
+        Some(DepotGuard::new(depot, mutex, permit))
+    }
+
+    /// The depot docked at the given point, once it is free
+    pub async fn at(&self, pos: Vec3) -> Option<DepotGuard> {
+        let (depot, mutex) = self.depots.lock().await
+            .iter()
+            .find(|(d, _)| d.position.pos == pos)
+            .cloned()?;
+
+        let permit = self.depot_semaphore.clone().acquire_owned().await.unwrap();
+        let mutex = mutex.lock_owned().await;
+
+        Some(DepotGuard::new(depot, mutex, permit))
     }
 
     pub async fn dock(&self, turtle: TurtleCommander) -> Option<usize> {
-        let depot = self.clone().nearest(turtle.pos().await).await;
-        trace!("depot at {:?}", depot.position());
-        turtle.goto(*depot.position()).await?;
+        let _docking = Docking::new(&self.docking);
+        // depots given up on, tried again only once there is nothing else
+        let mut skipped = Vec::new();
+        let depot = loop {
+            let preferred = match turtle.output() {
+                Some(pos) if !skipped.contains(&pos) => self.at(pos).await,
+                _ => None,
+            };
+            if let (Some(pos), None, true) = (turtle.output(), &preferred, skipped.is_empty()) {
+                warn!("depot at {} is gone, using the nearest", Coords(pos));
+            }
+            let depot = match preferred {
+                Some(depot) => depot,
+                None => self.nearest_except(turtle.pos().await, &skipped).await?,
+            };
+            let retry = skipped.contains(&depot.position().pos);
+            trace!("depot at {}", depot.position());
+
+            let reached = match turtle.goto(*depot.staging()).await {
+                Some(_) => turtle.goto(*depot.position()).await,
+                None => None,
+            };
+            if reached.is_none() {
+                if retry {
+                    error!("no depot can be reached");
+                    return None;
+                }
+                warn!("depot at {} can't be reached, trying the next nearest", depot.position());
+                skipped.push(depot.position().pos);
+                continue;
+            }
+
+            if let Err(e) = intact(&turtle).await {
+                // dumping into air loses everything
+                error!("depot at {} is broken, trying another: {e}", depot.position());
+                turtle.goto(*depot.staging()).await;
+                if !self.remove(depot).await {
+                    return None;
+                }
+                continue;
+            }
+
+            if dump(&turtle).await || retry {
+                break depot;
+            }
+            warn!("output chest at {} is full, trying the next nearest", depot.position());
+            skipped.push(depot.position().pos);
+            turtle.goto(*depot.staging()).await;
+        };
 
-        dump(&turtle).await;
         refuel(&turtle).await;
+        if let Some(side) = turtle.worn_tool().await {
+            turtle.swap_tool(side, depot.tools()).await;
+        }
         
+        // leave the way we came in
+        turtle.goto(*depot.staging()).await;
         // This can fail, we don't really care (as long as it executes once)
-        turtle.execute(Backward(4)).await;
+        turtle.execute(Backward(3)).await;
 
         drop(depot);
 
         // lava bucket fix
-        for (i, _) in turtle.inventory().await.into_iter().enumerate().filter(|(_,n)| n.is_some()) {
-            turtle.execute(Select((i+1) as u32)).await;
-            turtle.execute(DropDown(64)).await;
+        let leftover = turtle.inventory().await.into_iter().enumerate().filter(|(_,n)| n.is_some())
+            .flat_map(|(i, _)| [Select((i+1) as u32), DropDown(64)])
+            .collect::<Vec<_>>();
+        if !leftover.is_empty() {
+            turtle.execute_batch(leftover).await;
         }
 
 
         Some(turtle.fuel())
     }
 
-    pub async fn add(&self, pos: Position) {
-        info!("new depot at {pos:?}");
-        self.depots.lock().await.push(Arc::new(Mutex::new(pos)));
+    /// Stop handing out a depot, such as one whose chests are gone
+    ///
+    /// It has to be registered again once fixed
+    ///
+    /// The last depot is kept while other turtles are docking, as they would have nowhere to go.
+    /// False if it was kept
+    async fn remove(&self, depot: DepotGuard) -> bool {
+        let mut depots = self.depots.lock().await;
+        // the turtle removing it is docking too
+        if depots.len() == 1 && self.docking.load(Ordering::SeqCst) > 1 {
+            error!("depot at {} is the last one and turtles are waiting on it, keeping it", depot.position());
+            return false;
+        }
+        depots.retain(|(d, _)| d.position.pos != depot.position().pos);
+        // one less depot to go around
+        depot.semaphore.forget();
+        true
+    }
+
+    /// Distance (manhattan) from the nearest depot to the closest point of a region
+    pub async fn distance(&self, lower: Vec3, upper: Vec3) -> Option<i32> {
+        self.depots.lock().await.iter()
+            .map(|(d, _)| d.position.pos)
+            .map(|d| (d.sup(&lower).inf(&upper) - d).abs().sum())
+            .min()
+    }
+
+    /// Nearest depot, by where it is docked at, whether or not it is in use
+    pub async fn closest(&self, pos: Vec3) -> Option<Vec3> {
+        self.depots.lock().await.iter()
+            .map(|(d, _)| d.position.pos)
+            .min_by_key(|d| (d - pos).abs().sum())
+    }
+
+    /// Nearest depot with spare pickaxes, by where it is docked at
+    pub async fn with_tools(&self, pos: Vec3) -> Option<Vec3> {
+        self.depots.lock().await.iter()
+            .filter(|(d, _)| d.tools)
+            .map(|(d, _)| d.position.pos)
+            .min_by_key(|d| (d - pos).abs().sum())
+    }
+
+    /// Depots with a turtle docked or on the way, and how many there are
+    pub async fn occupancy(&self) -> (usize, usize) {
+        let depots = self.depots.lock().await;
+        let occupied = depots.iter().filter(|(_, m)| m.try_lock().is_err()).count();
+        (occupied, depots.len())
+    }
+
+    /// Count stacks put in a chest of the depot docked at the given point
+    pub async fn dropped(&self, pos: Vec3, role: ChestRole, stacks: u32) {
+        if let Some(chest) = self.depots.lock().await.iter_mut().find(|(d, _)| d.position.pos == pos).and_then(|(d, _)| d.chest_mut(role)) {
+            chest.filled += stacks;
+        }
+    }
+
+    /// A chest of the depot docked at the given point took no more, so it holds what was put in
+    pub async fn overflowed(&self, pos: Vec3, role: ChestRole) {
+        if let Some(chest) = self.depots.lock().await.iter_mut().find(|(d, _)| d.position.pos == pos).and_then(|(d, _)| d.chest_mut(role)) {
+            if chest.capacity.is_none() {
+                info!("{role:?} chest at {} holds about {} stacks", Coords(pos), chest.filled);
+            }
+            chest.capacity = Some(chest.filled);
+        }
+    }
+
+    /// Start counting a chest of the depot docked at the given point from empty, once a player or hauler cleared it
+    ///
+    /// None if there is no such depot, or it has no such chest
+    pub async fn emptied(&self, pos: Vec3, role: ChestRole) -> Option<Depot> {
+        let mut depots = self.depots.lock().await;
+        let (depot, _) = depots.iter_mut().find(|(d, _)| d.position.pos == pos)?;
+        depot.chest_mut(role)?.filled = 0;
+        Some(*depot)
+    }
+
+    pub async fn contains(&self, pos: Vec3) -> bool {
+        self.depots.lock().await.iter().any(|(d, _)| d.position.pos == pos)
+    }
+
+    pub async fn add(&self, depot: Depot) {
+        info!("new depot at {}, staged from {}", depot.position, depot.staging);
+        self.depots.lock().await.push((depot, Default::default()));
         self.depot_semaphore.add_permits(1);
     }
 
-    pub fn from_vec(vec: Vec<Position>) -> Self {
+    pub fn from_vec(vec: Vec<Depot>) -> Self {
         let mut depots = Vec::new();
         for depot in vec {
-            depots.push(Arc::new(Mutex::new(depot)));
+            depots.push((depot, Default::default()));
         }
         let permits = depots.len();
         Depots { depots: Arc::new(Mutex::new(depots)),
-            depot_semaphore: Arc::new(Semaphore::new(permits))
+            depot_semaphore: Arc::new(Semaphore::new(permits)),
+            docking: Default::default(),
         }
     }
 
-    pub async fn to_vec(self) -> Vec<Position> {
-        let mut depots = Vec::new();
-        for depot in self.depots.lock().await.iter() {
-            depots.push(*depot.lock().await)
-        }
-        depots
+    pub async fn to_vec(self) -> Vec<Depot> {
+        self.depots.lock().await.iter().map(|(d, _)| *d).collect()
     }
 }
 
-pub async fn dump(turtle: &TurtleCommander) {
+/// Empty the turtle into the chest below, false if it filled up first
+pub async fn dump(turtle: &TurtleCommander) -> bool {
+    let pos = turtle.pos().await.pos;
+    let mut retries = SUPPLY_RETRIES;
     for (i, _) in turtle.inventory().await.into_iter().enumerate().filter(|(_,n)| n.is_some()) {
         turtle.execute(Select((i+1) as u32)).await;
-        turtle.execute(DropDown(64)).await;
+        while let TurtleCommandResponse::Failure(_) = turtle.execute(DropDown(64)).await.ret {
+            if retries == 0 {
+                warn!("output chest below {} is full", turtle.pos().await);
+                turtle.depots().overflowed(pos, ChestRole::Output).await;
+                return false;
+            }
+            retries -= 1;
+            turtle.execute(Wait(SUPPLY_WAIT)).await;
+        }
+        turtle.depots().dropped(pos, ChestRole::Output, 1).await;
     }
+    true
 }
 
 
+/// Burn fuel from the chest ahead, sending what doesn't burn to the output chest below
 pub async fn refuel(turtle: &TurtleCommander) {
+    top_up(turtle, true).await
+}
+
+/// Burn fuel from a chest ahead that isn't part of a depot, putting back what doesn't burn
+///
+/// Gives up once the chest runs dry rather than waiting for a restock
+pub async fn refuel_from_ahead(turtle: &TurtleCommander) {
+    top_up(turtle, false).await
+}
+
+async fn top_up(turtle: &TurtleCommander, depot: bool) {
     turtle.execute(Select(1)).await;
     let limit = turtle.fuel_limit();
+    let mut retries = SUPPLY_RETRIES;
     while turtle.fuel() + 1000 < limit {
         turtle.execute(SuckFront(64)).await;
         let re = turtle.execute(Refuel).await;
-        turtle.execute(DropDown(64)).await;
-        if let TurtleCommandResponse::Failure = re.ret {
-            // partial refuel, good enough
-            warn!("only received {} fuel", turtle.fuel());
-            if turtle.fuel() > 1500 {
-                break;
-            } else {
-                turtle.execute(Wait(15)).await;
+        // whatever didn't burn goes to the output, or back where it came from
+        match depot {
+            true => if let TurtleCommandResponse::Success = turtle.execute(DropDown(64)).await.ret {
+                turtle.depots().dropped(turtle.pos().await.pos, ChestRole::Output, 1).await;
+            },
+            false => { turtle.execute(DropFront(64)).await; },
+        }
+        let TurtleCommandResponse::Failure(_) = re.ret else {
+            retries = SUPPLY_RETRIES;
+            continue;
+        };
+        if !depot {
+            break;
+        }
+        // the chest may only be empty while it is restocked
+        if retries > 0 {
+            retries -= 1;
+            turtle.execute(WaitUntil(Condition::ItemsAhead, SUPPLY_WAIT)).await;
+            continue;
+        }
+        // partial refuel, good enough
+        warn!("only received {} fuel", turtle.fuel());
+        if turtle.fuel() > MIN_FUEL {
+            break;
+        }
+        turtle.execute(WaitUntil(Condition::ItemsAhead, MAX_WAIT)).await;
+    }
+}
+
+/// Move the contents of one depot's output chest to another's
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Haul {
+    from: Vec3,
+    to: Vec3,
+    #[serde(skip_deserializing)]
+    haulers: Arc<AtomicUsize>,
+    done: Arc<AtomicBool>,
+}
+
+impl Haul {
+    pub fn new(from: Vec3, to: Vec3) -> Self {
+        Self { from, to, haulers: Default::default(), done: Default::default() }
+    }
+
+    /// Make trips between the depots until the source is empty
+    async fn haul(&self, turtle: TurtleCommander) -> Option<()> {
+        let trip = (self.from - self.to).abs().sum() as usize;
+
+        loop {
+            if turtle.cancelled() {
+                return None;
+            }
+            if turtle.fuel() < 2 * trip + 1000 {
+                turtle.dock().await;
+            }
+
+            let source = turtle.depot_at(self.from).await?;
+            turtle.goto_nodig(*source.staging()).await?;
+            turtle.goto_nodig(*source.position()).await?;
+
+            let mut empty = false;
+            for _ in 1..=16 {
+                match turtle.execute(SuckDown(64)).await.ret {
+                    TurtleCommandResponse::Failure(FailureReason::NoItems) => {
+                        empty = true;
+                        break;
+                    },
+                    TurtleCommandResponse::Failure(_) => break,
+                    _ => {},
+                }
+            }
+
+            if empty {
+                turtle.depots().emptied(self.from, ChestRole::Output).await;
+            }
+
+            turtle.goto_nodig(*source.staging()).await;
+            drop(source);
+
+            let destination = turtle.depot_at(self.to).await?;
+            turtle.goto_nodig(*destination.staging()).await?;
+            turtle.goto_nodig(*destination.position()).await?;
+
+            dump(&turtle).await;
+
+            turtle.goto_nodig(*destination.staging()).await;
+            drop(destination);
+
+            if turtle.inventory().await.iter().any(|s| s.is_some()) {
+                error!("output chest at {} is full", Coords(self.to));
+                return None;
+            }
+
+            if empty {
+                return Some(());
             }
         }
     }
 }
+
+#[serde]
+impl Task for Haul {
+    fn role(&self) -> Role {
+        Role::Hauler
+    }
+
+    fn run(&mut self, turtle: TurtleCommander) -> AbortHandle {
+        self.haulers.fetch_add(1, Ordering::SeqCst);
+        let slot = MinerSlot(self.haulers.clone());
+
+        let owned = self.clone();
+
+        tokio::spawn(async move {
+            let _slot = slot;
+            match owned.haul(turtle.clone()).await {
+                Some(()) => owned.done.store(true, Ordering::SeqCst),
+                None if turtle.cancelled() => info!("hauling from {} to {} cancelled", Coords(owned.from), Coords(owned.to)),
+                None => {
+                    // a full destination doesn't empty by trying again
+                    error!("hauling from {} to {} failed, giving up", Coords(owned.from), Coords(owned.to));
+                    owned.done.store(true, Ordering::SeqCst);
+                },
+            }
+        }).abort_handle()
+    }
+
+    fn poll(&mut self) -> TaskState {
+        if self.done.load(Ordering::SeqCst) {
+            return TaskState::Complete;
+        }
+        if self.haulers.load(Ordering::SeqCst) < 1 {
+            return TaskState::Ready(Position::new(self.from, Direction::North));
+        }
+        TaskState::Waiting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn last_depot() {
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let depots = Depots::from_vec(vec![Depot::new(here)]);
+
+        // another turtle waiting to dock keeps it around
+        let _other = Docking::new(&depots.docking);
+        let _this = Docking::new(&depots.docking);
+        assert!(!depots.remove(depots.nearest(here).await.unwrap()).await);
+        assert!(depots.contains(here.pos).await);
+
+        drop(_other);
+        assert!(depots.remove(depots.nearest(here).await.unwrap()).await);
+        // nothing left to wait on
+        assert!(depots.nearest(here).await.is_none());
+
+        depots.add(Depot::new(here)).await;
+        assert!(depots.nearest(here).await.is_some());
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::{Mul, Add};
+use std::{ops::{Mul, Add}, sync::{Arc, atomic::{AtomicUsize, Ordering}}};
 
 use tracing::{trace, warn, info, error};
 use nalgebra::Vector2;
@@ -7,15 +7,70 @@ use time::OffsetDateTime;
 use tokio::task::{JoinHandle, AbortHandle};
 use typetag::serde;
 
-use crate::{blocks::{Vec3, Position, Direction}, turtle::{TurtleCommander, TurtleCommand, TurtleCommandResponse, InventorySlot}, tasks::{Task, TaskState}, depot::Depots, mine::fill, paths::TRANSPARENT};
+use crate::{blocks::{Vec3, Position, Direction, Coords}, turtle::{TurtleCommander, TurtleCommand}, tasks::{Task, TaskState}, depot::Depots, mine::{fill, ChunkedTask, MinerSlot}};
 
-#[tracing::instrument(skip(turtle))]
-pub async fn fell_tree(turtle: TurtleCommander, bottom: Vec3) -> Option<bool> {
+/// Which blocks and items a tree farm treats as parts of its trees, by substrings of their names
+///
+/// Any one substring matching is enough, the defaults suit vanilla trees
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TreeKinds {
+    pub logs: Vec<String>,
+    pub saplings: Vec<String>,
+    pub soils: Vec<String>,
+}
+
+impl Default for TreeKinds {
+    fn default() -> Self {
+        Self {
+            logs: vec!["log".into()],
+            saplings: vec!["sapling".into()],
+            soils: vec!["dirt".into()],
+        }
+    }
+}
+
+impl TreeKinds {
+    /// Kinds from comma separated lists of substrings, keeping the default for any left empty
+    pub fn from_lists(logs: Option<&str>, saplings: Option<&str>, soils: Option<&str>) -> Self {
+        let defaults = Self::default();
+        Self {
+            logs: substrings(logs, defaults.logs),
+            saplings: substrings(saplings, defaults.saplings),
+            soils: substrings(soils, defaults.soils),
+        }
+    }
+
+    pub fn log(&self, name: &str) -> bool {
+        self.logs.iter().any(|l| name.contains(l.as_str()))
+    }
+
+    pub fn sapling(&self, name: &str) -> bool {
+        self.saplings.iter().any(|s| name.contains(s.as_str()))
+    }
+
+    pub fn soil(&self, name: &str) -> bool {
+        self.soils.iter().any(|s| name.contains(s.as_str()))
+    }
+}
+
+/// Names from a comma separated list, or the defaults if there are none
+fn substrings(list: Option<&str>, default: Vec<String>) -> Vec<String> {
+    let names: Vec<String> = list.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|n| !n.is_empty())
+        .map(String::from)
+        .collect();
+    if names.is_empty() { default } else { names }
+}
+
+#[tracing::instrument(skip(turtle, kinds))]
+pub async fn fell_tree(turtle: TurtleCommander, bottom: Vec3, kinds: &TreeKinds) -> Option<bool> {
     let mut log = bottom;
     let mut successful = false;
     loop {
         let near = turtle.goto_adjacent(log).await?;
-        if turtle.world().get(log).await.is_some_and(|b| !b.name.contains("log")) {
+        if turtle.world().get(log).await.is_some_and(|b| !kinds.log(&b.name)) {
             break;
         }
         successful = true;
@@ -28,11 +83,24 @@ pub async fn fell_tree(turtle: TurtleCommander, bottom: Vec3) -> Option<bool> {
 /// Minutes before checking
 const SWEEP_DELAY: i64 = 16;
 
+/// Distance between trees, vertical spacing keeps layers from touching
+const SPACING: Vec3 = Vec3::new(2, 32, 2);
+
+/// Turtles working a farm at once
+const MAX_FELLERS: usize = 8;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TreeFarm {
     position: Vec3,
     size: Vec3,
     last_sweep: OffsetDateTime,
+    /// Trees left in the current sweep
+    #[serde(default)]
+    progress: ChunkedTask,
+    #[serde(skip_deserializing)]
+    fellers: Arc<AtomicUsize>,
+    #[serde(default)]
+    kinds: TreeKinds,
 }
 
 impl TreeFarm {
@@ -41,86 +109,66 @@ impl TreeFarm {
             position,
             size: Vec3::new(5,1,2),
             last_sweep: OffsetDateTime::UNIX_EPOCH,
+            progress: Default::default(),
+            fellers: Default::default(),
+            kinds: Default::default(),
         }
     }
 
-    #[tracing::instrument]
-    pub async fn sweep(&self, turtle: TurtleCommander) -> Option<()> {
-        let trees = self.size.product();
-        let spacing = Vec3::new(2, 32, 2);
-        turtle.dock().await;
-        let mut successful = false;
-        for tree in 0..trees {
-            let index = fill(self.size, tree);
-            let offset = index.component_mul(&spacing);
-            trace!("tree {tree}; {offset:?}");
-            let tree = self.position + offset;
-            if fell_tree(turtle.clone(), tree).await? {
-                successful = true;
-            }
-        }
+    /// Fell and replant something other than vanilla trees
+    pub fn kinds(self, kinds: TreeKinds) -> Self {
+        Self { kinds, ..self }
+    }
 
-        if !successful {
-            warn!("incomplete harvest, no trees found");
-            return Some(());
+    /// Harvest a single tree and replant it
+    ///
+    /// Each tree is only handed to one turtle per sweep, so saplings are
+    /// taken from whatever that turtle picked up
+    #[tracing::instrument(skip(self, turtle))]
+    pub async fn tend(&self, turtle: TurtleCommander, tree: i32) -> Option<()> {
+        let offset = fill(self.size, tree).component_mul(&SPACING);
+        trace!("tree {tree}; {}", Coords(offset));
+        let tree = self.position + offset;
+
+        let full = turtle.inventory().await.iter().filter(|s| s.is_some()).count();
+        if turtle.fuel() < 5000 || turtle.full(full as u32) {
+            turtle.dock().await;
         }
 
-        // sweep across floor (not upper levels) to get saplings
-        // this goes one block past the far corner and to the near corner
-        let near_margin = Vec3::new(1, 0, 1);
-        let area = self.size.xz().component_mul(&spacing.xz()).add(near_margin.xz()).product();
-        for tile in 0..area {
-            let scale = self.size.component_mul(&Vec3::new(spacing.x, 1, spacing.z))
-                + near_margin; // near corner
-            let offset = fill(scale, tile);
-            let tile = self.position + offset - near_margin;
-            turtle.goto_adjacent(tile-Vec3::y()).await;
-            turtle.execute(TurtleCommand::SuckFront(64)).await;
+        if !fell_tree(turtle.clone(), tree, &self.kinds).await? {
+            trace!("no tree at {}", Coords(tree));
         }
 
-        // scan inventory for saplings
-        let mut saplings = Vec::new();
-        let mut needed = trees;
-        for slot in 1..=16 {
-            if let TurtleCommandResponse::Item(i) = turtle.execute(TurtleCommand::ItemInfo(slot)).await.ret {
-                if i.name.contains("sapling") {
-                    needed -= i.count as i32;
-                    saplings.push((slot,i));
-                }
-                if needed <= 0 { break; }
+        // sweep across the floor (not upper levels) around the tree to get saplings
+        let neighborhood = Vec3::new(3, 1, 3);
+        for tile in 0..neighborhood.product() {
+            let tile = tree + fill(neighborhood, tile) - Vec3::new(1, 0, 1);
+            if tile == tree {
+                continue;
             }
+            turtle.goto_adjacent(tile-Vec3::y()).await;
+            turtle.execute(TurtleCommand::SuckFront(64)).await;
         }
 
-        if needed > 0 {
-            warn!("incomplete wood harvest, {needed} saplings short");
+        if turtle.world().occupied(tree).await {
+            return Some(());
         }
 
-        fn pop_item(vec: &mut Vec<(u32, InventorySlot)>) -> Option<u32> {
-            let mut slot = vec.pop()?;
-            let index = slot.0;
-            slot.1.count -= 1;
-            if slot.1.count > 0 {
-                vec.push(slot);
-            }
-            Some(index)
-        }
+        let sapling = turtle.inventory().await.into_iter().enumerate()
+            .find(|(_, s)| s.as_ref().is_some_and(|s| self.kinds.sapling(&s.name)))
+            .map(|(i, _)| i as u32 + 1);
 
-        // plant saplings
-        for tree in 0..trees {
-            let index = fill(self.size, tree);
-            let offset = index.component_mul(&spacing);
-            let tree = self.position + offset;
+        let sapling = match sapling {
+            Some(slot) => slot,
+            None => {
+                warn!("no sapling to replant {}", Coords(tree));
+                return Some(());
+            },
+        };
 
-            if !turtle.world().occupied(tree).await {
-                let sapling = match pop_item(&mut saplings) {
-                    Some(slot) => slot,
-                    None => break,
-                };
-                let near = turtle.goto_adjacent(tree).await?;
-                turtle.execute(TurtleCommand::Select(sapling)).await;
-                turtle.execute(near.place(tree)?).await;
-            }
-        }
+        let near = turtle.goto_adjacent(tree).await?;
+        turtle.execute(TurtleCommand::Select(sapling)).await;
+        turtle.execute(near.place(tree)?).await;
 
         Some(())
     }
@@ -130,10 +178,10 @@ impl TreeFarm {
         let mut soil_to_lay = Vec::new();
         for tree in 0..trees {
             let index = fill(self.size, tree);
-            let offset = index.component_mul(&Vec3::new(2, 32, 2));
+            let offset = index.component_mul(&SPACING);
             let tree = self.position + offset;
             let soil = tree - Vec3::y();
-            if turtle.world().get(soil).await.map_or_else(|| true, |b| b.name.contains("dirt")) {
+            if turtle.world().get(soil).await.map_or_else(|| true, |b| self.kinds.soil(&b.name)) {
                 soil_to_lay.push(soil);
             }
         }
@@ -155,18 +203,65 @@ impl Task for TreeFarm {
     fn run(&mut self,turtle:TurtleCommander) -> AbortHandle  {
         let frozen = self.clone();
         tokio::spawn(async move {
-            if let None = frozen.sweep(turtle).await {
-                error!("felling at {} failed", frozen.position);
+            let _slot = MinerSlot(frozen.fellers.clone());
+            let tree = frozen.progress.next_chunk();
+
+            if let Some(tree) = tree {
+                if frozen.tend(turtle, *tree).await.is_none() {
+                    error!("felling at {} failed", Coords(frozen.position));
+                    tree.cancel();
+                } else {
+                    tree.finish();
+                }
+            } else {
+                error!("scheduled tree out of range");
             }
         }).abort_handle()
     }
 
     fn poll(&mut self) -> TaskState  {
-        let elapsed = OffsetDateTime::now_utc() - self.last_sweep;
-        if elapsed.whole_minutes() <= SWEEP_DELAY {
+        if self.progress.done() {
+            let elapsed = OffsetDateTime::now_utc() - self.last_sweep;
+            if elapsed.whole_minutes() <= SWEEP_DELAY {
+                return TaskState::Waiting;
+            }
+            self.last_sweep = OffsetDateTime::now_utc();
+            self.progress = ChunkedTask::new(self.size.product());
+        }
+
+        if self.progress.allocated() {
             return TaskState::Waiting;
         }
-        self.last_sweep = OffsetDateTime::now_utc();
-        TaskState::Ready(Position::new(self.position, Direction::North)) // request a turtle
+
+        let only = self.fellers.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            if n < MAX_FELLERS {
+                Some(n+1)
+            } else {
+                None
+            }
+        }).is_ok();
+
+        if only {
+            return TaskState::Ready(Position::new(self.position, Direction::North)); // request a turtle
+        }
+        TaskState::Waiting
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tree_kinds() {
+        let kinds = TreeKinds::from_lists(Some(" log, stem ,,mangrove_roots"), Some(" , "), None);
+        assert!(kinds.log("minecraft:stripped_oak_log"));
+        assert!(kinds.log("minecraft:crimson_stem"));
+        assert!(kinds.log("minecraft:mangrove_roots"));
+        assert!(!kinds.log("minecraft:stone"));
+        assert!(kinds.sapling("minecraft:birch_sapling"));
+        assert_eq!(kinds.soils, ["dirt"]);
+        assert!(kinds.soil("minecraft:coarse_dirt"));
+        assert!(!TreeKinds::default().log("minecraft:warped_stem"));
     }
 }
@@ -1,14 +1,14 @@
 use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
 
 use anyhow::{Ok, Context, anyhow, Result};
-use axum::{Router, routing::post, extract::State, Json};
+use axum::{Router, routing::post, extract::State, Json, http::StatusCode};
 use hyper::body::Buf;
 use serde::{Deserialize, Serialize};
 use tokio::task::AbortHandle;
-use tracing::{info, error};
+use tracing::{info, warn};
 use typetag::serde;
 
-use crate::{SharedControl, mine::{Remove, ChunkedTask, Quarry}, blocks::{Vec3, Direction, Position}, tasks::{TaskState, Task}, turtle::TurtleCommander, construct::BuildSimple, vendored::schematic::Schematic};
+use crate::{SharedControl, depot::Depots, mine::{Remove, ChunkedTask, Quarry, WhenFull, Keep, BlockFilter, check_region}, blocks::{Vec3, Direction, Position, Coords}, tasks::{TaskState, Task, Added}, turtle::TurtleCommander, turtle_api::{Ack, AckResponse}, construct::BuildSimple, vendored::schematic::Schematic};
 
 pub fn forms_api() -> Router<SharedControl> {
     Router::new()
@@ -26,28 +26,109 @@ struct GoogleFormsRemoveVein{
     y: String,
     #[serde(rename(deserialize = "Z coordinate"))]
     z: String,
+    #[serde(default, rename(deserialize = "Confirm"))]
+    confirm: Option<String>,
+}
+
+/// Furthest a form coordinate can be from the origin, about the world border
+const MAX_HORIZONTAL: i32 = 30_000_000;
+/// Furthest a form coordinate can be from y=0, past any build height
+const MAX_VERTICAL: i32 = 4096;
+
+/// Parse a whole number typed into a form, tolerating spaces and thousands separators
+///
+/// Errors name the field so they make sense when sent back to the submitter
+fn number(field: &str, answer: &str) -> anyhow::Result<i32> {
+    let answer = answer.trim();
+    // a pasted paragraph is not worth echoing back
+    let shown: String = answer.chars().take(32).collect();
+    if answer.starts_with('~') {
+        return Err(anyhow!("{field}: relative coordinates like {shown:?} aren't supported, use absolute ones"));
+    }
+    let digits: String = answer.chars().filter(|c| !matches!(c, ',' | '_' | ' ')).collect();
+    digits.parse().map_err(|_| anyhow!("{field}: {shown:?} is not a whole number"))
+}
+
+/// Settings for orders placed through the forms, read from `forms.ron` in the save
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct FormConfig {
+    /// Where `~` coordinates are measured from
+    pub reference: Option<Vec3>,
+}
+
+/// Point that `~` coordinates are relative to: the configured one, or else the depot nearest spawn
+async fn reference(forms: &FormConfig, depots: &Depots) -> Option<Vec3> {
+    if let Some(reference) = forms.reference {
+        return Some(reference);
+    }
+    let depot = depots.closest(Vec3::zeros()).await;
+    if let Some(depot) = depot {
+        warn!("no reference point in forms.ron, so ~ is measured from the depot at {}", Coords(depot));
+    }
+    depot
+}
+
+/// Parse a coordinate, rejecting ones too far out to be meant
+///
+/// `~` or `~offset` is taken relative to the reference point's value on this axis
+fn coordinate(field: &str, answer: &str, reference: Option<i32>, limit: i32) -> anyhow::Result<i32> {
+    let n = match answer.trim().strip_prefix('~') {
+        None => number(field, answer)?,
+        Some(offset) => {
+            let reference = reference.ok_or_else(|| anyhow!("{field}: relative coordinates need a reference point, and there is no depot or reference in forms.ron to use"))?;
+            let offset = offset.trim();
+            if offset.starts_with('~') {
+                let shown: String = answer.trim().chars().take(32).collect();
+                return Err(anyhow!("{field}: {shown:?} has more than one ~"));
+            }
+            let offset = if offset.is_empty() { 0 } else { number(field, offset)? };
+            reference.checked_add(offset).ok_or_else(|| anyhow!("{field}: ~{offset} is out of range"))?
+        },
+    };
+    if n.unsigned_abs() > limit.unsigned_abs() {
+        return Err(anyhow!("{field}: {n} is further than {limit} from 0"));
+    }
+    Ok(n)
+}
+
+fn position(x: &str, y: &str, z: &str, reference: Option<Vec3>) -> anyhow::Result<Vec3> {
+    Ok(Vec3::new(
+        coordinate("X coordinate", x, reference.map(|r| r.x), MAX_HORIZONTAL)?,
+        coordinate("Y coordinate", y, reference.map(|r| r.y), MAX_VERTICAL)?,
+        coordinate("Z coordinate", z, reference.map(|r| r.z), MAX_HORIZONTAL)?,
+    ))
+}
+
+/// Whether a yes/no answer was yes, such as "Confirm" allowing the safety caps to be exceeded
+fn confirmed(answer: &Option<String>) -> bool {
+    answer.as_ref().is_some_and(|a| {
+        let a = a.trim();
+        a.eq_ignore_ascii_case("yes") || a.eq_ignore_ascii_case("true")
+    })
 }
 
 async fn remove_vein(
     State(state): State<SharedControl>,
     Json(req): Json<GoogleFormsRemoveVein>,
-) -> &'static str {
+) -> AckResponse {
     match remove_vein_inner(state, req).await {
-        anyhow::Result::Ok(_) => {},
-        anyhow::Result::Err(e) => error!("remove vein request failed: {e}"),
-    };
-
-    "ACK"
+        Result::Ok((added, notes)) => Ack::task_with(added, &notes),
+        Err(e) => Ack::err(StatusCode::BAD_REQUEST, format!("remove vein request failed: {e}")),
+    }
 }
 
-async fn remove_vein_inner(state: SharedControl, req: GoogleFormsRemoveVein) -> anyhow::Result<()> {
+/// The task added, with anything worth mentioning about where it is
+async fn remove_vein_inner(state: SharedControl, req: GoogleFormsRemoveVein) -> anyhow::Result<(Added, Vec<String>)> {
     let state = state.read().await;
-    let mut schedule = state.tasks.lock().await;
-    let position = { Vec3::new(req.x.parse()?,req.y.parse()?,req.z.parse()?) };
+    let schedule = &state.tasks;
+    let reference = reference(&state.forms, &state.depots).await;
+    let position = position(&req.x, &req.y, &req.z, reference)?;
     let block = req.block;
-    info!("new remove {block} command from the internet at {position}");
-    schedule.add_task(Box::new(Remove::new(position,block)));
-    Ok(())
+    let notes = check_region(position, position + Vec3::repeat(1), &state.depots, &state.world, &state.limits, confirmed(&req.confirm)).await?;
+    let remove = Remove::new(position, block.clone(), &state.limits, confirmed(&req.confirm))?;
+    info!("new remove {block} command from the internet at {}", Coords(position));
+    Ok((schedule.add_task(Box::new(remove)), notes))
 }
 
 #[derive(Deserialize, Debug)]
@@ -85,26 +166,45 @@ struct GoogleOmniForm{
     z2: Option<String>,
     #[serde(rename(deserialize = "Upload a .schematic file"))]
     schematic: Option<Vec<String>>,
+    #[serde(default, rename(deserialize = "Confirm"))]
+    confirm: Option<String>,
+    /// Simulate the operation and log a report instead of running it
+    #[serde(default, rename(deserialize = "Dry run"))]
+    dry_run: Option<String>,
+    /// Bring junk back from area removals instead of leaving it there
+    #[serde(default, rename(deserialize = "Keep everything"))]
+    keep: Option<String>,
+    /// Leave the outer layer of an area removal standing
+    #[serde(default, rename(deserialize = "Keep walls"))]
+    walls: Option<String>,
+    #[serde(default, rename(deserialize = "Pillar spacing"))]
+    pillars: Option<String>,
+    /// Comma separated blocks for an area removal to take, leaving the rest
+    #[serde(default, rename(deserialize = "Only remove"))]
+    only: Option<String>,
+    /// Comma separated blocks for an area removal to leave
+    #[serde(default, rename(deserialize = "Remove all but"))]
+    except: Option<String>,
 }
 
 async fn omni(
     State(state): State<SharedControl>,
     Json(req): Json<GoogleOmniForm>,
-) -> &'static str {
+) -> AckResponse {
     info!("omni: {:?}", req);
     match omni_inner(state, req).await {
-        anyhow::Result::Ok(_) => {},
-        anyhow::Result::Err(e) => error!("remove vein request failed: {e}"),
-    };
-
-    "ACK"
+        Result::Ok((added, notes)) => Ack::task_with(added, &notes),
+        Err(e) => Ack::err(StatusCode::BAD_REQUEST, format!("omni request failed: {e}")),
+    }
 }
 
-async fn omni_inner(state: SharedControl, req: GoogleOmniForm) -> anyhow::Result<()> {
+async fn omni_inner(state: SharedControl, req: GoogleOmniForm) -> anyhow::Result<(Added, Vec<String>)> {
     let state = state.read().await;
-    let mut schedule = state.tasks.lock().await;
-    let position = { Vec3::new(req.x.parse()?,req.y.parse()?,req.z.parse()?) };
-    match req.operation {
+    let schedule = &state.tasks;
+    let reference = reference(&state.forms, &state.depots).await;
+    let position = position(&req.x, &req.y, &req.z, reference)?;
+    let dry_run = confirmed(&req.dry_run);
+    let added = match req.operation {
         GoogleOmniFormMode::Schematic => {
             let schematic = req.schematic.context("no schematic uploaded")?.get(0).context("zero schematics")?.to_owned();
             let schematic = reqwest::get(format!("https://docs.google.com/uc?export=download&id={schematic}")).await?.bytes().await?;
@@ -120,19 +220,23 @@ async fn omni_inner(state: SharedControl, req: GoogleOmniForm) -> anyhow::Result
                 let schematic = Schematic::load(&mut schematic.reader()).unwrap();
                 BuildSimple::new(position, &schematic, input)
             }).await.unwrap();
+            let builder = if dry_run { builder.dry_run() } else { builder };
 
-            schedule.add_task(Box::new(builder));
+            (schedule.add_task(Box::new(builder)), Vec::new())
         },
         GoogleOmniFormMode::RemoveVein => {
             let block = req.block.context("missing block name")?;
-            info!("new remove {block} command from the internet at {position}");
-            schedule.add_task(Box::new(Remove::new(position,block)));
+            let notes = check_region(position, position + Vec3::repeat(1), &state.depots, &state.world, &state.limits, confirmed(&req.confirm)).await?;
+            let remove = Remove::new(position, block.clone(), &state.limits, confirmed(&req.confirm))?;
+            let remove = if dry_run { remove.dry_run() } else { remove };
+            info!("new remove {block} command from the internet at {}", Coords(position));
+            (schedule.add_task(Box::new(remove)), notes)
         },
         GoogleOmniFormMode::RemoveArea => {
             let upper = Vec3::new(
-                req.x2.context("x2")?.parse()?,
-                req.y2.context("y2")?.parse()?,
-                req.z2.context("z2")?.parse()?,
+                coordinate("X coordinate (to)", &req.x2.context("missing X coordinate (to)")?, reference.map(|r| r.x), MAX_HORIZONTAL)?,
+                coordinate("Y coordinate (to)", &req.y2.context("missing Y coordinate (to)")?, reference.map(|r| r.y), MAX_VERTICAL)?,
+                coordinate("Z coordinate (to)", &req.z2.context("missing Z coordinate (to)")?, reference.map(|r| r.z), MAX_HORIZONTAL)?,
             );
 
             let min = Vec3::new(
@@ -147,14 +251,28 @@ async fn omni_inner(state: SharedControl, req: GoogleOmniForm) -> anyhow::Result
                 upper.z.max(position.z),
             );
 
-            let quarry = Quarry::new(min, max);
-            schedule.add_task(Box::new(quarry));
+            let full = if confirmed(&req.keep) { WhenFull::Dock } else { WhenFull::Trash };
+            let pillars = req.pillars.as_deref().map(str::trim).filter(|p| !p.is_empty());
+            let keep = match pillars {
+                _ if confirmed(&req.walls) => Keep::Shell,
+                Some(spacing) => Keep::Pillars(number("Pillar spacing", spacing)?),
+                None => Keep::Nothing,
+            };
+            let filter = BlockFilter::from_lists(req.only.as_deref(), req.except.as_deref())?;
+            let notes = check_region(min, max, &state.depots, &state.world, &state.limits, confirmed(&req.confirm)).await?;
+            let quarry = Quarry::new(min, max, &state.limits, confirmed(&req.confirm))?.when_full(full).keep(keep)?.filter(filter);
+            let quarry = match state.chunk_size().await {
+                Some(chunk) => quarry.chunk_size(chunk),
+                None => quarry,
+            };
+            let quarry = if dry_run { quarry.dry_run() } else { quarry };
+            (schedule.add_task(Box::new(quarry)), notes)
         },
         GoogleOmniFormMode::Goto => {
-            schedule.add_task(Box::new(Goto::new(Position::new(position, req.facing.context("missing direction")?))));
+            (schedule.add_task(Box::new(Goto::new(Position::new(position, req.facing.context("missing direction")?)))), Vec::new())
         },
-    }
-    Ok(())
+    };
+    Ok(added)
 }
 
 #[derive(Serialize, Deserialize)]
@@ -179,7 +297,7 @@ impl Task for Goto {
         let position = self.position.clone();
 
         tokio::spawn(async move {
-            turtle.goto(position).await;
+            turtle.goto_nodig(position).await;
         }).abort_handle()
     }
 
@@ -191,3 +309,39 @@ impl Task for Goto {
         TaskState::Ready(self.position)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messy_numbers() {
+        assert_eq!(number("X", " -1,024 ").unwrap(), -1024);
+        assert_eq!(number("X", "12 345").unwrap(), 12345);
+        assert!(number("X", "~5").unwrap_err().to_string().contains("relative"));
+        assert!(number("X", "next to the big tree").unwrap_err().to_string().starts_with("X:"));
+        assert!(position("0", "64", "99,999,999", None).is_err());
+        assert!(position("0", "-5000", "0", None).is_err());
+        assert!(position("-2147483648", "0", "0", None).is_err());
+        assert_eq!(position("1", "2", "3", None).unwrap(), Vec3::new(1, 2, 3));
+    }
+
+    #[test]
+    fn relative_coordinates() {
+        let landmark = Some(Vec3::new(100, 64, -20));
+        assert_eq!(position("~5", "~", "~ -1,000", landmark).unwrap(), Vec3::new(105, 64, -1020));
+        assert_eq!(position("7", "~-4", "~+0", landmark).unwrap(), Vec3::new(7, 60, -20));
+        assert!(position("~5", "64", "0", None).unwrap_err().to_string().contains("reference point"));
+        assert!(position("~~5", "64", "0", landmark).unwrap_err().to_string().contains("more than one ~"));
+        assert!(position("~north", "64", "0", landmark).unwrap_err().to_string().starts_with("X coordinate:"));
+        assert!(position("0", "~5000", "0", landmark).is_err());
+    }
+
+    #[test]
+    fn form_config() {
+        let forms = FormConfig { reference: Some(Vec3::new(-22, 91, 42)) };
+        let saved = ron::ser::to_string(&forms).unwrap();
+        assert_eq!(ron::de::from_str::<FormConfig>(&saved).unwrap(), forms);
+        assert_eq!(ron::de::from_str::<FormConfig>("()").unwrap(), FormConfig::default());
+    }
+}
@@ -1,28 +1,32 @@
 #![feature(iter_map_windows, iter_collect_into, int_roundings, test)]
 
-use std::{collections::VecDeque, io::ErrorKind, sync::Arc, env::args, path, borrow::BorrowMut, time::Duration};
+use std::{collections::{HashMap, VecDeque}, io::{ErrorKind, Read}, sync::{Arc, atomic::{AtomicBool, Ordering}}, env::args, path, borrow::BorrowMut, time::Duration};
 
-use anyhow::{Error, Ok};
+use anyhow::{Error, Ok, anyhow};
 use axum::{
-    extract::{State},
-    routing::{get},
-    Router,
+    body::Bytes,
+    extract::{State, DefaultBodyLimit, Query},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Router, Json,
 };
-use blocks::{SharedWorld, Position, World, };
-use depot::Depots;
+use blocks::{SharedWorld, Position, World, Vec3};
+use depot::{Depots, Depot};
+use mine::Quarry;
 use opentelemetry::global;
 use opentelemetry_sdk::{runtime::Tokio, trace::BatchConfig};
 use ron::ser::PrettyConfig;
 use tower_http::trace::TraceLayer;
-use tracing::{info, span, Level};
-use rstar::RTree;
+use tracing::{info, error, span, Level};
+use rstar::{RTree, AABB};
 
-use names::Name;
-use tasks::Scheduler;
+use names::{Name, NameScheme};
+use tasks::{Scheduler, SchedulerHandle, Role};
 use tokio::{sync::{
     RwLock, mpsc, OnceCell, Mutex, watch
 }, fs, time::Instant, runtime::Runtime};
-use tracing_subscriber::{fmt::format::FmtSpan, layer::{SubscriberExt, Filter}, util::SubscriberInitExt, filter, Layer};
+use tracing_subscriber::{fmt::format::FmtSpan, layer::{SubscriberExt, Filter}, util::SubscriberInitExt, filter::{self, LevelFilter}, Layer, reload, Registry};
 use turtle::{Turtle, TurtleCommander};
 use serde::{Deserialize, Serialize};
 use indoc::formatdoc;
@@ -40,11 +44,17 @@ mod turtle;
 mod turtle_api;
 mod tasks;
 mod depot;
+mod deliver;
 mod googleforms;
+mod updates;
+mod metrics;
 mod vendored;
 
 static PORT: OnceCell<u16> = OnceCell::const_new();
 static SAVE: OnceCell<path::PathBuf> = OnceCell::const_new();
+/// client.lua, served to turtles as they boot
+static CLIENT: OnceCell<path::PathBuf> = OnceCell::const_new();
+static LOG_FILTER: OnceCell<reload::Handle<filter::Targets, Registry>> = OnceCell::const_new();
 
 type SharedControl = Arc<RwLock<LiveState>>;
 
@@ -59,6 +69,18 @@ async fn main() -> Result<(), Error> {
         Some(file) => file.into(),
         None => "save".into(),
     })?;
+    // next to the server's source unless moved for deployment
+    CLIENT.set(match std::env::var_os("AVARUS_CLIENT") {
+        Some(file) => file.into(),
+        None => path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../client/client.lua"),
+    })?;
+    let scheme: Option<NameScheme> = args.next().map(|s| s.parse()).transpose()?;
+    let pathfinders = match args.next() {
+        // none would leave every route waiting forever
+        Some(n) => n.parse::<usize>()?.max(1),
+        None => paths::MAX_PATHFINDERS,
+    };
+    paths::PATHFINDERS.set(tokio::sync::Semaphore::new(pathfinders))?;
 
     global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
 
@@ -75,27 +97,27 @@ async fn main() -> Result<(), Error> {
         .with_target("server::construct", Level::INFO)
         .with_target("server::depot", Level::TRACE);
 
+    let (filter, filter_handle) = reload::Layer::new(filter);
+    LOG_FILTER.set(filter_handle)?;
+
     let log = fs::OpenOptions::new().append(true).create(true).open(SAVE.get().unwrap().join("avarus.log")).await?;
     let (non_blocking, _guard) = tracing_appender::non_blocking(log.into_std().await);
 
     let stdout = tracing_subscriber::fmt::layer()
         .compact()
         .with_file(false)
-        .with_target(true)
         //.with_span_events(FmtSpan::ACTIVE)
-        .with_filter(filter.clone());
+        .with_target(true);
 
     let log = tracing_subscriber::fmt::layer()
         .compact()
         .with_file(false)
         .with_target(true)
         //.with_span_events(FmtSpan::ACTIVE)
-        .with_writer(non_blocking)
-        .with_filter(filter);
+        .with_writer(non_blocking);
 
     let reg = tracing_subscriber::registry()
-        .with(stdout)
-        .with(log);
+        .with(stdout.and_then(log).with_filter(filter));
 
     let otel = false;
     if otel {
@@ -117,17 +139,49 @@ async fn main() -> Result<(), Error> {
 
 
     info!("starting");
+    names::check_tables();
 
 
     let (kill_send, kill_recv) = watch::channel(false);
 
-    let state = read_from_disk(kill_send).await?;
+    let state = read_from_disk(kill_send, scheme).await?;
+
+    info!("boot #{}", state.boots);
+
+    tokio::spawn(state.world.updates().clone().publish());
+
+    // turtles that boot quickly shouldn't get every task before the rest have checked in
+    let ready = state.ready.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(turtle_api::STARTUP_ALLOWANCE).await;
+        ready.store(true, Ordering::Relaxed);
+        info!("allocating turtles to tasks");
+    });
 
     let state = SharedControl::new(RwLock::new(state));
 
     let server = Router::new()
         //.route("/turtle/:id/placeUp", get(place_up))
         .route("/flush", get(flush))
+        .route("/world/compact", post(compact))
+        .route("/world/export", get(export))
+        .route("/world/query", post(query))
+        .route("/world/updates", get(updates::viewer))
+        .route("/backup", get(backup))
+        .route("/restore", post(restore).layer(DefaultBodyLimit::disable()))
+        .route("/health", get(health))
+        .route("/metrics", get(metrics::metrics))
+        .route("/log", get(log_filter).post(set_log_level))
+        .route("/protected", get(protected).post(set_protected))
+        .route("/protected/reviewed", post(reviewed))
+        .route("/reloadConfig", post(reload_config))
+        .route("/depot/empty", post(turtle_api::empty_depot))
+        .route("/scheduler/pause", post(turtle_api::pause))
+        .route("/scheduler/resume", post(turtle_api::resume))
+        .route("/scheduler/status", get(turtle_api::scheduler_status))
+        .route("/tasks", get(turtle_api::tasks))
+        .route("/tasks/archive", get(turtle_api::archive))
+        .route("/tasks/:id/rerun", post(turtle_api::rerun))
         .nest("/turtle", turtle_api::turtle_api())
         .nest("/forms", googleforms::forms_api())
         .layer(TraceLayer::new_for_http())
@@ -147,37 +201,397 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 
-async fn flush(State(state): State<SharedControl>) -> &'static str {
-    write_to_disk(&*state.read().await).await.unwrap();
+async fn flush(State(state): State<SharedControl>) -> turtle_api::AckResponse {
+    match write_to_disk(&*state.read().await).await {
+        Result::Ok(()) => turtle_api::Ack::ok("state written to disk"),
+        Err(e) => turtle_api::Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("writing to disk failed: {e}")),
+    }
+}
 
-    "ACK"
+/// Forget chunks that are only air to keep the save small
+///
+/// Only done when asked, as it also forgets the dug out quarries routes cut through
+async fn compact(State(state): State<SharedControl>) -> turtle_api::AckResponse {
+    let dropped = state.read().await.world.compact().await;
+    turtle_api::Ack::ok(format!("dropped {dropped} empty chunks"))
 }
 
-async fn write_to_disk(state: &LiveState) -> anyhow::Result<()> {
-    let tasks = &state.tasks;
-    let mut turtles = Vec::new();
-    for turtle in state.turtles.iter() {
-        turtles.push(turtle.read().await.info());
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    /// Litematica schematic, for viewing in game
+    #[default]
+    Litematic,
+    /// The chunks as they are kept, for debugging
+    Ron,
+}
+
+#[derive(Deserialize)]
+struct Export {
+    #[serde(default)]
+    format: ExportFormat,
+    /// Opposite corners of the box to export as `x,y,z`, in either order, instead of everything mapped
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// Largest box exported as a schematic, which stores every block in it, air or not
+const MAX_EXPORT_VOLUME: i64 = 512 * 512 * 384;
+
+/// A corner given as `x,y,z`
+fn corner(text: &str) -> Option<Vec3> {
+    let mut coords = text.split(',').map(|n| n.trim().parse::<i32>().ok());
+    let corner = Vec3::new(coords.next()??, coords.next()??, coords.next()??);
+    coords.next().is_none().then_some(corner)
+}
+
+/// Download what the turtles have mapped
+async fn export(State(state): State<SharedControl>, Query(export): Query<Export>) -> Result<impl IntoResponse, turtle_api::AckResponse> {
+    // converting can take a while, so turtles can keep mapping meanwhile
+    let world = World::clone(&*state.read().await.world.clone().lock().await);
+    let failed = |e: Error| turtle_api::Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("export failed: {e}"));
+
+    let (file, disposition) = match export.format {
+        ExportFormat::Litematic => {
+            let within = match (export.from.as_deref(), export.to.as_deref()) {
+                (None, None) => None,
+                (Some(from), Some(to)) => match (corner(from), corner(to)) {
+                    (Some(from), Some(to)) => Some(AABB::from_corners(from.into(), to.into())),
+                    _ => return Err(turtle_api::Ack::err(StatusCode::BAD_REQUEST, "corners are given as x,y,z")),
+                },
+                _ => return Err(turtle_api::Ack::err(StatusCode::BAD_REQUEST, "give both corners or neither")),
+            };
+            let (lower, upper) = world.bounds(within)
+                .ok_or_else(|| turtle_api::Ack::err(StatusCode::NOT_FOUND, "nothing mapped there yet"))?;
+            let volume = World::volume(lower, upper);
+            if volume > MAX_EXPORT_VOLUME {
+                return Err(turtle_api::Ack::err(StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("{volume} blocks is more than the {MAX_EXPORT_VOLUME} allowed in one export, ask for a smaller box")));
+            }
+            let litematic = world.to_litematic(lower, upper).map_err(failed)?;
+            (litematic.to_bytes().map_err(|e| failed(e.into()))?, "attachment; filename=\"avarus.litematic\"")
+        },
+        ExportFormat::Ron => (ron::ser::to_string(&world).map_err(|e| failed(e.into()))?.into_bytes(), "attachment; filename=\"world.ron\""),
     };
-    let depots = state.depots.clone().to_vec().await;
+    info!("exported {} bytes of world", file.len());
 
-    let pretty = PrettyConfig::default()
-        .struct_names(true);
+    Result::Ok(([
+        (header::CONTENT_TYPE, "application/octet-stream"),
+        (header::CONTENT_DISPOSITION, disposition),
+    ], file))
+}
 
-    let turtles = ron::ser::to_string_pretty(&turtles, pretty.clone())?;
-    let world = bincode::serialize(&*state.world.clone().lock().await)?;
-    let depots = ron::ser::to_string_pretty(&depots, pretty.clone())?;
-    let tasks = ron::ser::to_string_pretty(&*tasks.lock().await, pretty.clone())?;
+#[derive(Deserialize)]
+struct WorldQuery {
+    /// Opposite corners of the box, in either order
+    from: Vec3,
+    to: Vec3,
+    /// Only blocks with this in their name, such as `ore`
+    #[serde(default)]
+    name: String,
+}
 
-    let path = &SAVE.get().unwrap();
-    tokio::fs::write(path.join("turtles.ron"), turtles).await?;
-    tokio::fs::write(path.join("depots.ron"), depots).await?;
-    tokio::fs::write(path.join("tasks.ron"), tasks).await?;
-    tokio::fs::write(path.join("world.bin"), world).await?;
-    Ok(())
+/// Known blocks in a box
+async fn query(State(state): State<SharedControl>, Json(query): Json<WorldQuery>) -> Json<Vec<Block>> {
+    let aabb = AABB::from_corners(query.from.into(), query.to.into());
+    let mut blocks = state.read().await.world.blocks_in(aabb).await;
+    blocks.retain(|b| b.name.contains(&query.name));
+    Json(blocks)
+}
+
+/// Current log filter, in `target=level` form
+async fn log_filter() -> String {
+    LOG_FILTER.get().unwrap()
+        .with_current(|f| f.to_string())
+        .unwrap_or_default()
+}
+
+#[derive(Deserialize)]
+struct LogLevel {
+    /// Module path, such as `server::paths`
+    target: String,
+    /// trace, debug, info, warn, error, or off
+    level: String,
+}
+
+/// Change the level of a single log target until restart
+async fn set_log_level(Json(req): Json<LogLevel>) -> Result<String, turtle_api::AckResponse> {
+    let handle = LOG_FILTER.get().unwrap();
+    let level = req.level.parse::<LevelFilter>()
+        .map_err(|e| turtle_api::Ack::err(StatusCode::BAD_REQUEST, format!("bad log level {}: {e}", req.level)))?;
+    info!("logging {} at {level}", req.target);
+    if let Err(e) = handle.modify(|f| *f = f.clone().with_target(req.target, level)) {
+        error!("log filter update failed: {e}");
+        return Err(turtle_api::Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("log filter update failed: {e}")));
+    }
+
+    Result::Ok(log_filter().await)
+}
+
+#[derive(Serialize)]
+struct Protected {
+    /// Block names, matched by substring, that turtles leave standing
+    blocks: Vec<String>,
+    /// Protected blocks turtles have come across since they were last reviewed
+    flagged: Vec<Block>,
+}
+
+async fn protected(State(state): State<SharedControl>) -> Json<Protected> {
+    let protection = state.read().await.world.protection().clone();
+    Json(Protected { blocks: protection.blocks().to_vec(), flagged: protection.flagged() })
+}
+
+/// Replace the protected blocks, keeping them across restarts
+async fn set_protected(State(state): State<SharedControl>, Json(blocks): Json<Vec<String>>) -> turtle_api::AckResponse {
+    if blocks.iter().any(|b| b.trim().is_empty()) {
+        return turtle_api::Ack::err(StatusCode::BAD_REQUEST, "an empty name would protect every block");
+    }
+    let file = match ron::ser::to_string(&blocks) {
+        Result::Ok(file) => file,
+        Err(e) => return turtle_api::Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("protected blocks not saved: {e}")),
+    };
+    if let Err(e) = tokio::fs::write(SAVE.get().unwrap().join("protected.ron"), file).await {
+        return turtle_api::Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("protected blocks not saved: {e}"));
+    }
+
+    info!("protecting {blocks:?}");
+    state.read().await.world.set_protected(blocks);
+    turtle_api::Ack::ok("protected blocks updated")
+}
+
+/// A config file in the save, or the defaults if there is none
+async fn read_config<T: serde::de::DeserializeOwned + Default>(file: &str) -> anyhow::Result<T> {
+    match tokio::fs::read_to_string(SAVE.get().unwrap().join(file)).await {
+        tokio::io::Result::Ok(file) => Ok(ron::de::from_str(&file)?),
+        tokio::io::Result::Err(e) => match e.kind() {
+            ErrorKind::NotFound => Ok(T::default()),
+            _ => Err(e.into()),
+        },
+    }
+}
+
+/// Read `blocks.ron`, `limits.ron`, and `forms.ron` again, keeping what is in use if any is broken
+///
+/// Chunks already being mined carry on with the lists they started with
+async fn reload_config(State(state): State<SharedControl>) -> turtle_api::AckResponse {
+    let blocks = match read_config::<paths::BlockConfig>("blocks.ron").await {
+        Result::Ok(blocks) => blocks,
+        Err(e) => return turtle_api::Ack::err(StatusCode::BAD_REQUEST, format!("blocks.ron not loaded: {e}")),
+    };
+    let limits = match read_config::<tasks::Limits>("limits.ron").await {
+        Result::Ok(limits) => limits,
+        Err(e) => return turtle_api::Ack::err(StatusCode::BAD_REQUEST, format!("limits.ron not loaded: {e}")),
+    };
+    let forms = match read_config::<googleforms::FormConfig>("forms.ron").await {
+        Result::Ok(forms) => forms,
+        Err(e) => return turtle_api::Ack::err(StatusCode::BAD_REQUEST, format!("forms.ron not loaded: {e}")),
+    };
+    info!("block lists: {blocks:?}");
+    let mut state = state.write().await;
+    state.world.set_block_config(blocks);
+    info!("limits: {limits:?}");
+    state.limits = limits;
+    info!("forms: {forms:?}");
+    state.forms = forms;
+    turtle_api::Ack::ok("config reloaded")
+}
+
+/// Forget the flagged blocks once an operator has dealt with them
+async fn reviewed(State(state): State<SharedControl>) -> turtle_api::AckResponse {
+    let cleared = state.read().await.world.protection().clear_flagged();
+    turtle_api::Ack::ok(format!("cleared {cleared} flagged blocks"))
+}
+
+#[derive(Serialize)]
+struct Health {
+    /// Seconds since boot
+    uptime: f64,
+    /// Times this save has been loaded, counting this one
+    boots: u64,
+    /// Whether turtles are being allocated to tasks yet
+    ready: bool,
+    turtles: usize,
+    tasks: usize,
+}
+
+async fn health(State(state): State<SharedControl>) -> Json<Health> {
+    let state = state.read().await;
+    let tasks = state.tasks.task_count();
+
+    Json(Health {
+        uptime: state.started.elapsed().as_secs_f64(),
+        boots: state.boots,
+        ready: state.ready.load(Ordering::Relaxed),
+        turtles: state.turtles.len(),
+        tasks,
+    })
+}
+
+/// Download every save file at once, taken while nothing else can touch the state
+async fn backup(State(state): State<SharedControl>) -> Result<impl IntoResponse, turtle_api::AckResponse> {
+    let state = state.write().await;
+    let backup = Backup::capture(&state).await
+        .and_then(|backup| backup.to_tar())
+        .map_err(|e| turtle_api::Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("backup failed: {e}")))?;
+    info!("backed up {} bytes", backup.len());
+
+    Result::Ok(([
+        (header::CONTENT_TYPE, "application/x-tar"),
+        (header::CONTENT_DISPOSITION, "attachment; filename=\"avarus.tar\""),
+    ], backup))
 }
 
-async fn read_from_disk(kill: watch::Sender<bool>) -> anyhow::Result<LiveState> {
+/// Replace the save with a backup from `/backup` once the server shuts down
+async fn restore(State(state): State<SharedControl>, body: Bytes) -> turtle_api::AckResponse {
+    let backup = match Backup::from_tar(&body) {
+        Result::Ok(backup) => backup,
+        Err(e) => return turtle_api::Ack::err(StatusCode::BAD_REQUEST, format!("not a backup: {e}")),
+    };
+    if let Err(e) = backup.validate() {
+        return turtle_api::Ack::err(StatusCode::BAD_REQUEST, format!("backup would not load: {e}"));
+    }
+
+    state.write().await.restore = Some(backup);
+    info!("backup staged");
+    turtle_api::Ack::ok("backup will replace the save on shutdown")
+}
+
+/// Every save file, as written to disk
+struct Backup {
+    turtles: String,
+    depots: String,
+    tasks: String,
+    names: String,
+    world: Vec<u8>,
+}
+
+impl Backup {
+    async fn capture(state: &LiveState) -> anyhow::Result<Self> {
+        let tasks = &state.tasks;
+        let mut turtles = Vec::new();
+        for turtle in state.turtles.iter() {
+            turtles.push(turtle.read().await.info());
+        };
+        let depots = state.depots.clone().to_vec().await;
+
+        let pretty = PrettyConfig::default()
+            .struct_names(true);
+
+        let turtles = ron::ser::to_string_pretty(&turtles, pretty.clone())?;
+        let world = state.world.clone().lock().await.to_bytes()?;
+        let depots = ron::ser::to_string_pretty(&depots, pretty.clone())?;
+        let tasks = tasks.save(pretty.clone()).await?;
+        let names = ron::ser::to_string_pretty(&names::SCHEME.get(), pretty.clone())?;
+
+        Ok(Self { turtles, depots, tasks, names, world })
+    }
+
+    /// Check that every file would load into this server
+    fn validate(&self) -> anyhow::Result<()> {
+        let scheme: Option<NameScheme> = ron::de::from_str(&self.names)?;
+        let current = names::SCHEME.get().copied();
+        if scheme != current {
+            return Err(anyhow!("backup uses {scheme:?} names, not {current:?}"));
+        }
+        ron::de::from_str::<Vec<Turtle>>(&self.turtles)?;
+        ron::de::from_str::<Vec<Depot>>(&self.depots)?;
+        ron::de::from_str::<Scheduler>(&self.tasks)?;
+        World::from_bytes(&self.world)?;
+        Ok(())
+    }
+
+    /// Each file's name in the save directory, and what goes in it
+    fn files(&self) -> [(&'static str, &[u8]); 5] {
+        [
+            ("turtles.ron", self.turtles.as_bytes()),
+            ("depots.ron", self.depots.as_bytes()),
+            ("tasks.ron", self.tasks.as_bytes()),
+            ("names.ron", self.names.as_bytes()),
+            ("world.bin", &self.world),
+        ]
+    }
+
+    async fn write(&self, path: &path::Path) -> anyhow::Result<()> {
+        for (name, data) in self.files() {
+            tokio::fs::write(path.join(name), data).await?;
+        }
+        Ok(())
+    }
+
+    /// Pack the files into a tar archive, laid out like the save directory
+    fn to_tar(&self) -> anyhow::Result<Vec<u8>> {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+        let mut archive = tar::Builder::new(Vec::new());
+        for (name, data) in self.files() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(now);
+            header.set_cksum();
+            archive.append_data(&mut header, name, data)?;
+        }
+        Ok(archive.into_inner()?)
+    }
+
+    fn from_tar(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut files = HashMap::new();
+        for entry in tar::Archive::new(bytes).entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            files.insert(name, data);
+        }
+        let mut take = |name: &str| files.remove(name).ok_or_else(|| anyhow!("{name} is missing"));
+
+        Ok(Self {
+            turtles: String::from_utf8(take("turtles.ron")?)?,
+            depots: String::from_utf8(take("depots.ron")?)?,
+            tasks: String::from_utf8(take("tasks.ron")?)?,
+            names: String::from_utf8(take("names.ron")?)?,
+            world: take("world.bin")?,
+        })
+    }
+}
+
+async fn write_to_disk(state: &LiveState) -> anyhow::Result<()> {
+    let path = SAVE.get().unwrap();
+    // a restored backup has to survive until it is loaded
+    if let Some(backup) = &state.restore {
+        info!("writing restored backup");
+        return backup.write(path).await;
+    }
+    Backup::capture(state).await?.write(path).await
+}
+
+async fn read_from_disk(kill: watch::Sender<bool>, scheme: Option<NameScheme>) -> anyhow::Result<LiveState> {
+    // names are stored encoded, so this has to be known before anything else
+    let saved: Option<NameScheme> = match tokio::fs::read_to_string(SAVE.get().unwrap().join("names.ron")).await {
+        tokio::io::Result::Ok(file) => ron::de::from_str(&file)?,
+        tokio::io::Result::Err(e) => match e.kind() {
+            ErrorKind::NotFound => None,
+            _ => panic!(),
+        },
+    };
+    let existing = SAVE.get().unwrap().join("turtles.ron").exists();
+    let scheme = match (saved, scheme) {
+        (Some(saved), Some(scheme)) if saved != scheme => {
+            error!("save uses {saved:?} names, ignoring {scheme:?}");
+            saved
+        },
+        (Some(saved), _) => saved,
+        // saves from before schemes were recorded
+        (None, scheme) if existing => {
+            if scheme.is_some_and(|s| s != NameScheme::Human) {
+                error!("save uses Human names, ignoring {scheme:?}");
+            }
+            NameScheme::Human
+        },
+        (None, scheme) => scheme.unwrap_or_default(),
+    };
+    info!("using {scheme:?} names");
+    names::SCHEME.set(scheme)?;
+
     let turtles: Vec<Turtle> = match tokio::fs::OpenOptions::new()
         .read(true)
         .open(SAVE.get().unwrap().join("turtles.ron"))
@@ -190,19 +604,19 @@ async fn read_from_disk(kill: watch::Sender<bool>) -> anyhow::Result<LiveState>
         },
     };
 
-    let depots = match tokio::fs::OpenOptions::new()
-        .read(true)
-        .open(SAVE.get().unwrap().join("depots.ron"))
-        .await
-    {
-        tokio::io::Result::Ok(file) => ron::de::from_reader(file.into_std().await)?,
+    let depots: Vec<Depot> = match tokio::fs::read_to_string(SAVE.get().unwrap().join("depots.ron")).await {
+        tokio::io::Result::Ok(file) => match ron::de::from_str(&file) {
+            Result::Ok(depots) => depots,
+            // saves from before staging positions
+            Err(_) => ron::de::from_str::<Vec<Position>>(&file)?.into_iter().map(Depot::new).collect(),
+        },
         tokio::io::Result::Err(e) => match e.kind() {
             ErrorKind::NotFound => Vec::new(),
             _ => panic!(),
         },
     };
 
-    let scheduler = match tokio::fs::OpenOptions::new()
+    let scheduler: Scheduler = match tokio::fs::OpenOptions::new()
         .read(true)
         .open(SAVE.get().unwrap().join("tasks.ron"))
         .await
@@ -214,9 +628,8 @@ async fn read_from_disk(kill: watch::Sender<bool>) -> anyhow::Result<LiveState>
         },
     };
 
-    let world = match tokio::fs::OpenOptions::new()
-    .read(true).open(SAVE.get().unwrap().join("world.bin")).await {
-        tokio::io::Result::Ok(file) => bincode::deserialize_from(file.into_std().await)?,
+    let world = match tokio::fs::read(SAVE.get().unwrap().join("world.bin")).await {
+        tokio::io::Result::Ok(file) => World::from_bytes(&file)?,
         tokio::io::Result::Err(e) => match e.kind() {
             ErrorKind::NotFound => World::new(),
             _ => panic!(),
@@ -224,20 +637,62 @@ async fn read_from_disk(kill: watch::Sender<bool>) -> anyhow::Result<LiveState>
         
     };
 
+    let protected: Vec<String> = match tokio::fs::read_to_string(SAVE.get().unwrap().join("protected.ron")).await {
+        tokio::io::Result::Ok(file) => ron::de::from_str(&file)?,
+        tokio::io::Result::Err(e) => match e.kind() {
+            ErrorKind::NotFound => paths::DEFAULT_PROTECTED.map(String::from).to_vec(),
+            _ => return Err(e.into()),
+        },
+    };
+    info!("protecting {protected:?}");
+
+    let blocks = read_config::<paths::BlockConfig>("blocks.ron").await?;
+    info!("block lists: {blocks:?}");
+
+    let limits = read_config::<tasks::Limits>("limits.ron").await?;
+    info!("limits: {limits:?}");
+
+    let forms = read_config::<googleforms::FormConfig>("forms.ron").await?;
+    info!("forms: {forms:?}");
+
+    // counted as soon as the save loads so crashes show up too
+    let boots: u64 = match tokio::fs::read_to_string(SAVE.get().unwrap().join("boots.ron")).await {
+        tokio::io::Result::Ok(file) => ron::de::from_str(&file)?,
+        tokio::io::Result::Err(e) => match e.kind() {
+            ErrorKind::NotFound => 0,
+            _ => panic!(),
+        },
+    } + 1;
+    tokio::fs::write(SAVE.get().unwrap().join("boots.ron"), ron::ser::to_string(&boots)?).await?;
+
     let scheduler = scheduler;let sender = kill;
     let mut bound_turtles: Vec<Turtle> = Vec::new();
     for turtle in turtles.into_iter() {
         let (tx, rx) = mpsc::channel(1);
-        bound_turtles.push(Turtle::with_channel(turtle.name.to_num(), turtle.position, turtle.fuel, turtle.fuel_limit, tx, rx));
+        let mut live = Turtle::with_channel(turtle.name.to_num(), turtle.position, turtle.fuel, turtle.fuel_limit, tx, rx);
+        live.fuel_used = turtle.fuel_used;
+        live.role = turtle.role;
+        live.home = turtle.home;
+        live.usable_slots = turtle.usable_slots;
+        bound_turtles.push(live);
     };
     let depots = Depots::from_vec(depots);
+    let world = SharedWorld::from_world(world);
+    world.set_protected(protected);
+    world.set_block_config(blocks);
     
     Ok(LiveState { turtles: bound_turtles.into_iter().map(|t| Arc::new(RwLock::new(t))).collect(),
-        tasks: Arc::new(Mutex::new(scheduler)), 
-        world: SharedWorld::from_world(world),
+        tasks: scheduler.spawn(), 
+        world,
         depots,
+        positions: Default::default(),
         started: Instant::now(),
+        boots,
+        ready: Default::default(),
         kill:sender,
+        restore: None,
+        limits,
+        forms,
     })
 }
 
@@ -245,17 +700,29 @@ async fn read_from_disk(kill: watch::Sender<bool>) -> anyhow::Result<LiveState>
 struct SavedState {
     turtles: Vec<turtle::Turtle>,
     world: World,
-    depots: Vec<Position>,
+    depots: Vec<Depot>,
     //chunkloaders: unimplemented!(),
 }
 
 struct LiveState {
     turtles: Vec<Arc<RwLock<turtle::Turtle>>>,
-    tasks: Arc<Mutex<Scheduler>>,
+    tasks: SchedulerHandle,
     world: blocks::SharedWorld,
     depots: Depots,
+    /// Last reported position of each turtle
+    positions: turtle::Positions,
     started: Instant,
+    /// Times the save has been loaded
+    boots: u64,
+    /// Set once the startup allowance has passed
+    ready: Arc<AtomicBool>,
     kill: watch::Sender<bool>,
+    /// Backup to write in place of the live state
+    restore: Option<Backup>,
+    /// Caps on the work tasks take on without confirmation
+    limits: tasks::Limits,
+    /// Settings for orders placed through the forms
+    forms: googleforms::FormConfig,
 }
 
 impl LiveState {
@@ -263,19 +730,45 @@ impl LiveState {
         let mut turtles = Vec::new();
         for turtle in save.turtles.into_iter() {
             let (tx, rx) = mpsc::channel(1);
-            turtles.push(Turtle::with_channel(turtle.name.to_num(), turtle.position, turtle.fuel, turtle.fuel_limit, tx, rx));
+            let mut live = Turtle::with_channel(turtle.name.to_num(), turtle.position, turtle.fuel, turtle.fuel_limit, tx, rx);
+            live.fuel_used = turtle.fuel_used;
+            live.role = turtle.role;
+            live.home = turtle.home;
+            live.usable_slots = turtle.usable_slots;
+            turtles.push(live);
         };
         let depots = Depots::from_vec(save.depots);
             
-        Self { turtles: turtles.into_iter().map(|t| Arc::new(RwLock::new(t))).collect(), tasks: Arc::new(Mutex::new(scheduler)), world: SharedWorld::from_world(save.world),
+        Self { turtles: turtles.into_iter().map(|t| Arc::new(RwLock::new(t))).collect(), tasks: scheduler.spawn(), world: SharedWorld::from_world(save.world),
             depots,
+            positions: Default::default(),
             started: Instant::now(),
+            boots: 0,
+            ready: Default::default(),
             kill:sender,
+            restore: None,
+            limits: Default::default(),
+            forms: Default::default(),
         }
     }
 
     async fn get_turtle(&self, name: u32) -> Option<TurtleCommander> {
         TurtleCommander::new(Name::from_num(name), self).await
     }
+
+    /// Quarry chunk that any turtle able to mine can dig out between docks,
+    /// going by its own fuel tank and the slots it fills before unloading
+    pub(crate) async fn chunk_size(&self) -> Option<Vec3> {
+        let mut size: Option<Vec3> = None;
+        for turtle in &self.turtles {
+            let turtle = turtle.read().await;
+            if turtle.fuel_limit == 0 || !turtle.role.fits(Role::Miner) {
+                continue;
+            }
+            let chunk = Quarry::chunk_for(turtle.fuel_limit, turtle.usable_slots.load(Ordering::SeqCst) as usize);
+            size = Some(size.map_or(chunk, |s| s.inf(&chunk)));
+        }
+        size
+    }
     
 }
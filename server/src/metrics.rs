@@ -0,0 +1,103 @@
+//! Gauges for Prometheus, read from the live state on every scrape
+//!
+//! Written in the text exposition format by hand, there are few enough of them
+
+use std::{collections::BTreeMap, fmt::{Display, Write}};
+
+use axum::{extract::State, http::header};
+
+use crate::SharedControl;
+
+/// Prometheus text format, one metric family after another
+#[derive(Default)]
+struct Exposition(String);
+
+impl Exposition {
+    fn gauge(&mut self, name: &str, help: &str) -> &mut Self {
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} gauge");
+        self
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) -> &mut Self {
+        self.0.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape(v)))
+                .collect();
+            let _ = write!(self.0, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.0, " {value}");
+        self
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub(crate) async fn metrics(State(state): State<SharedControl>) -> ([(header::HeaderName, &'static str); 1], String) {
+    // handles only, so the scheduler round trips below don't hold up everything else
+    let (turtles, tasks, depots, world) = {
+        let state = state.read().await;
+        (state.turtles.clone(), state.tasks.clone(), state.depots.clone(), state.world.clone())
+    };
+    let mut out = Exposition::default();
+
+    let mut fuel = Vec::new();
+    for turtle in &turtles {
+        let turtle = turtle.read().await;
+        fuel.push((turtle.name.to_owned().to_str(), turtle.fuel, turtle.fuel_limit));
+    }
+    out.gauge("avarus_turtle_fuel", "Fuel each turtle has left");
+    for (name, level, _) in &fuel {
+        out.sample("avarus_turtle_fuel", &[("turtle", name)], level);
+    }
+    out.gauge("avarus_turtle_fuel_limit", "Most fuel each turtle can hold");
+    for (name, _, limit) in &fuel {
+        out.sample("avarus_turtle_fuel_limit", &[("turtle", name)], limit);
+    }
+
+    let status = tasks.status().await;
+    out.gauge("avarus_turtles", "Turtles known to the scheduler, by whether they are running a task")
+        .sample("avarus_turtles", &[("state", "busy")], status.busy())
+        .sample("avarus_turtles", &[("state", "idle")], status.turtles().saturating_sub(status.busy()));
+
+    let mut pending: BTreeMap<&str, usize> = BTreeMap::new();
+    for task in tasks.tasks().await {
+        *pending.entry(task.name()).or_default() += 1;
+    }
+    out.gauge("avarus_tasks", "Queued tasks, by kind");
+    for (task, count) in pending {
+        out.sample("avarus_tasks", &[("task", task)], count);
+    }
+
+    let (occupied, depots) = depots.occupancy().await;
+    out.gauge("avarus_depots", "Depots, by whether a turtle is using one")
+        .sample("avarus_depots", &[("state", "occupied")], occupied)
+        .sample("avarus_depots", &[("state", "free")], depots - occupied);
+
+    out.gauge("avarus_world_blocks", "Blocks seen by turtles, air included")
+        .sample("avarus_world_blocks", &[], world.known().await);
+
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], out.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposition() {
+        let mut out = Exposition::default();
+        out.gauge("avarus_turtle_fuel", "Fuel")
+            .sample("avarus_turtle_fuel", &[("turtle", "Quote \"Q\" \\ Back")], 20)
+            .sample("avarus_world_blocks", &[], 3);
+        assert_eq!(out.0, concat!(
+            "# HELP avarus_turtle_fuel Fuel\n",
+            "# TYPE avarus_turtle_fuel gauge\n",
+            "avarus_turtle_fuel{turtle=\"Quote \\\"Q\\\" \\\\ Back\"} 20\n",
+            "avarus_world_blocks 3\n",
+        ));
+    }
+}
@@ -1,76 +1,254 @@
-use std::{sync::{Arc, atomic::{AtomicUsize, Ordering, AtomicI32, AtomicBool} }, ops::Deref};
+use std::{sync::{Arc, atomic::{AtomicUsize, Ordering, AtomicI32, AtomicBool, AtomicU32} }, ops::Deref, collections::{HashSet, BTreeSet}};
 
+use anyhow::anyhow;
 use crossbeam::channel::{Sender, Receiver};
 use tracing::{info, warn, error, instrument, trace};
 use serde::{Serialize, Deserialize};
-use tokio::{task::{JoinHandle, AbortHandle}, sync::{RwLock, Mutex}};
+use tokio::{task::{JoinHandle, AbortHandle}, sync::RwLock, time::Instant};
 use typetag::serde;
 
-use crate::{blocks::{Position, Vec3, Direction, SharedWorld}, turtle::{TurtleCommand, TurtleCommander, TurtleCommandResponse, InventorySlot}, paths::TRANSPARENT, tasks::{Task, TaskState}, names::Name, depot};
+use crate::{blocks::{Position, Vec3, Direction, SharedWorld, Coords, Block}, turtle::{TurtleCommand, TurtleCommander, TurtleCommandResponse, InventorySlot, DryRun, Condition, MAX_WAIT}, paths::{Approach, Avoid, BlockConfig}, tasks::{Task, TaskState, Role, Timing, Progress, Needs, Limits}, names::Name, depot};
 use TurtleCommand::*;
 
-/// Things to leave in the field (not worth fuel)
-const USELESS: [&str; 5] = [
-    "minecraft:dirt",
-    "minecraft:gravel",
-    "minecraft:cobblestone",
-    "minecraft:cobbled_deepslate",
-    "minecraft:rhyolite",
-    //"minecraft:andesite", // TODO: Reach 2k
+/// Things no pickaxe can break
+const UNBREAKABLE: [&str; 4] = [
+    "minecraft:bedrock",
+    "minecraft:barrier",
+    "minecraft:end_portal_frame",
+    "minecraft:reinforced_deepslate",
 ];
 
-/// Things that are desirable
-const VALUABLE: [&str; 1] = [
-    "ore",
-];
+/// Most valuables dug while following veins out of one chunk
+const MAX_CHASE: usize = 64;
+/// Most valuables dug in one go, the rest wait until the chunks are taken
+const MAX_CHASE_TRIP: usize = 16;
+/// Farthest (manhattan) from the chunk center a vein is followed
+const MAX_CHASE_DISTANCE: i32 = 16;
+
+/// Parts of a quarry left standing
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Keep {
+    /// Dig out everything
+    #[default]
+    Nothing,
+    /// The walls and floor, leaving a room open at the top for turtles to get in
+    Shell,
+    /// Columns spaced this many blocks apart, from the corner
+    Pillars(i32),
+}
 
-pub async fn mine(turtle: TurtleCommander, pos: Vec3, chunk: Vec3) -> Option<()> {
-    let mut pos = pos;
+impl Keep {
+    /// True if the block at `offset` from the corner of a volume is left standing
+    pub fn keeps(self, offset: Vec3, size: Vec3) -> bool {
+        let inside = offset.iter().all(|n| *n >= 0) && offset.zip_map(&size, |n, s| n < s).iter().all(|n| *n);
+        if !inside {
+            return false;
+        }
+        match self {
+            Keep::Nothing => false,
+            Keep::Shell => offset.y == 0
+                || offset.x == 0 || offset.x == size.x - 1
+                || offset.z == 0 || offset.z == size.z - 1,
+            Keep::Pillars(spacing) => offset.x % spacing == 0 && offset.z % spacing == 0,
+        }
+    }
+}
 
-    loop {
-        mine_chunk_and_sweep(turtle.clone(), pos, chunk).await?;
+/// Which blocks a quarry digs, by name
+///
+/// Names match if they contain any of the given ones, so "leaves" catches every kind
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BlockFilter {
+    #[default]
+    All,
+    Only(Vec<String>),
+    Except(Vec<String>),
+}
 
-        pos += Vec3::z() * chunk.z;
+impl BlockFilter {
+    /// Build from comma separated lists, at most one of which can be given
+    pub fn from_lists(only: Option<&str>, except: Option<&str>) -> anyhow::Result<Self> {
+        let names = |list: &str| -> Vec<String> {
+            list.split(',').map(str::trim).filter(|n| !n.is_empty()).map(String::from).collect()
+        };
+        let only = only.map(names).filter(|n| !n.is_empty());
+        let except = except.map(names).filter(|n| !n.is_empty());
+        match (only, except) {
+            (Some(_), Some(_)) => Err(anyhow!("give blocks to only remove or to leave, not both")),
+            (Some(only), None) => Ok(BlockFilter::Only(only)),
+            (None, Some(except)) => Ok(BlockFilter::Except(except)),
+            (None, None) => Ok(BlockFilter::All),
+        }
+    }
+
+    /// True if the block is to be dug
+    pub fn takes(&self, name: &str) -> bool {
+        match self {
+            BlockFilter::All => true,
+            BlockFilter::Only(names) => names.iter().any(|n| name.contains(n.as_str())),
+            BlockFilter::Except(names) => !names.iter().any(|n| name.contains(n.as_str())),
+        }
     }
 }
 
-#[instrument]
-pub async fn mine_chunk_and_sweep(turtle: TurtleCommander, pos: Vec3, chunk: Vec3) -> Option<()> {
-    let volume = chunk.x * chunk.y * chunk.z;
-    let mut valuables = Vec::new();
+/// What a mining turtle does once its inventory fills
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WhenFull {
+    /// Drop useless items on the spot, only docking once valuables fill it
+    #[default]
+    Trash,
+    /// Bring everything back to a depot
+    Dock,
+}
 
-    async fn refuel_needed(turtle: &TurtleCommander, volume: i32) {
-        if (turtle.fuel() as i32) < 2 * volume + 4000 {
-            turtle.dock().await;
+/// Mine a line of chunks along z, `length` of them or forever if None
+///
+/// Finished chunks are counted in `mined`, picking up after any already there
+pub async fn mine(turtle: TurtleCommander, pos: Vec3, chunk: Vec3, length: Option<u32>, mined: &AtomicU32) -> Option<()> {
+    loop {
+        let done = mined.load(Ordering::SeqCst);
+        if length.is_some_and(|l| done >= l) {
+            return Some(());
         }
+        // a cancelled chunk fails, so only stop between them
+        if turtle.cancelled() {
+            return Some(());
+        }
+
+        let pos = pos + Vec3::z() * chunk.z * done as i32;
+        let left = mine_chunk_and_sweep(turtle.clone(), pos, chunk, WhenFull::default(), Approach::Any, Pattern::default(), &BlockFilter::All, &turtle.world().block_config(), |_| false).await?;
+        // nothing comes back for these, unlike a quarry's
+        if !left.blocks.is_empty() {
+            warn!("abandoning {} valuables of a vein near {}, out of budget", left.blocks.len(), Coords(left.center));
+        }
+
+        mined.fetch_add(1, Ordering::SeqCst);
     }
+}
+
+/// Fuel to have on hand before digging out a volume
+fn fuel_needed(volume: i32) -> usize {
+    (2 * volume + 4000) as usize
+}
+
+async fn refuel_needed(turtle: &TurtleCommander, volume: i32) {
+    if turtle.fuel() < fuel_needed(volume) {
+        turtle.dock().await;
+    }
+}
 
-    if dump_filter(turtle.clone(), |i| USELESS.iter().any(|u| **u == i.name)).await > 12 {
+/// Valuables a chase left for later, with what it had to spend on them
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Vein {
+    blocks: Vec<Vec3>,
+    /// Center of the chunk the vein was found from, it is only followed so far from here
+    center: Vec3,
+    /// Valuables that may still be dug for that chunk
+    budget: usize,
+}
+
+/// Mine a chunk then follow any veins found in it
+///
+/// Returns the valuables that were left to chase later
+///
+/// Veins are left alone when only some blocks are wanted
+#[instrument(skip(blocks, kept))]
+pub async fn mine_chunk_and_sweep<K>(turtle: TurtleCommander, pos: Vec3, chunk: Vec3, full: WhenFull, approach: Approach, pattern: Pattern, filter: &BlockFilter, blocks: &BlockConfig, kept: K) -> Option<Vein>
+where K: Fn(Vec3) -> bool + Copy {
+    let volume = chunk.x * chunk.y * chunk.z;
+
+    let slots = match full {
+        WhenFull::Trash => dump_filter(turtle.clone(), |i| blocks.useless(&i.name)).await,
+        WhenFull::Dock => dump_filter(turtle.clone(), |_| false).await,
+    };
+    // merging stacks may save the trip
+    let slots = match turtle.full(slots) {
+        true => turtle.compact_inventory().await,
+        false => slots,
+    };
+    if turtle.full(slots) {
+        // whatever is left is worth the trip
         info!("storage rtb");
         turtle.dock().await;
+    } else if turtle.worn_tool().await.is_some() {
+        // a fresh one is picked up while docked, from a tool chest if any depot has one
+        info!("tool rtb");
+        turtle.with_output(turtle.tool_depot().await).dock().await;
     }
 
     devore(&turtle).await;
 
     refuel_needed(&turtle, volume).await;
 
-    mine_chunk(turtle.clone(), pos, chunk).await?;
+    mine_chunk(turtle.clone(), pos, chunk, approach, pattern, filter, blocks, kept).await?;
 
-    valuables.append(&mut near_valuables(&turtle, pos, chunk).await);
+    let center = pos + chunk / 2;
+    if *filter != BlockFilter::All {
+        return Some(Vein { blocks: Vec::new(), center, budget: 0 });
+    }
 
-    while let Some(block) = valuables.pop() {
-        refuel_needed(&turtle, volume).await;
+    let valuables = near_valuables(&turtle, pos, chunk, blocks).await;
 
-        if turtle.world().garbage(block).await {
-            continue;
+    chase(&turtle, Vein { blocks: valuables, center, budget: MAX_CHASE }, volume, blocks, kept).await
+}
+
+/// Dig out valuables and whatever more of them turns up nearby,
+/// within the vein's budget and MAX_CHASE_DISTANCE of its center
+///
+/// Returns the valuables left for another trip, past the budget or too far out are dropped
+async fn chase<K>(turtle: &TurtleCommander, vein: Vein, volume: i32, blocks: &BlockConfig, kept: K) -> Option<Vein>
+where K: Fn(Vec3) -> bool {
+    let Vein { blocks: mut valuables, center, mut budget } = vein;
+    let mut left = Vec::new();
+    let mut chased = 0;
+
+    while !valuables.is_empty() {
+        if turtle.cancelled() {
+            // whoever picks this up next can finish the vein
+            left.append(&mut valuables);
+            break;
         }
-        let near = turtle.goto_adjacent(block).await?;
+        let mut candidates = Vec::new();
+        for block in valuables.drain(..) {
+            if kept(block) || turtle.world().garbage(block).await || guarded(&turtle.world(), block).await {
+                continue;
+            }
+
+            if budget == 0 || (block - center).abs().sum() > MAX_CHASE_DISTANCE {
+                continue;
+            }
+            if chased >= MAX_CHASE_TRIP {
+                left.push(block);
+                continue;
+            }
+            candidates.push(block);
+        }
+        candidates.sort_by_key(|b| (b.x, b.y, b.z));
+        candidates.dedup();
+
+        if candidates.is_empty() {
+            break;
+        }
+
+        refuel_needed(turtle, volume).await;
+
+        // one search for the closest, rather than one per block in whatever order
+        let (block, near) = turtle.goto_adjacent_any(&candidates, Approach::Any).await?;
         turtle.execute(near.dig(block)?).await;
+        chased += 1;
+        budget -= 1;
         observe(turtle.clone(), block).await;
-        valuables.append(&mut near_valuables(&turtle, near.pos, Vec3::new(2,2,2)).await);
+        candidates.retain(|b| *b != block);
+        valuables = candidates;
+        valuables.append(&mut near_valuables(turtle, near.pos, Vec3::new(2,2,2), blocks).await);
     }
 
-    Some(())
+    left.sort_by_key(|b| (b.x, b.y, b.z));
+    left.dedup();
+    if !left.is_empty() {
+        info!("left {} valuables near {} for later", left.len(), Coords(center));
+    }
+    Some(Vein { blocks: left, center, budget })
 }
 
 /// Send mined turtles to the nearest depot
@@ -83,37 +261,52 @@ async fn devore(turtle: &TurtleCommander) {
         return;
     }
 
-    let depot = turtle.get_depot().await;
+    // anything carried was picked up before now, so can't have reported since
+    let started = Instant::now();
+    let Some(depot) = turtle.get_depot().await else {
+        error!("no depot to devore turtles at");
+        return;
+    };
 
     for i in turtles {
-        let position = depot.position().clone();
+        let staging = depot.staging().clone();
 
-        let staging = position.pos - position.dir.unit();
-
-        turtle.goto(Position::new(staging, position.dir)).await;
+        turtle.goto(staging).await;
         warn!("devoring {i}");
-        turtle.execute(Select(i)).await;
-        turtle.execute(Place).await;
+        turtle.execute(PlaceFrom(i)).await;
 
         loop { // cancel the task of the turtle ahead so that it doesn't go wild in the depot
             if let TurtleCommandResponse::Name(name) = turtle.execute(NameFront).await.ret {
-                let name = name.string;
-                info!("rebirth: {name}");
-                match Name::from_str(&name) {
+                let label = name.string;
+                info!("rebirth: {label}");
+                match Name::from_str(&label) {
+                    // a misread label or a duplicate name would take over a turtle that is out working
+                    // booting it would still hand it the other turtle's commands
+                    Ok(name) if name.to_num() == turtle.name().to_num() => {
+                        error!("rebirth: {label} is the name of the devoring turtle, leaving it off");
+                        return;
+                    },
+                    Ok(name) if turtle.positions().reported_since(name.to_num(), started) => {
+                        error!("rebirth: {label} is still reporting, so it isn't the turtle ahead, leaving it off");
+                        return;
+                    },
                     Ok(name) => {
-                        let mut scheduler = turtle.scheduler().await;
-                        scheduler.cancel(name).await;
+                        let scheduler = turtle.scheduler();
+                        scheduler.cancel(name);
                         info!("rebirth: canceled existing");
-                        scheduler.do_on(move |turtle| tokio::spawn(async move {
+                        let launched = scheduler.do_on(move |turtle| tokio::spawn(async move {
                             depot::dump(&turtle).await;
                             depot::refuel(&turtle).await;
                             // *teleports behind you*
-                            turtle.goto(Position::new(staging - position.dir.unit(), position.dir)).await;
-                        }).abort_handle(), name).unwrap();
-                        info!("rebirth: launched move");
+                            turtle.goto(Position::new(staging.pos - staging.dir.unit(), staging.dir)).await;
+                        }).abort_handle(), name).await;
+                        match launched {
+                            Some(()) => info!("rebirth: launched move"),
+                            None => error!("rebirth: {label} isn't a known turtle"),
+                        }
                         break;
                     },
-                    Err(_) => error!("bad turtle name: {name}"),
+                    Err(_) => error!("bad turtle name: {label}"),
                 }
             } else {
                 error!("could not get name");
@@ -123,7 +316,7 @@ async fn devore(turtle: &TurtleCommander) {
         turtle.execute(CycleFront).await; // boot child
 
         loop {
-            let ret = turtle.execute(Wait(3)).await;
+            let ret = turtle.execute(WaitUntil(Condition::FrontNot("turtle".into()), MAX_WAIT)).await;
             // this won't do well with dead (energy-lacking) turtles, perhaps obtaining 
             // a new depot (lock) for every turtle is more consistent
             //
@@ -138,35 +331,129 @@ async fn devore(turtle: &TurtleCommander) {
     }
 }
 
-async fn near_valuables(turtle: &TurtleCommander, pos: Vec3, chunk: Vec3) -> Vec<Vec3> {
+async fn near_valuables(turtle: &TurtleCommander, pos: Vec3, chunk: Vec3, blocks: &BlockConfig) -> Vec<Vec3> {
     let scan = (0..(chunk*2).product()).map(|n| fill(chunk * 2, n) - chunk/2);
         
     let world = turtle.world().lock().await;
     scan.map(|n| world.get(n + pos))
         .filter_map(|f| f)
         .filter(|n| n.name != "minecraft:air")
-        .filter(|n| VALUABLE.iter().any(|v| n.name.contains(v)))
+        .filter(|n| blocks.valuable(&n.name))
         .map(|b|b.pos).collect()
 }
 
-#[instrument]
-pub async fn mine_chunk(turtle: TurtleCommander, pos: Vec3, chunk: Vec3) -> Option<()> {
+/// True if the block at the point is protected, flagging it for review
+async fn guarded(world: &SharedWorld, pos: Vec3) -> bool {
+    match world.get(pos).await {
+        Some(block) if world.protection().protects(&block.name) => {
+            world.protection().flag(block);
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Dig out a volume, other than the blocks that are to be kept or filtered out
+#[instrument(skip(blocks, kept))]
+pub async fn mine_chunk<K>(turtle: TurtleCommander, pos: Vec3, chunk: Vec3, approach: Approach, pattern: Pattern, filter: &BlockFilter, blocks: &BlockConfig, kept: K) -> Option<()>
+where K: Fn(Vec3) -> bool {
     let turtle = turtle.clone();
-    let volume = chunk.x * chunk.y * chunk.z;
+    let mut sealed = Vec::new();
+
+    for n in pattern.points(chunk).into_iter().map(|n| n + pos).filter(|n| !kept(*n)) {
+        if turtle.cancelled() {
+            return None;
+        }
+        let known = turtle.world().get(n).await;
 
-    for n in (0..volume).map(|n| fill(chunk, n) + pos) {
-        if turtle.world().get(n).await.is_some_and(|b| TRANSPARENT.contains(&b.name.as_str())) {
+        if known.as_ref().is_some_and(|b| blocks.fluid(&b.name)) {
+            if seal(&turtle, n, blocks).await.is_some() {
+                sealed.push(n);
+            }
             continue;
         }
 
-        let near = turtle.goto_adjacent(n).await?;
+        if known.is_some_and(|b| blocks.transparent(&b.name) || !filter.takes(&b.name)) {
+            continue;
+        }
+
+        if guarded(&turtle.world(), n).await {
+            continue;
+        }
+
+        let near = turtle.goto_adjacent_from(n, approach).await?;
+
+        // unknown blocks are only seen once next to them
+        if turtle.world().get(n).await.is_some_and(|b| !filter.takes(&b.name)) || guarded(&turtle.world(), n).await {
+            continue;
+        }
 
+        let dug = turtle.execute(near.dig(n)?).await;
+
+        // known air routes at half the cost of unknown space, but leave
+        // anything reported there since, such as gravel that fell in
+        if let TurtleCommandResponse::Success = dug.ret {
+            if !turtle.world().occupied(n).await {
+                turtle.world().set(Block::new("minecraft:air", n)).await;
+            }
+        }
+    }
+
+    // fillers only held fluids back while the rest was dug, buckets leave nothing behind
+    for n in sealed {
+        if turtle.cancelled() {
+            return None;
+        }
+        if !turtle.world().occupied(n).await {
+            continue;
+        }
+        let near = turtle.goto_adjacent_from(n, approach).await?;
         turtle.execute(near.dig(n)?).await;
-        
     }
     Some(())
 }
 
+/// Stop the fluid at the given position from flowing
+///
+/// Sources are picked up if there is an empty bucket on hand,
+/// everything else is plugged with a block not worth keeping
+async fn seal(turtle: &TurtleCommander, pos: Vec3, blocks: &BlockConfig) -> Option<()> {
+    let near = turtle.goto_adjacent(pos).await?;
+    let place = near.place(pos)?;
+
+    // the level, and so whether it is a source, only comes from a look at it
+    let inspect = match place {
+        Place => Inspect,
+        PlaceUp => InspectUp,
+        _ => InspectDown,
+    };
+    turtle.execute(inspect).await;
+    let source = turtle.world().fluid_source(pos).await?;
+
+    let inventory = turtle.inventory().await;
+    let find = |wanted: &dyn Fn(&str) -> bool| inventory.iter()
+        .position(|s| s.as_ref().is_some_and(|s| wanted(&s.name)));
+
+    let filler = find(&|n| blocks.useless(n));
+    let slot = if source {
+        find(&|n| n == "minecraft:bucket").or(filler)
+    } else {
+        filler
+    };
+
+    let slot = match slot {
+        Some(slot) => slot,
+        None => {
+            warn!("nothing to seal fluid at {} with", Coords(pos));
+            return None;
+        },
+    };
+
+    turtle.execute(place.from_slot(slot as u32 + 1)?).await;
+
+    Some(())
+}
+
 async fn refuel(turtle: TurtleCommander) {
     turtle.execute(Select(16)).await;
     turtle.execute(DropUp(64)).await;
@@ -174,13 +461,13 @@ async fn refuel(turtle: TurtleCommander) {
     while turtle.fuel() < limit {
         turtle.execute(SuckFront(64)).await;
         let re = turtle.execute(Refuel).await;
-        if let TurtleCommandResponse::Failure = re.ret {
+        if let TurtleCommandResponse::Failure(_) = re.ret {
             // partial refuel, good enough
             warn!("only received {} fuel", turtle.fuel());
             if turtle.fuel() > 5000 {
                 break;
             } else {
-                turtle.execute(Wait(15)).await;
+                turtle.execute(WaitUntil(Condition::ItemsAhead, MAX_WAIT)).await;
             }
         }
     }
@@ -233,6 +520,66 @@ pub fn fill(scale: Vec3, n: i32) -> Vec3 {
     )
 }
 
+/// Order to dig the blocks of a chunk in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Pattern {
+    /// Back and forth, as `fill` does
+    #[default]
+    Lawnmower,
+    /// Each layer from the outside in, and the next back out, so layers join up
+    Spiral,
+    /// Each layer's outer ring first, then its inside back and forth
+    Perimeter,
+}
+
+impl Pattern {
+    /// Every position in the volume once, in order
+    pub fn points(self, scale: Vec3) -> Vec<Vec3> {
+        if self == Pattern::Lawnmower {
+            return (0..scale.product()).map(|n| fill(scale, n)).collect();
+        }
+
+        let mut points = Vec::new();
+        for y in 0..scale.y {
+            let mut layer = match self {
+                Pattern::Spiral => spiral(scale.x, scale.z),
+                _ => perimeter(scale.x, scale.z),
+            };
+            if self == Pattern::Spiral && y % 2 == 1 {
+                layer.reverse();
+            }
+            points.extend(layer.into_iter().map(|(x, z)| Vec3::new(x, y, z)));
+        }
+        points
+    }
+}
+
+/// Rings of an x by z rectangle, outermost first
+fn spiral(x: i32, z: i32) -> Vec<(i32, i32)> {
+    let (mut x0, mut z0, mut x1, mut z1) = (0, 0, x - 1, z - 1);
+    let mut points = Vec::new();
+    while x0 <= x1 && z0 <= z1 {
+        points.extend((x0..=x1).map(|x| (x, z0)));
+        points.extend((z0 + 1..=z1).map(|z| (x1, z)));
+        if z0 < z1 {
+            points.extend((x0..x1).rev().map(|x| (x, z1)));
+        }
+        if x0 < x1 {
+            points.extend((z0 + 1..z1).rev().map(|z| (x0, z)));
+        }
+        (x0, z0, x1, z1) = (x0 + 1, z0 + 1, x1 - 1, z1 - 1);
+    }
+    points
+}
+
+/// Outer ring of an x by z rectangle, then the inside back and forth
+fn perimeter(x: i32, z: i32) -> Vec<(i32, i32)> {
+    let edge = |(a, b): &(i32, i32)| *a == 0 || *b == 0 || *a == x - 1 || *b == z - 1;
+    let ring = spiral(x, z).into_iter().take_while(edge);
+    let inside = Vec3::new(x - 2, 1, z - 2).map(|n| n.max(0));
+    ring.chain((0..inside.product()).map(|n| fill(inside, n)).map(|p| (p.x + 1, p.z + 1))).collect()
+}
+
 /// Looks at all the blocks around the given pos
 /// destructive
 async fn observe(turtle: TurtleCommander, pos: Vec3) -> Option<()> {
@@ -261,26 +608,84 @@ pub struct Mine {
     pos: Vec3,
     chunk: Vec3,
     #[serde(skip_deserializing)]
-    miners: usize, // Default is false
+    miners: Arc<AtomicUsize>,
+    /// Chunks to mine, None to never stop
+    #[serde(default)]
+    length: Option<u32>,
+    /// Chunks mined so far
+    #[serde(default)]
+    mined: Arc<AtomicU32>,
+    /// Depot to unload at, None for the nearest
+    #[serde(default)]
+    output: Option<Vec3>,
 }
 
 impl Mine {
-    pub fn new(pos: Vec3, chunk: Vec3) -> Self { Self { pos, chunk, miners: 0 } }
+    pub fn new(pos: Vec3, chunk: Vec3, length: u32) -> Self {
+        Self { pos, chunk, miners: Default::default(), length: Some(length), mined: Default::default(), output: None }
+    }
+
+    /// Unload at the depot docked at this point while it is registered
+    pub fn output(mut self, depot: Option<Vec3>) -> Self {
+        self.output = depot;
+        self
+    }
+
+    /// Mine that keeps going until cancelled
+    pub fn endless(pos: Vec3, chunk: Vec3) -> Self {
+        Self { length: None, ..Self::new(pos, chunk, 0) }
+    }
+}
+
+/// A claim on one of a task's miner slots, given back when dropped
+///
+/// Aborted tasks drop their future without running the rest of it,
+/// so this can't be a decrement at the end
+pub(crate) struct MinerSlot(pub(crate) Arc<AtomicUsize>);
+
+impl Drop for MinerSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
 #[serde]
 impl Task for Mine {
+    fn role(&self) -> Role {
+        Role::Miner
+    }
+
     fn run(&mut self,turtle:TurtleCommander) -> AbortHandle {
-        self.miners += 1;
+        // claimed in poll
+        let slot = MinerSlot(self.miners.clone());
         let frozen = self.clone();
+        let turtle = turtle.with_output(frozen.output);
         tokio::spawn(async move {
-            mine(turtle,frozen.pos, frozen.chunk).await.unwrap();
+            let _slot = slot;
+            if mine(turtle.clone(),frozen.pos, frozen.chunk, frozen.length, &frozen.mined).await.is_none() {
+                match turtle.cancelled() {
+                    true => info!("mining at {} cancelled", Coords(frozen.pos)),
+                    false => error!("mining at {} failed", Coords(frozen.pos)),
+                }
+            }
         }).abort_handle()
-        // TODO: mutability after spawn
     }
 
     fn poll(&mut self) -> TaskState {
-        if self.miners < 1 {
+        let done = self.length.is_some_and(|l| self.mined.load(Ordering::SeqCst) >= l);
+        if done && self.miners.load(Ordering::Acquire) == 0 {
+            return TaskState::Complete;
+        }
+
+        let only = self.miners.fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+            if n < 1 {
+                Some(n+1)
+            }else {
+                None
+            }
+        }).is_ok();
+
+        if only {
             return TaskState::Ready(Position::new(self.pos, Direction::North));
         }
         TaskState::Waiting
@@ -289,6 +694,57 @@ impl Task for Mine {
 
 const MAX_MINERS: usize = 42;
 
+/// Fuel burned per block dug, counting the moves between blocks and to the depot
+const FUEL_PER_BLOCK: usize = 4;
+/// Blocks a slot holds on average, mixed junk rarely fills whole stacks
+const BLOCKS_PER_SLOT: usize = 16;
+/// Widest chunk a quarry is split into
+const MAX_CHUNK_SIDE: i32 = 16;
+
+fn default_chunk() -> Vec3 {
+    Vec3::new(4,4,4)
+}
+
+/// Largest side up to `max` that leaves the least of `size` undug
+///
+/// Sides under half of `max` are passed over, or a prime size would be split
+/// into single blocks, each with its own trip to a depot
+fn fit(size: i32, max: i32) -> i32 {
+    let max = max.max(1);
+    // ties go to the last, and so largest, side
+    ((max + 1) / 2..=max).max_by_key(|s| size / s * s).unwrap()
+}
+
+/// Check that a region is somewhere a turtle can get to and dig, before a task is made for it
+///
+/// Fails if the region is far from every depot without confirmation, or is already known
+/// to be nothing but unbreakable blocks. Returns anything worth warning about otherwise
+///
+/// `upper` is exclusive, as for `Quarry::new`
+pub async fn check_region(lower: Vec3, upper: Vec3, depots: &depot::Depots, world: &SharedWorld, limits: &Limits, confirm: bool) -> anyhow::Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    let limit = limits.depot_distance;
+    match depots.distance(lower, upper).await {
+        None => warnings.push("no depots are registered".to_string()),
+        Some(distance) if distance > limit && !confirm => {
+            return Err(anyhow!("{} is {distance} blocks from the nearest depot, confirm to proceed", Coords(lower)));
+        },
+        Some(distance) if distance > limit => {
+            warnings.push(format!("{} is {distance} blocks from the nearest depot", Coords(lower)));
+        },
+        Some(_) => {},
+    }
+
+    if world.all_known(lower, upper, |b| UNBREAKABLE.contains(&b.name.as_str())).await {
+        return Err(anyhow!("{} to {} is all unbreakable", Coords(lower), Coords(upper)));
+    }
+
+    for warning in &warnings {
+        warn!("{warning}");
+    }
+    Ok(warnings)
+}
+
 #[derive(Serialize, Deserialize,Clone)]
 pub struct Quarry {
     pos: Vec3,
@@ -296,67 +752,266 @@ pub struct Quarry {
     #[serde(skip_deserializing)]
     miners: Arc<AtomicUsize>,
     progress: ChunkedTask,
+    /// Size of the pieces handed out to turtles
+    #[serde(default = "default_chunk")]
+    chunk: Vec3,
+    /// Valuables left by chases, dug once the chunks are taken
+    #[serde(rename = "unchased", default)]
+    veins: Arc<std::sync::Mutex<Vec<Vein>>>,
+    /// Valuables left by saves from before chases kept their budget
+    #[serde(rename = "veins", default, skip_serializing)]
+    legacy_veins: Vec<Vec3>,
+    #[serde(default)]
+    full: WhenFull,
+    #[serde(default)]
+    keep: Keep,
+    /// Side to dig blocks from
+    #[serde(default)]
+    approach: Approach,
+    /// Order to dig each chunk in
+    #[serde(default)]
+    pattern: Pattern,
+    #[serde(default)]
+    filter: BlockFilter,
+    /// Depot to unload at, None for the nearest
+    #[serde(default)]
+    output: Option<Vec3>,
+    #[serde(default)]
+    timing: Arc<std::sync::Mutex<Timing>>,
+    /// Only simulate the work, logging a report once complete
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(skip)]
+    report: Arc<DryRun>,
 }
 
 impl Quarry {
-    pub fn new(lower: Vec3, upper: Vec3) -> Self {
+    /// Fails if the area is inverted, or larger than the configured limit without confirmation
+    pub fn new(lower: Vec3, upper: Vec3, limits: &Limits, confirm: bool) -> anyhow::Result<Self> {
         let size = upper - lower;
 
-        let max_chunk = Vec3::new(4,4,4);
-        let chunks = size.component_div(&max_chunk);
+        if size.iter().any(|n| *n < 0) {
+            return Err(anyhow!("quarry corners are swapped: {} to {}", Coords(lower), Coords(upper)));
+        }
+
+        // corners from the far ends of the world would overflow an i32
+        let volume = size.iter().try_fold(1i64, |v, n| v.checked_mul(*n as i64))
+            .ok_or_else(|| anyhow!("quarry from {} to {} is too large", Coords(lower), Coords(upper)))?;
+        let limit = limits.quarry_volume;
+        if !confirm && volume > limit {
+            return Err(anyhow!("quarry of {volume} blocks exceeds {limit}, confirm to proceed"));
+        }
+
+        let chunks = size.component_div(&default_chunk());
+        let chunks = chunks.iter().try_fold(1i32, |v, n| v.checked_mul(*n))
+            .ok_or_else(|| anyhow!("quarry of {volume} blocks has too many chunks to keep track of"))?;
 
-        Self { 
+        Ok(Self { 
             pos: lower, 
             size, 
             miners: Arc::new(AtomicUsize::new(0)),
-            progress: ChunkedTask::new(chunks.product())
+            progress: ChunkedTask::new(chunks),
+            chunk: default_chunk(),
+            veins: Default::default(),
+            legacy_veins: Vec::new(),
+            full: Default::default(),
+            keep: Default::default(),
+            approach: Default::default(),
+            pattern: Default::default(),
+            filter: Default::default(),
+            output: None,
+            timing: Default::default(),
+            dry_run: false,
+            report: Default::default(),
+        })
+    }
+
+    /// Simulate against a copy of the world instead of running the turtles
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Unload at the depot docked at this point while it is registered
+    pub fn output(mut self, depot: Option<Vec3>) -> Self {
+        self.output = depot;
+        self
+    }
+
+    pub fn when_full(mut self, full: WhenFull) -> Self {
+        self.full = full;
+        self
+    }
+
+    /// Leave part of the volume standing, fails on pillars too close to dig between
+    pub fn keep(mut self, keep: Keep) -> anyhow::Result<Self> {
+        if let Keep::Pillars(spacing) = keep {
+            if spacing < 2 {
+                return Err(anyhow!("pillars every {spacing} blocks leave nothing to dig"));
+            }
         }
+        self.keep = keep;
+        Ok(self)
+    }
+
+    pub fn approach(mut self, approach: Approach) -> Self {
+        self.approach = approach;
+        self
+    }
+
+    pub fn pattern(mut self, pattern: Pattern) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    /// Only dig some blocks, leaving the rest and any veins alone
+    pub fn filter(mut self, filter: BlockFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Chunk that can be dug out between two docks, 4 high like the default
+    pub fn chunk_for(fuel_limit: usize, slots: usize) -> Vec3 {
+        let blocks = (fuel_limit / FUEL_PER_BLOCK).min(slots * BLOCKS_PER_SLOT);
+        let side = ((blocks / 4) as f64).sqrt() as i32;
+        let side = side.clamp(2, MAX_CHUNK_SIDE);
+        Vec3::new(side, 4, side)
+    }
+
+    /// Split the quarry into chunks no larger than this, fitted to its size
+    ///
+    /// Only whole chunks are dug, so a side that divides the quarry evenly wins out
+    pub fn chunk_size(mut self, max: Vec3) -> Self {
+        let max = max.map(|n| n.clamp(1, MAX_CHUNK_SIDE));
+        self.chunk = self.size.zip_map(&max, fit);
+        self.progress = ChunkedTask::new(self.size.component_div(&self.chunk).product());
+        self
+    }
+
+    /// Queue what a chase left behind
+    fn leave(&self, vein: Vein) {
+        if !vein.blocks.is_empty() {
+            self.veins.lock().unwrap().push(vein);
+        }
+    }
+
+    /// True if the block is to be left standing
+    fn kept(&self, block: Vec3) -> bool {
+        self.keep.keeps(block - self.pos, self.dug())
+    }
+
+    /// Size of the part dug, as only whole chunks are
+    fn dug(&self) -> Vec3 {
+        self.size.component_div(&self.chunk).component_mul(&self.chunk)
     }
 
-    pub fn chunk(pos: Vec3) -> Self {
-        let base = pos - pos.map(|n| n%16);
-        Self::new(base, base+Vec3::new(16,16,16))
+    /// What turtles are kept from digging through on the way, None if everything goes
+    fn avoid(&self) -> Option<Avoid> {
+        if self.keep == Keep::Nothing {
+            return None;
+        }
+        let (keep, pos, dug) = (self.keep, self.pos, self.dug());
+        Some(Arc::new(move |block| keep.keeps(block - pos, dug)))
     }
 }
 
 #[serde]
 impl Task for Quarry {
+    fn role(&self) -> Role {
+        Role::Miner
+    }
+
+    /// Background work, any other task can have the turtles first
+    fn priority(&self) -> i8 {
+        -1
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
     #[instrument(skip(self))]
     fn run(&mut self,turtle:TurtleCommander) -> AbortHandle {
+        // claimed in poll
+        let slot = MinerSlot(self.miners.clone());
         let owned = self.clone();
+        let turtle = turtle.with_output(owned.output).avoiding(owned.avoid());
         tokio::spawn(async move {
+            let _slot = slot;
+            let turtle = match owned.dry_run {
+                true => turtle.simulated(owned.report.clone()).await,
+                false => turtle,
+            };
+            owned.timing.lock().unwrap().start();
             let chunk = owned.progress.next_chunk();
 
             if let None = chunk {
-                error!("scheduled quarry out of range");
+                // every chunk is taken, so this is for a vein left behind
+                let vein = owned.veins.lock().unwrap().pop();
+                match vein {
+                    Some(vein) => match chase(&turtle, vein.clone(), owned.chunk.product(), &turtle.world().block_config(), |b| owned.kept(b)).await {
+                        Some(left) => owned.leave(left),
+                        None if turtle.cancelled() => {
+                            info!("chasing vein near {} cancelled, returning it", Coords(vein.center));
+                            owned.veins.lock().unwrap().push(vein);
+                        },
+                        // one that can't be reached would hold the quarry open forever
+                        None => error!("chasing vein near {} failed, leaving {} valuables", Coords(vein.center), vein.blocks.len()),
+                    },
+                    None => error!("scheduled quarry out of range"),
+                }
                 return;
             }
             let mut chunk = chunk.unwrap();
 
             info!("#{} doing chunk {}", turtle.name().to_str(), *chunk);
 
-            let max_chunk = Vec3::new(4,4,4);
+            let max_chunk = owned.chunk;
             let e = owned.size.component_div(&max_chunk);
 
             let rel_pos = fill(e, *chunk).component_mul(&max_chunk);
             let abs_pos = rel_pos
                 + owned.pos;
-            if let None = mine_chunk_and_sweep(turtle, abs_pos, max_chunk).await {
-                error!("mining at {abs_pos} failed");
-                chunk.cancel();
-            } else {
-                chunk.finish();
+            match mine_chunk_and_sweep(turtle.clone(), abs_pos, max_chunk, owned.full, owned.approach, owned.pattern, &owned.filter, &turtle.world().block_config(), |b| owned.kept(b)).await {
+                Some(left) => {
+                    owned.leave(left);
+                    chunk.finish();
+                    owned.timing.lock().unwrap().step();
+                },
+                None => {
+                    match turtle.cancelled() {
+                        true => info!("mining at {} cancelled, returning the chunk", Coords(abs_pos)),
+                        false => error!("mining at {} failed", Coords(abs_pos)),
+                    }
+                    chunk.cancel();
+                },
             }
-            owned.miners.fetch_sub(1, Ordering::AcqRel);
         }).abort_handle()
     }
 
+    fn progress(&self) -> Option<Progress> {
+        let timing = *self.timing.lock().unwrap();
+        Some(Progress::new(self.progress.finished() as usize, Some(self.progress.parts() as usize), timing))
+    }
+
     fn poll(&mut self) -> TaskState {
-        if self.progress.done() {
+        let legacy = std::mem::take(&mut self.legacy_veins).into_iter()
+            .map(|b| Vein { blocks: vec![b], center: b, budget: MAX_CHASE });
+        self.veins.lock().unwrap().extend(legacy);
+        let vein = self.veins.lock().unwrap().last().map(|v| v.blocks[0]);
+
+        if self.progress.done() && vein.is_none() {
+            // chasers still out may leave more behind
+            if self.miners.load(Ordering::Acquire) > 0 {
+                return TaskState::Waiting;
+            }
+            if self.dry_run {
+                info!("dry run of quarry at {}: {}", Coords(self.pos), self.report);
+            }
             return TaskState::Complete;
         }
 
-        if self.progress.allocated() {
+        if self.progress.allocated() && vein.is_none() {
             return TaskState::Waiting;
         }
 
@@ -369,20 +1024,40 @@ impl Task for Quarry {
         }).is_ok();
 
         if only {
-            // This is approximate as we have to go to a depot anyway
-            return TaskState::Ready(Position::new(self.pos, Direction::North));
+            let pos = match vein {
+                Some(vein) if self.progress.allocated() => vein,
+                // This is approximate as we have to go to a depot anyway
+                _ => self.pos,
+            };
+            let needs = Needs { fuel: Some(fuel_needed(self.chunk.product())), role: None };
+            return TaskState::ReadyFor(Position::new(pos, Direction::North), needs);
         }
         TaskState::Waiting
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChunkedTask {
+    /// Highest chunk finished while none were waiting to be redone, only read from older saves
+    #[serde(default, skip_serializing)]
     confirmed: Arc<AtomicI32>,
     max: i32,
     #[serde(skip_deserializing)]
-    head: Arc<AtomicI32>, // highest active chunk
-    #[serde(skip, default = "channel")]
+    head: Arc<AtomicI32>, // next chunk to hand out
+    /// Chunks finished, in any order
+    #[serde(default)]
+    finished: Arc<AtomicI32>,
+    /// Every chunk below this is finished, which is where a restart picks back up
+    #[serde(default)]
+    through: Arc<AtomicI32>,
+    /// Chunks finished past `through`, so it can move up once the gaps below them are
+    #[serde(skip)]
+    ahead: Arc<std::sync::Mutex<BTreeSet<i32>>>,
+    /// False for a task just loaded, until it has been resumed
+    #[serde(skip)]
+    live: Arc<AtomicBool>,
+    /// Chunks given up on, to hand out again before moving the head on
+    #[serde(default = "channel", with = "canceled")]
     canceled: Option<(Sender<i32>, Receiver<i32>)>,
 }
 
@@ -390,11 +1065,44 @@ fn channel() -> Option<(Sender<i32>, Receiver<i32>)> {
     Some(crossbeam::channel::unbounded())
 }
 
+/// Saves the queue of cancelled chunks as a list, so gaps are still filled after a restart
+mod canceled {
+    use crossbeam::channel::{Sender, Receiver};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(channel: &Option<(Sender<i32>, Receiver<i32>)>, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut chunks = Vec::new();
+        if let Some((send, recv)) = channel {
+            // the task carries on after a save, so they go back in
+            chunks.extend(recv.try_iter());
+            for chunk in &chunks {
+                send.send(*chunk).unwrap();
+            }
+        }
+        chunks.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<(Sender<i32>, Receiver<i32>)>, D::Error> {
+        let chunks = Vec::<i32>::deserialize(deserializer)?;
+        let channel = super::channel();
+        if let Some((send, _)) = &channel {
+            for chunk in chunks {
+                send.send(chunk).unwrap();
+            }
+        }
+        Ok(channel)
+    }
+}
+
 impl Default for ChunkedTask {
     fn default() -> Self {
         Self {
             confirmed: Default::default(),
             head: Default::default(),
+            finished: Default::default(),
+            through: Default::default(),
+            ahead: Default::default(),
+            live: Arc::new(AtomicBool::new(true)),
             canceled: Some(crossbeam::channel::unbounded()),
             max: 0,
         } 
@@ -410,28 +1118,61 @@ impl ChunkedTask {
     }
 
     pub fn done(&self) -> bool {
-        let backstop = self.confirmed.load(Ordering::SeqCst);
-        backstop + 1 >= self.max
+        self.finished() >= self.max
+    }
+
+    pub fn parts(&self) -> i32 {
+        self.max
+    }
+
+    pub fn finished(&self) -> i32 {
+        self.resume();
+        self.finished.load(Ordering::SeqCst)
     }
 
     pub fn allocated(&self) -> bool {
+        self.resume();
         let front = self.head.load(Ordering::SeqCst);
-        front + 1 >= self.max && self.canceled.clone().unwrap().0.is_empty()
+        front >= self.max && self.canceled.clone().unwrap().0.is_empty()
     }
 
-    pub fn next_chunk(&self) -> Option<ChunkedTaskGuard> {
-        let cancelled = self.canceled.clone().unwrap().1;
+    /// Pick a loaded task back up from the first chunk that wasn't finished
+    ///
+    /// Chunks past it that were handed out or finished when it was saved are done
+    /// again, as nothing says how far the turtles on them got before the restart
+    fn resume(&self) {
+        if self.live.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut ahead = self.ahead.lock().unwrap();
+        if self.live.load(Ordering::SeqCst) {
+            return;
+        }
 
-        if let Some(chunk) = cancelled.try_recv().ok() {
-            return Some(ChunkedTaskGuard::with_task(self.clone(), chunk));
+        let (_, canceled) = self.canceled.as_ref().unwrap();
+        let mut through = self.through.load(Ordering::SeqCst);
+        // saves from before `through` only kept the highest chunk finished, with gaps below it canceled
+        let legacy = self.confirmed.swap(0, Ordering::SeqCst);
+        if legacy > 0 {
+            through = through.max(legacy + 1);
+        }
+        if let Some(gap) = canceled.try_iter().min() {
+            through = through.min(gap);
         }
 
-        loop { // update head (from a save)
-            let minimum = self.confirmed.load(Ordering::SeqCst);
-            let head = self.head.load(Ordering::SeqCst);
-            if let Ok(_) = self.head.compare_exchange(head, minimum.max(head), Ordering::AcqRel, Ordering::SeqCst) {
-                break;
-            }
+        // everything past it comes from the head again
+        ahead.clear();
+        self.through.store(through, Ordering::SeqCst);
+        self.finished.store(through, Ordering::SeqCst);
+        self.head.store(through, Ordering::SeqCst);
+        self.live.store(true, Ordering::SeqCst);
+    }
+
+    pub fn next_chunk(&self) -> Option<ChunkedTaskGuard> {
+        self.resume();
+
+        if let Ok(chunk) = self.canceled.clone().unwrap().1.try_recv() {
+            return Some(ChunkedTaskGuard::with_task(self.clone(), chunk));
         }
 
         let head = self.head.fetch_add(1, Ordering::AcqRel);
@@ -444,14 +1185,14 @@ impl ChunkedTask {
     }
 
     fn mark_done(&self, chunk: i32) {
-        if self.clone().canceled.unwrap().1.len() == 0 {
-            loop {
-                let curr = self.confirmed.load(Ordering::SeqCst);
-                if let Ok(_) = self.confirmed.compare_exchange(curr, curr.max(chunk), Ordering::AcqRel, Ordering::SeqCst) {
-                    break;
-                }
-            }
+        self.finished.fetch_add(1, Ordering::AcqRel);
+        let mut ahead = self.ahead.lock().unwrap();
+        ahead.insert(chunk);
+        let mut through = self.through.load(Ordering::SeqCst);
+        while ahead.remove(&through) {
+            through += 1;
         }
+        self.through.store(through, Ordering::SeqCst);
     }
 
     fn cancel(&self, chunk: i32) {
@@ -516,6 +1257,10 @@ impl Deref for ChunkedTaskGuard {
 mod tests {
     use super::*;
 
+    fn quarry(lower: Vec3, upper: Vec3) -> Quarry {
+        Quarry::new(lower, upper, &Limits::default(), false).unwrap()
+    }
+
     #[tokio::test]
     async fn guard() {
         let tracker = ChunkedTask::new(5);
@@ -549,8 +1294,268 @@ mod tests {
         d.finish();
         e.finish();
         assert!(tracker.done());
+        assert_eq!(tracker.finished(), 5);
         assert!(tracker.allocated());
     }
+
+    #[test]
+    fn cancelled_saved() {
+        let tracker = ChunkedTask::new(5);
+        let chunks: Vec<_> = (0..3).map(|_| tracker.next_chunk().unwrap()).collect();
+        let [zero, one, two]: [ChunkedTaskGuard; 3] = chunks.try_into().ok().unwrap();
+        zero.finish();
+        one.finish();
+        two.cancel();
+
+        let saved = ron::ser::to_string(&tracker).unwrap();
+        assert!(saved.contains("canceled:[2]"), "{saved}");
+        let loaded: ChunkedTask = ron::de::from_str(&saved).unwrap();
+        let redo = loaded.next_chunk().unwrap();
+        assert_eq!(*redo, 2);
+        // only then does the head move, past what was finished
+        let next = loaded.next_chunk().unwrap();
+        assert_eq!(*next, 3);
+        redo.finish();
+        next.finish();
+        loaded.next_chunk().unwrap().finish();
+        assert!(loaded.next_chunk().is_none());
+        assert!(loaded.done());
+        assert_eq!(loaded.finished(), 5);
+
+        // saving doesn't take them from the running task
+        assert_eq!(*tracker.next_chunk().unwrap(), 2);
+    }
+
+    #[test]
+    fn resume() {
+        let tracker = ChunkedTask::new(4);
+        let chunks: Vec<_> = (0..3).map(|_| tracker.next_chunk().unwrap()).collect();
+        let [zero, one, two]: [ChunkedTaskGuard; 3] = chunks.try_into().ok().unwrap();
+        one.finish();
+        two.finish();
+        assert_eq!(tracker.finished(), 2);
+
+        // zero was still out when it was saved, so everything from it is done again
+        let saved = ron::ser::to_string(&tracker).unwrap();
+        let loaded: ChunkedTask = ron::de::from_str(&saved).unwrap();
+        assert_eq!(loaded.finished(), 0);
+        let redo: Vec<_> = (0..4).map(|_| loaded.next_chunk().unwrap()).collect();
+        assert_eq!(redo.iter().map(|c| c.id()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert!(loaded.next_chunk().is_none());
+        redo.into_iter().for_each(ChunkedTaskGuard::finish);
+        assert!(loaded.done());
+        assert_eq!(loaded.finished(), 4);
+
+        // once it's in, the ones past it are kept
+        zero.finish();
+        let saved = ron::ser::to_string(&tracker).unwrap();
+        let loaded: ChunkedTask = ron::de::from_str(&saved).unwrap();
+        assert_eq!(loaded.finished(), 3);
+        assert!(!loaded.allocated());
+        assert_eq!(*loaded.next_chunk().unwrap(), 3);
+        assert!(loaded.next_chunk().is_none());
+    }
+
+    #[test]
+    fn resume_legacy() {
+        // saves from before `through` only have the highest chunk finished
+        let loaded: ChunkedTask = ron::de::from_str("(confirmed:2,max:5,finished:3,canceled:[])").unwrap();
+        assert_eq!(loaded.finished(), 3);
+        assert_eq!(*loaded.next_chunk().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn region() {
+        let world = SharedWorld::new();
+        let depots = depot::Depots::from_vec(Vec::new());
+        let lower = Vec3::new(0, -64, 0);
+        let upper = Vec3::new(2, -63, 2);
+
+        // nowhere to unload is worth mentioning, not refusing
+        assert_eq!(check_region(lower, upper, &depots, &world, &Limits::default(), false).await.unwrap().len(), 1);
+
+        depots.add(depot::Depot::new(Position::new(Vec3::new(2000, 64, 0), Direction::North))).await;
+        assert!(check_region(lower, upper, &depots, &world, &Limits::default(), false).await.is_err());
+        assert_eq!(check_region(lower, upper, &depots, &world, &Limits::default(), true).await.unwrap().len(), 1);
+
+        depots.add(depot::Depot::new(Position::new(Vec3::new(0, 64, 0), Direction::North))).await;
+        assert!(check_region(lower, upper, &depots, &world, &Limits::default(), false).await.unwrap().is_empty());
+
+        for x in 0..2 {
+            for z in 0..2 {
+                world.set(Block::new("minecraft:bedrock", Vec3::new(x, -64, z))).await;
+            }
+        }
+        assert!(check_region(lower, upper, &depots, &world, &Limits::default(), false).await.is_err());
+        // the upper corner is outside, like a quarry's
+        world.set(Block::new("minecraft:stone", upper)).await;
+        assert!(check_region(lower, upper, &depots, &world, &Limits::default(), false).await.is_err());
+        world.set(Block::new("minecraft:stone", Vec3::new(1, -64, 1))).await;
+        assert!(check_region(lower, upper, &depots, &world, &Limits::default(), false).await.is_ok());
+        // a single block is the box up to the next one
+        assert!(check_region(lower, lower + Vec3::repeat(1), &depots, &world, &Limits::default(), false).await.is_err());
+    }
+
+    #[test]
+    fn remove_resumes() {
+        let remove = Remove::new(Vec3::zeros(), "ore".into(), &Limits::default(), false).unwrap();
+        remove.pending.lock().unwrap().push(Vec3::zeros());
+        remove.settle(Vec3::zeros(), true);
+
+        let saved = ron::ser::to_string(&remove).unwrap();
+        let loaded: Remove = ron::de::from_str(&saved).unwrap();
+        assert_eq!(loaded.pending.lock().unwrap().len(), 6);
+        assert!(loaded.visited.lock().unwrap().contains(&Vec3::zeros()));
+
+        // the way back isn't queued again
+        let next = loaded.next().unwrap();
+        loaded.settle(next, true);
+        assert_eq!(loaded.pending.lock().unwrap().len(), 10);
+        assert!(!loaded.pending.lock().unwrap().contains(&Vec3::zeros()));
+    }
+
+    #[test]
+    fn single_chunk() {
+        let tracker = ChunkedTask::new(1);
+        assert!(!tracker.done());
+        tracker.next_chunk().unwrap().finish();
+        assert!(tracker.done());
+    }
+
+    #[test]
+    fn allocate_last() {
+        let tracker = ChunkedTask::new(2);
+        let a = tracker.next_chunk().unwrap();
+        assert!(!tracker.allocated());
+        let b = tracker.next_chunk().unwrap();
+        assert!(tracker.allocated());
+        a.finish();
+        b.finish();
+        assert!(tracker.done());
+    }
+
+    #[tokio::test]
+    async fn single_miner() {
+        let mut task = Mine::endless(Vec3::zeros(), Vec3::new(4, 4, 4));
+        assert!(matches!(task.poll(), TaskState::Ready(_)));
+        assert!(matches!(task.poll(), TaskState::Waiting));
+
+        // an aborted run still gives its slot back
+        let slot = MinerSlot(task.miners.clone());
+        let run = tokio::spawn(async move {
+            let _slot = slot;
+            std::future::pending::<()>().await
+        });
+        run.abort();
+        assert!(run.await.unwrap_err().is_cancelled());
+        assert!(matches!(task.poll(), TaskState::Ready(_)));
+    }
+
+    #[test]
+    fn mine_length() {
+        let mut task = Mine::new(Vec3::zeros(), Vec3::new(4, 4, 4), 2);
+        task.mined.store(2, Ordering::SeqCst);
+        assert!(matches!(task.poll(), TaskState::Complete));
+
+        let mut task = Mine::endless(Vec3::zeros(), Vec3::new(4, 4, 4));
+        task.mined.store(1000, Ordering::SeqCst);
+        assert!(matches!(task.poll(), TaskState::Ready(_)));
+    }
+
+    #[test]
+    fn patterns() {
+        for pattern in [Pattern::Lawnmower, Pattern::Spiral, Pattern::Perimeter] {
+            for scale in [Vec3::new(4, 4, 4), Vec3::new(5, 3, 2), Vec3::new(1, 2, 7), Vec3::new(6, 1, 5)] {
+                let points = pattern.points(scale);
+                let mut unique = points.clone();
+                unique.sort_by_key(|p| (p.x, p.y, p.z));
+                unique.dedup();
+                assert_eq!(points.len(), scale.product() as usize, "{pattern:?} {scale}");
+                assert_eq!(unique.len(), points.len(), "{pattern:?} {scale}");
+                assert!(points.iter().all(|p| p.iter().zip(scale.iter()).all(|(n, max)| (0..*max).contains(n))));
+            }
+        }
+
+        // spiral layers meet end to start
+        let spiral = Pattern::Spiral.points(Vec3::new(5, 2, 4));
+        for pair in spiral.windows(2) {
+            assert_eq!((pair[1] - pair[0]).abs().sum(), 1);
+        }
+    }
+
+    #[test]
+    fn chunk_size() {
+        // fuel and inventory each bound the chunk
+        assert_eq!(Quarry::chunk_for(100_000, 16), Vec3::new(8, 4, 8));
+        assert_eq!(Quarry::chunk_for(1000, 16), Vec3::new(7, 4, 7));
+        assert_eq!(Quarry::chunk_for(0, 16), Vec3::new(2, 4, 2));
+
+        let origin = Vec3::zeros();
+        let sized = quarry(origin, Vec3::new(16, 16, 16)).chunk_size(Vec3::new(6, 4, 6));
+        assert_eq!(sized.chunk, Vec3::new(4, 4, 4));
+        let sized = quarry(origin, Vec3::new(24, 8, 24)).chunk_size(Vec3::new(8, 4, 8));
+        assert_eq!(sized.chunk, Vec3::new(8, 4, 8));
+        assert!(!sized.progress.done());
+
+        // nothing divides a prime size, so it keeps to near the largest chunk
+        let sized = quarry(origin, Vec3::new(13, 13, 13)).chunk_size(Vec3::new(4, 4, 4));
+        assert_eq!(sized.chunk, Vec3::new(4, 4, 4));
+        assert_eq!(fit(13, 8), 6);
+        assert_eq!(fit(16, 6), 4);
+        assert_eq!(fit(5, 1), 1);
+    }
+
+    #[test]
+    fn filter() {
+        let leaves = BlockFilter::from_lists(Some("leaves, "), None).unwrap();
+        assert!(leaves.takes("minecraft:oak_leaves"));
+        assert!(!leaves.takes("minecraft:stone"));
+
+        let ores = BlockFilter::from_lists(Some(""), Some("_ore")).unwrap();
+        assert!(!ores.takes("minecraft:iron_ore"));
+        assert!(ores.takes("minecraft:stone"));
+
+        assert_eq!(BlockFilter::from_lists(None, Some(" ")).unwrap(), BlockFilter::All);
+        assert!(BlockFilter::from_lists(Some("dirt"), Some("stone")).is_err());
+    }
+
+    #[test]
+    fn keep() {
+        let size = Vec3::new(8, 4, 8);
+        assert!(!Keep::Nothing.keeps(Vec3::zeros(), size));
+
+        assert!(Keep::Shell.keeps(Vec3::zeros(), size));
+        assert!(!Keep::Shell.keeps(Vec3::new(3, 3, 3), size));
+        assert!(Keep::Shell.keeps(Vec3::new(7, 1, 2), size));
+        assert!(!Keep::Shell.keeps(Vec3::new(3, 2, 6), size));
+        assert!(!Keep::Shell.keeps(Vec3::new(8, 1, 1), size));
+
+        assert!(Keep::Pillars(4).keeps(Vec3::new(4, 2, 0), size));
+        assert!(!Keep::Pillars(4).keeps(Vec3::new(4, 2, 1), size));
+        assert!(!Keep::Pillars(4).keeps(Vec3::new(-4, 2, 0), size));
+
+        let walled = quarry(Vec3::new(10, 0, 10), Vec3::new(18, 4, 18));
+        assert!(walled.clone().keep(Keep::Pillars(1)).is_err());
+        let walled = walled.keep(Keep::Shell).unwrap();
+        assert!(walled.kept(Vec3::new(10, 1, 12)));
+        assert!(!walled.kept(Vec3::new(12, 1, 12)));
+    }
+
+    #[test]
+    fn quarry_cap() {
+        let origin = Vec3::zeros();
+        let huge = Vec3::new(1000, 100, 1000);
+        assert!(Quarry::new(origin, Vec3::new(16,16,16), &Limits::default(), false).is_ok());
+        assert!(Quarry::new(origin, huge, &Limits::default(), false).is_err());
+        assert!(Quarry::new(origin, huge, &Limits::default(), true).is_ok());
+        assert!(Quarry::new(huge, origin, &Limits::default(), true).is_err());
+
+        // the far corners of the world don't fit in an i32, or even an i64
+        let edge = Vec3::new(30_000_000, 0, 30_000_000);
+        assert!(Quarry::new(-edge, edge + Vec3::new(0, 4096, 0), &Limits::default(), false).is_err());
+        assert!(Quarry::new(-edge, edge + Vec3::new(0, 4096, 0), &Limits::default(), true).is_err());
+        assert!(Quarry::new(origin, Vec3::new(2000, 2000, 2000), &Limits::default(), false).is_err());
+    }
 }
 
 #[derive(Serialize, Deserialize,Clone)]
@@ -560,58 +1565,91 @@ pub struct Remove {
     #[serde(skip_deserializing)]
     miners: Arc<AtomicUsize>,
     done: Arc<AtomicBool>,
-    #[serde(skip)] // TODO: not this
-    pending: Arc<Mutex<Vec<Vec3>>>,
+    /// Places left to look at, the edge of the flood fill
+    #[serde(default)]
+    pending: Arc<std::sync::Mutex<Vec<Vec3>>>,
+    /// Places dug, or looked at and left, which are never queued again
+    #[serde(default)]
+    visited: Arc<std::sync::Mutex<HashSet<Vec3>>>,
+    /// Blocks dug so far
+    #[serde(default)]
+    removed: Arc<AtomicUsize>,
+    /// None if confirmed to be unbounded
+    #[serde(default)]
+    limit: Option<usize>,
+    #[serde(default)]
+    timing: Arc<std::sync::Mutex<Timing>>,
+    /// Only simulate the work, logging a report once complete
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(skip)]
+    report: Arc<DryRun>,
 }
 
 impl Remove {
-    pub fn new(start: Vec3, block: String) -> 
-        Self { 
-            Self { 
-                start, block, miners:Default::default(), done: Default::default(), pending: Default::default(),
-            } 
+    /// Fails on an empty block name, which would match everything
+    pub fn new(start: Vec3, block: String, limits: &Limits, confirm: bool) -> anyhow::Result<Self> {
+        if block.trim().is_empty() {
+            return Err(anyhow!("refusing to remove every block"));
         }
 
+        Ok(Self { 
+            start, block, miners:Default::default(), done: Default::default(), pending: Default::default(),
+            visited: Default::default(),
+            removed: Default::default(),
+            limit: if confirm { None } else { Some(limits.remove_blocks) },
+            timing: Default::default(),
+            dry_run: false,
+            report: Default::default(),
+        })
+    }
+
+    /// Simulate against a copy of the world instead of running the turtle
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
 
     /// Remove all blocks matching the predicate around the starting position
     ///
     /// MS paint bucket tool but in Minecraft with air
     async fn remove(&self, turtle: TurtleCommander) -> Option<bool> {
-        let mut next = self.pending.lock().await;
         let world = turtle.world();
 
-        if self.wanted(&world, self.start).await.unwrap_or(true) {
-            next.push(self.start);
+        // otherwise this picks up where the last turtle, or the last boot, left off
+        let fresh = self.visited.lock().unwrap().is_empty() && self.pending.lock().unwrap().is_empty();
+        if fresh && self.wanted(&world, self.start).await.unwrap_or(true) {
+            self.pending.lock().unwrap().push(self.start);
         }
 
         let mut inventory_check = 64;
 
-        while let Some(pos) = next.pop() {
-            // skip if seen and unwanted
-            if !self.wanted(&world, pos).await.unwrap_or(true) {
+        while let Some(pos) = self.next() {
+            if turtle.cancelled() {
+                return Some(false);
+            }
+            // skip if already handled, or seen and unwanted
+            if self.visited.lock().unwrap().contains(&pos) || !self.wanted(&world, pos).await.unwrap_or(true) {
+                self.settle(pos, false);
                 continue; 
             }
             let close = turtle.goto_adjacent(pos).await?; // look and see
             // skip if unseen or unwanted
-            if !self.wanted(&world, pos).await.is_some_and(|v| v) {
+            if !self.wanted(&world, pos).await.is_some_and(|v| v) || guarded(&world, pos).await {
+                self.settle(pos, false);
                 continue; 
             }
 
-            turtle.execute(close.dig(pos)?).await;
-
-            let cube = vec![
-                Vec3::x(),
-                -Vec3::x(),
-                Vec3::y(),
-                -Vec3::y(),
-                Vec3::z(),
-                -Vec3::z(),
-            ];
-
-            let mut near: Vec<Vec3> = cube.into_iter().map(|n| n + pos).collect();
+            if self.limit.is_some_and(|l| self.removed.load(Ordering::SeqCst) >= l) {
+                error!("removal of {} stopped after {} blocks, confirm to remove more", self.block, self.removed.load(Ordering::SeqCst));
+                return Some(true);
+            }
 
-            // these will be pruned in the next iterations
-            next.append(&mut near);
+            turtle.execute(close.dig(pos)?).await;
+            self.removed.fetch_add(1, Ordering::SeqCst);
+            self.timing.lock().unwrap().step();
+            self.settle(pos, true);
 
             inventory_check -= 1;
             if inventory_check == 0 {
@@ -620,7 +1658,7 @@ impl Remove {
                 let inventory = turtle.inventory().await;
                 let full = inventory.iter().filter(|s| s.is_some()).count();
 
-                if full > 14 {
+                if turtle.full(full as u32) {
                     turtle.dock().await;
                 }
             }
@@ -629,6 +1667,34 @@ impl Remove {
         Some(true)
     }
 
+    /// Place to look at next, left on the frontier until settled so that a save taken meanwhile keeps it
+    fn next(&self) -> Option<Vec3> {
+        self.pending.lock().unwrap().last().copied()
+    }
+
+    /// Done with the place from `next`, queueing its neighbors if it was dug
+    fn settle(&self, pos: Vec3, dug: bool) {
+        let mut visited = self.visited.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        visited.insert(pos);
+        pending.pop();
+        if !dug {
+            return;
+        }
+
+        let cube = vec![
+            Vec3::x(),
+            -Vec3::x(),
+            Vec3::y(),
+            -Vec3::y(),
+            Vec3::z(),
+            -Vec3::z(),
+        ];
+
+        // these will be pruned as they come up
+        pending.extend(cube.into_iter().map(|n| n + pos).filter(|n| !visited.contains(n)));
+    }
+
     async fn wanted(&self, world: &SharedWorld, pos: Vec3) -> Option<bool> {
         Some(world.get(pos).await?.name.contains(&self.block))
     }
@@ -636,12 +1702,32 @@ impl Remove {
 
 #[serde]
 impl Task for Remove {
+    fn role(&self) -> Role {
+        Role::Miner
+    }
+
+    /// Usually asked for because something is in the way
+    fn priority(&self) -> i8 {
+        1
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
     fn run(&mut self,turtle:TurtleCommander) -> AbortHandle {
         self.miners.fetch_add(1, Ordering::SeqCst);
+        let slot = MinerSlot(self.miners.clone());
 
         let owned = self.clone();
+        owned.timing.lock().unwrap().start();
 
         tokio::spawn(async move {
+            let _slot = slot;
+            let turtle = match owned.dry_run {
+                true => turtle.simulated(owned.report.clone()).await,
+                false => turtle,
+            };
             match owned.remove(turtle).await {
                 Some(true) => {
                     owned.done.store(true, Ordering::SeqCst);
@@ -653,12 +1739,19 @@ impl Task for Remove {
                     error!("removal failed");
                 },
             };
-            owned.miners.fetch_sub(1, Ordering::SeqCst);
         }).abort_handle()
     }
 
+    fn progress(&self) -> Option<Progress> {
+        let timing = *self.timing.lock().unwrap();
+        Some(Progress::new(self.removed.load(Ordering::SeqCst), self.limit, timing))
+    }
+
     fn poll(&mut self) -> TaskState {
         if self.done.load(Ordering::SeqCst) {
+            if self.dry_run {
+                info!("dry run of removing {} from {}: {}", self.block, Coords(self.start), self.report);
+            }
             return TaskState::Complete;
         }
         if self.miners.load(Ordering::SeqCst) < 1 {
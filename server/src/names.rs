@@ -1,6 +1,11 @@
-use anyhow::Context;
+use std::str::FromStr;
+
+use anyhow::{Context, anyhow};
 use bit_struct::*;
 use feistel_rs::{feistel_decrypt, feistel_encrypt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::{info, warn};
 
 bit_struct! {
     pub struct Name(u32) {
@@ -20,9 +25,71 @@ const LAST_NAMES: &str = include_str!("../surnames.txt");
 const ORDER: &str = include_str!("../order.txt");
 const PRONOUNS: &str = include_str!("../pronouns.txt");
 
+/// How turtle ids are turned into names
+///
+/// Names are stored encoded, so a save has to keep the scheme it was made with
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameScheme {
+    /// Hashed into a first name, surname, title and pronouns
+    #[default]
+    Human,
+    /// The id itself
+    Numeric,
+}
+
+impl FromStr for NameScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "numeric" => Ok(Self::Numeric),
+            _ => Err(anyhow!("unknown name scheme: {s}")),
+        }
+    }
+}
+
+/// Scheme in use, set once at startup
+pub static SCHEME: OnceCell<NameScheme> = OnceCell::const_new();
+
+fn scheme() -> NameScheme {
+    SCHEME.get().copied().unwrap_or_default()
+}
+
+/// Log how much of each bit field the name tables cover,
+/// ids landing past the end of a table get a numeric name
+pub fn check_tables() {
+    let tables = [
+        ("first names", FIRST_NAMES, 13),
+        ("last names", LAST_NAMES, 14),
+        ("orders", ORDER, 3),
+        ("pronouns", PRONOUNS, 2),
+    ];
+    for (table, entries, bits) in tables {
+        let (len, capacity) = (entries.lines().count(), 1usize << bits);
+        if len < capacity {
+            warn!("{table}: {len} of {capacity} entries, some turtles will be numbered");
+        } else {
+            info!("{table}: {len} of {capacity} entries");
+        }
+    }
+}
+
 impl Name {
     pub fn from_str(name: &str) -> anyhow::Result<Self> {
+        if scheme() == NameScheme::Numeric {
+            return Ok(Name::from_num(name.trim_start_matches('#').parse()?));
+        }
+
+        // placeholder from to_str
+        if let Some(num) = name.strip_prefix('#') {
+            return Ok(Name::from_num(num.parse()?));
+        }
+
         let parts: Vec<&str> = name.splitn(4, ' ').collect();
+        if parts.len() != 4 || parts[3].len() < 2 {
+            return Err(anyhow!("not a turtle name: {name}"));
+        }
         let (first, last, order, mut pronouns) = (parts[0], parts[1], parts[2], parts[3]);
         pronouns = &pronouns[1..pronouns.len() - 1];
 
@@ -62,6 +129,10 @@ impl Name {
     }
 
     pub fn from_num(name: u32) -> Self {
+        if scheme() == NameScheme::Numeric {
+            return unsafe { Name(UnsafeStorage::new_unsafe(name)) };
+        }
+
         let encrypted = u32::from_le_bytes(
             feistel_encrypt(&name.to_le_bytes(), &FEISTEL_KEY, FEISTEL_ROUNDS)
                 .try_into()
@@ -74,6 +145,9 @@ impl Name {
 
     pub fn to_num(self) -> u32 {
         let num = self.raw();
+        if scheme() == NameScheme::Numeric {
+            return num;
+        }
 
         u32::from_le_bytes(
             feistel_decrypt(&num.to_le_bytes(), &FEISTEL_KEY, FEISTEL_ROUNDS)
@@ -82,25 +156,30 @@ impl Name {
         )
     }
 
+    /// Human readable name, or `#<num>` if the name tables are too short
     pub fn to_str(&mut self) -> String {
+        if scheme() == NameScheme::Numeric {
+            return self.to_num().to_string();
+        }
+
         let first = FIRST_NAMES
             .lines()
-            .nth(self.first().get().value() as usize)
-            .unwrap();
+            .nth(self.first().get().value() as usize);
         let last = LAST_NAMES
             .lines()
-            .nth(self.last().get().value() as usize)
-            .unwrap();
+            .nth(self.last().get().value() as usize);
         let order = ORDER
             .lines()
-            .nth(self.order().get().value() as usize)
-            .unwrap();
+            .nth(self.order().get().value() as usize);
         let pronouns = PRONOUNS
             .lines()
-            .nth(self.pronouns().get().value() as usize)
-            .unwrap();
+            .nth(self.pronouns().get().value() as usize);
 
-        format!("{first} {last} {order} ({pronouns})")
+        match (first, last, order, pronouns) {
+            (Some(first), Some(last), Some(order), Some(pronouns)) =>
+                format!("{first} {last} {order} ({pronouns})"),
+            _ => format!("#{}", self.to_num()),
+        }
     }
 }
 
@@ -132,6 +211,20 @@ mod tests {
         assert_eq!(TEST_NUM, test_to_from(TEST_NUM));
     }
 
+    #[test]
+    fn malformed() {
+        assert!(Name::from_str("turtle").is_err());
+        assert!(Name::from_str("a b c ").is_err());
+    }
+
+    #[test]
+    fn placeholder() {
+        let name = Name::from_num(42);
+        assert_eq!(42, Name::from_str("#42").unwrap().to_num());
+        assert_eq!(name.raw(), Name::from_str(&format!("#{}", name.to_num())).unwrap().raw());
+        assert!(Name::from_str("#turtle").is_err());
+    }
+
     #[test]
     fn many() {
         for n in 0..1000 {
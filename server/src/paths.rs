@@ -1,38 +1,199 @@
+extern crate test;
+use std::{sync::{Arc, Mutex}, collections::HashMap};
+
 use crate::{
-    blocks::{SharedWorld, Position, Direction, Vec3, World, nearest, Block},
+    blocks::{SharedWorld, Position, Direction, Vec3, World, nearest, Block, Coords},
+    turtle::TurtleCommand,
 };
 use rstar::{AABB, Envelope};
-use tokio::task::spawn_blocking;
-use tracing::{trace, error};
+use serde::{Deserialize, Serialize};
+use tokio::{task::spawn_blocking, sync::{OnceCell, Semaphore}};
+use tracing::{trace, warn, error};
 use pathfinding::prelude::astar;
 
 const LOOKUP_LIMIT: usize = 100_000_000;
 
-#[tracing::instrument(skip(world))]
-pub async fn route_facing(from: Position, to: Vec3, world: &SharedWorld) -> Option<Vec<Position>> {
-    let facing = move |p: &Position| {
-        let ahead = p.dir.unit() + p.pos;
-        let above = Vec3::y() + p.pos;
-        let below = -Vec3::y() + p.pos;
-        to == ahead || to == below || to == above
-    };
-    route_to(from, to, facing, world).await
+/// Routes computed at once unless configured otherwise
+pub const MAX_PATHFINDERS: usize = 4;
+/// Each route takes a blocking thread, so this keeps some free for disk IO
+pub static PATHFINDERS: OnceCell<Semaphore> = OnceCell::const_new();
+/// Routes kept for reuse, the least recently used going first
+pub const ROUTE_CACHE: usize = 256;
+
+/// Routes already found, by where they start and end
+///
+/// Depots and other busy spots see the same trips over and over, so these
+/// are kept until a block along them changes
+#[derive(Clone, Default)]
+pub struct RouteCache {
+    routes: Arc<Mutex<Routes>>,
+    /// Bumped on every lookup, to tell how recently each route was used
+    uses: Arc<std::sync::atomic::AtomicU64>,
+    /// Bumped whenever routes are thrown out, so one found before that isn't kept
+    generation: Arc<std::sync::atomic::AtomicU64>,
+}
+
+#[derive(Default)]
+struct Routes {
+    by_ends: HashMap<(Position, Position), (Vec<Position>, u64)>,
+    /// Ends of the routes through each block, stair corners included
+    through: HashMap<Vec3, Vec<(Position, Position)>>,
+}
+
+impl Routes {
+    /// Blocks a route depends on staying as they are
+    fn blocks(route: &[Position]) -> impl Iterator<Item = Vec3> + '_ {
+        route.iter().map(|p| p.pos)
+            .chain(route.windows(2).filter_map(|pair| pair[0].corner(pair[1])))
+    }
+
+    fn remove(&mut self, ends: (Position, Position)) {
+        let Some((route, _)) = self.by_ends.remove(&ends) else {
+            return;
+        };
+        for block in Self::blocks(&route) {
+            if let Some(through) = self.through.get_mut(&block) {
+                through.retain(|e| *e != ends);
+                if through.is_empty() {
+                    self.through.remove(&block);
+                }
+            }
+        }
+    }
+}
+
+impl RouteCache {
+    fn get(&self, from: Position, to: Position) -> Option<Vec<Position>> {
+        let now = self.uses.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut routes = self.routes.lock().unwrap();
+        let (route, used) = routes.by_ends.get_mut(&(from, to))?;
+        *used = now;
+        Some(route.clone())
+    }
+
+    /// Current generation, to pass to `insert` along with a route found after this
+    pub fn generation(&self) -> u64 {
+        self.generation.load(std::sync::atomic::Ordering::Acquire)
+    }
+
+    /// Keep a route, unless routes were thrown out since it was looked for
+    fn insert(&self, from: Position, to: Position, route: Vec<Position>, generation: u64) {
+        let now = self.uses.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut routes = self.routes.lock().unwrap();
+        if self.generation() != generation {
+            return;
+        }
+        if routes.by_ends.len() >= ROUTE_CACHE && !routes.by_ends.contains_key(&(from, to)) {
+            // few enough to look through, next to the search it saves
+            let oldest = routes.by_ends.iter().min_by_key(|(_, (_, used))| *used).map(|(k, _)| *k);
+            oldest.map(|k| routes.remove(k));
+        }
+        routes.remove((from, to));
+        for block in Routes::blocks(&route) {
+            let through = routes.through.entry(block).or_default();
+            if !through.contains(&(from, to)) {
+                through.push((from, to));
+            }
+        }
+        routes.by_ends.insert((from, to), (route, now));
+    }
+
+    /// Forget routes through a block that changed
+    pub fn invalidate(&self, block: Vec3) {
+        let mut routes = self.routes.lock().unwrap();
+        self.generation.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        for ends in routes.through.remove(&block).unwrap_or_default() {
+            routes.remove(ends);
+        }
+    }
+
+    pub fn clear(&self) {
+        let mut routes = self.routes.lock().unwrap();
+        self.generation.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        *routes = Routes::default();
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.lock().unwrap().by_ends.len()
+    }
+}
+
+/// Blocks a route must not pass through unless they are already open,
+/// such as the walls a quarry leaves standing
+pub type Avoid = Arc<dyn Fn(Vec3) -> bool + Send + Sync>;
+
+/// Sides to reach a block from
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Approach {
+    /// Ahead, above, or below, whichever is closest
+    #[default]
+    Any,
+    /// Directly above or below, to dig straight down or up
+    Vertical,
+}
+
+impl Approach {
+    /// True if a turtle at `p` is in place to reach `to`
+    pub fn accepts(self, p: &Position, to: Vec3) -> bool {
+        let vertical = to == p.pos + Vec3::y() || to == p.pos - Vec3::y();
+        match self {
+            Approach::Any => vertical || to == p.pos + p.dir.unit(),
+            Approach::Vertical => vertical,
+        }
+    }
+}
+
+/// Route to face whichever target is cheapest to reach, in one search
+///
+/// Returns the target along with the route to it
+#[tracing::instrument(skip(world, avoid))]
+pub async fn route_to_any(from: Position, targets: &[Vec3], approach: Approach, avoid: Option<Avoid>, world: &SharedWorld) -> Option<(Vec3, Vec<Position>)> {
+    if targets.is_empty() {
+        return None;
+    }
+    let goals = targets.to_vec();
+    let route = route_to(from, targets.to_vec(), move |p| goals.iter().any(|t| approach.accepts(p, *t)), true, avoid, world).await?;
+    let end = route.last()?;
+    let target = targets.iter().find(|t| approach.accepts(end, **t))?;
+    Some((*target, route))
 }
 
-#[tracing::instrument(skip(world))]
 pub async fn route(from: Position, to: Position, world: &SharedWorld) -> Option<Vec<Position>> {
-    trace!("routing from {from:?} to {to:?}");
+    route_with(from, to, true, None, world).await
+}
+
+/// Route, reusing the last one found between the same places if nothing along it has changed
+pub async fn route_cached(from: Position, to: Position, world: &SharedWorld) -> Option<Vec<Position>> {
+    if let Some(route) = world.routes().get(from, to) {
+        return Some(route);
+    }
+    let generation = world.routes().generation();
+    let route = route(from, to, world).await?;
+    world.routes().insert(from, to, route.clone(), generation);
+    Some(route)
+}
+
+/// Route that only passes through known open space and the unknown if `dig` is false
+#[tracing::instrument(skip(world, avoid))]
+pub async fn route_with(from: Position, to: Position, dig: bool, avoid: Option<Avoid>, world: &SharedWorld) -> Option<Vec<Position>> {
+    trace!("routing from {from} to {to}");
     // attempt at not crashing by looking infinitely into the abyss
     if world.get(to.pos).await
-        .is_some_and(|b| difficulty(&b.name).is_none())
+        .is_some_and(|b| cost(&b.name, dig, &world.block_config(), &world.protection().blocks()).is_none())
     {
         return None;
     }
-    route_to(from, to.pos, move |p| p == &to, world).await
+    route_to(from, vec![to.pos], move |p| p == &to, dig, avoid, world).await
 }
 
-async fn route_to<D>(from: Position, to: Vec3, mut done: D, world: &SharedWorld) -> Option<Vec<Position>>
+/// A* until `done` holds, guided by the distance to the nearest target
+async fn route_to<D>(from: Position, targets: Vec<Vec3>, mut done: D, dig: bool, avoid: Option<Avoid>, world: &SharedWorld) -> Option<Vec<Position>>
 where D: FnMut(&Position) -> bool + Send + 'static {
+    let _permit = PATHFINDERS.get_or_init(|| async { Semaphore::new(MAX_PATHFINDERS) }).await
+        .acquire().await.unwrap();
+
+    let blocks = world.block_config();
+    let protected = world.protection().blocks();
     // lock once, we'll be doing a lot of lookups
     let world = world.clone().lock().await;
 
@@ -42,8 +203,8 @@ where D: FnMut(&Position) -> bool + Send + 'static {
         spawn_blocking( move ||
         astar(
         &from,
-        move |p| next(p, &world),
-        |p1| (p1.pos - &to).abs().sum() as u32,
+        move |p| next(p, dig, avoid.as_ref(), &world, &blocks, &protected),
+        |p1| targets.iter().map(|to| distance(p1.pos, *to)).min().unwrap_or(0),
         |p| {
             limit -= 1;
             if limit == 0 {
@@ -63,21 +224,39 @@ where D: FnMut(&Position) -> bool + Send + 'static {
     }
 }
 
-fn next(from: &Position, world: &World) -> Vec<(Position, u32)> {
+/// Fewest moves between two points, where a stair goes across and up or down at once
+///
+/// Never more than a route would cost, which keeps A* finding the shortest
+fn distance(from: Vec3, to: Vec3) -> u32 {
+    let d = (from - to).abs();
+    (d.x + d.z).max(d.y) as u32
+}
+
+/// Commands to follow a route, empty if it starts where it ends
+///
+/// None if any two positions aren't a single command apart
+pub fn steps(route: &[Position]) -> Option<Vec<TurtleCommand>> {
+    route.windows(2).map(|pair| pair[0].difference(pair[1])).collect()
+}
+
+fn next(from: &Position, dig: bool, avoid: Option<&Avoid>, world: &World, blocks: &BlockConfig, protected: &[String]) -> Vec<(Position, u32)> {
     let mut vec: Vec<(Position, u32)> = Vec::new();
 
-    fn insert(
+    let insert = |
         vec: &mut Vec<(Position, u32)>,
         point: Vec3,
         orientation: Direction,
         world: &World,
         unknown: Option<u32>,
-    ) {
+    | {
+        if avoid.is_some_and(|a| a(point)) && !world.get(point).is_some_and(|b| blocks.transparent(&b.name)) {
+            return;
+        }
         world
             .get(point)
-            .map_or(unknown, |b| difficulty(&b.name))
+            .map_or(unknown, |b| cost(&b.name, dig, blocks, protected))
             .map(|d| vec.push((Position::new(point, orientation), d)));
-    }
+    };
 
     vec.push((Position::new(from.pos, from.dir.left()), 1));
     vec.push((Position::new(from.pos, from.dir.right()), 1));
@@ -94,17 +273,48 @@ fn next(from: &Position, world: &World) -> Vec<(Position, u32)> {
     let below = from.pos - Vec3::y();
     insert(&mut vec, below, from.dir, world, UNKNOWN);
 
+    // stairs only through known open space, as nothing can be dug on the way
+    let open = |point: Vec3| world.get(point).is_some_and(|b| blocks.transparent(&b.name));
+    if open(ahead) {
+        for stair in [ahead + Vec3::y(), ahead - Vec3::y()] {
+            if open(stair) {
+                vec.push((Position::new(stair, from.dir), 1));
+            }
+        }
+    }
+
     vec
 }
 
-/// Blocks that you can go through without a pickaxe
-pub const TRANSPARENT: [&str; 3] = [
+/// Blocks that you can go through without a pickaxe, unless configured otherwise
+const TRANSPARENT: [&str; 3] = [
     "minecraft:air",
     "minecraft:water",
     "minecraft:lava",
 ];
 
-/// Blocks that are fine to tunnel through
+/// Things to leave in the field (not worth fuel), unless configured otherwise
+const USELESS: [&str; 5] = [
+    "minecraft:dirt",
+    "minecraft:gravel",
+    "minecraft:cobblestone",
+    "minecraft:cobbled_deepslate",
+    "minecraft:rhyolite",
+    //"minecraft:andesite", // TODO: Reach 2k
+];
+
+/// Things that are desirable, unless configured otherwise
+const VALUABLE: [&str; 1] = [
+    "ore",
+];
+
+/// Blocks that flow, the source blocks of which can be picked up with a bucket, unless configured otherwise
+const FLUIDS: [&str; 2] = [
+    "minecraft:water",
+    "minecraft:lava",
+];
+
+/// Blocks that are fine to tunnel through, unless configured otherwise
 const GARBAGE: [&str; 15] = [
     "minecraft:stone",
     "minecraft:dirt",
@@ -123,16 +333,374 @@ const GARBAGE: [&str; 15] = [
     "minecraft:snow",
 ];
 
+/// Blocks left for an operator unless configured otherwise, matched by substring
+pub const DEFAULT_PROTECTED: [&str; 3] = [
+    "spawner",
+    "shulker_box",
+    "chest",
+];
+
+/// Block names that differ between modpacks, read from `blocks.ron` in the save
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BlockConfig {
+    /// Blocks that you can go through without a pickaxe
+    pub transparent: Vec<String>,
+    /// Blocks that are fine to tunnel through
+    pub garbage: Vec<String>,
+    /// Things to leave in the field (not worth fuel)
+    pub useless: Vec<String>,
+    /// Things that are desirable, matched by substring
+    pub valuable: Vec<String>,
+    /// Blocks that flow, the source blocks of which can be picked up with a bucket
+    pub fluids: Vec<String>,
+}
+
+impl Default for BlockConfig {
+    fn default() -> Self {
+        let owned = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        Self {
+            transparent: owned(&TRANSPARENT),
+            garbage: owned(&GARBAGE),
+            useless: owned(&USELESS),
+            valuable: owned(&VALUABLE),
+            fluids: owned(&FLUIDS),
+        }
+    }
+}
+
+impl BlockConfig {
+    pub fn transparent(&self, name: &str) -> bool {
+        self.transparent.iter().any(|t| t == name)
+    }
+
+    pub fn garbage(&self, name: &str) -> bool {
+        self.garbage.iter().any(|g| g == name)
+    }
+
+    pub fn useless(&self, name: &str) -> bool {
+        self.useless.iter().any(|u| u == name)
+    }
+
+    pub fn valuable(&self, name: &str) -> bool {
+        self.valuable.iter().any(|v| name.contains(v.as_str()))
+    }
+
+    pub fn fluid(&self, name: &str) -> bool {
+        self.fluids.iter().any(|f| f == name)
+    }
+}
+
+/// Blocks no turtle digs, whatever task it is on, matched by substring
+#[derive(Clone, Default)]
+pub struct Protection {
+    blocks: Arc<std::sync::RwLock<Arc<Vec<String>>>>,
+    /// Protected blocks turtles were stopped from digging, for an operator to review
+    flagged: Arc<Mutex<Vec<Block>>>,
+}
+
+impl Protection {
+    /// Protected blocks as they are now, unchanged by any later update
+    pub fn blocks(&self) -> Arc<Vec<String>> {
+        self.blocks.read().unwrap().clone()
+    }
+
+    pub fn set(&self, blocks: Vec<String>) {
+        *self.blocks.write().unwrap() = Arc::new(blocks);
+    }
+
+    pub fn protects(&self, name: &str) -> bool {
+        protected(name, &self.blocks())
+    }
+
+    /// Note a protected block that was left in place
+    pub fn flag(&self, block: Block) {
+        let mut flagged = self.flagged.lock().unwrap();
+        if flagged.iter().any(|b| b.pos == block.pos) {
+            return;
+        }
+        warn!("left {} at {} for review", block.name, Coords(block.pos));
+        flagged.push(block);
+    }
+
+    /// Protected blocks left in place, oldest first
+    pub fn flagged(&self) -> Vec<Block> {
+        self.flagged.lock().unwrap().clone()
+    }
+
+    /// Forget the blocks flagged so far, returning how many there were
+    pub fn clear_flagged(&self) -> usize {
+        self.flagged.lock().unwrap().drain(..).count()
+    }
+}
+
+fn protected(name: &str, protected: &[String]) -> bool {
+    protected.iter().any(|p| name.contains(p.as_str()))
+}
+
 /// time taken to go through uncharted territory (in turtle. calls)
 const UNKNOWN: Option<u32> = Some(2);
 
+/// Difficulty, or only open space if not digging
+fn cost(name: &str, dig: bool, blocks: &BlockConfig, protected: &[String]) -> Option<u32> {
+    match dig {
+        true => difficulty(name, blocks, protected),
+        false => blocks.transparent(name).then_some(1),
+    }
+}
+
 // time to go somewhere
-pub fn difficulty(name: &str) -> Option<u32> {
-    if TRANSPARENT.contains(&name) {
+pub fn difficulty(name: &str, blocks: &BlockConfig, protected: &[String]) -> Option<u32> {
+    if blocks.transparent(name) {
         return Some(1);
     };
-    if GARBAGE.contains(&name) {
+    if self::protected(name, protected) {
+        return None;
+    };
+    if blocks.garbage(name) {
         return Some(2);
     };
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use test::Bencher;
+
+    use super::*;
+
+    #[test]
+    fn protected_blocks() {
+        let protection = Protection::default();
+        protection.set(vec!["spawner".into()]);
+        assert!(protection.protects("minecraft:spawner"));
+        assert_eq!(difficulty("minecraft:spawner", &BlockConfig::default(), &protection.blocks()), None);
+        assert_eq!(difficulty("minecraft:stone", &BlockConfig::default(), &protection.blocks()), Some(2));
+
+        protection.flag(Block::new("minecraft:spawner", Vec3::zeros()));
+        protection.flag(Block::new("minecraft:spawner", Vec3::zeros()));
+        assert_eq!(protection.flagged().len(), 1);
+        assert_eq!(protection.clear_flagged(), 1);
+        assert!(protection.flagged().is_empty());
+    }
+
+    #[test]
+    fn configured_blocks() {
+        let blocks: BlockConfig = ron::de::from_str(r#"(garbage: ["create:limestone"], valuable: ["ore", "raw_"])"#).unwrap();
+        assert_eq!(difficulty("create:limestone", &blocks, &[]), Some(2));
+        assert_eq!(difficulty("minecraft:stone", &blocks, &[]), None);
+        assert_eq!(difficulty("minecraft:air", &blocks, &[]), Some(1));
+        assert!(blocks.valuable("mekanism:block_raw_osmium"));
+        assert!(blocks.useless("minecraft:gravel"));
+        assert!(blocks.fluid("minecraft:lava"));
+    }
+
+    #[tokio::test]
+    async fn already_there() {
+        let world = SharedWorld::new();
+        let here = Position::new(Vec3::new(3, -2, 1), Direction::East);
+
+        let route = route(here, here, &world).await.unwrap();
+        assert_eq!(route, vec![here]);
+        assert!(steps(&route).unwrap().is_empty());
+
+        let ahead = here.pos + here.dir.unit();
+        let (_, route) = route_to_any(here, &[ahead], Approach::Any, None, &world).await.unwrap();
+        assert_eq!(route, vec![here]);
+        assert!(steps(&route).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn from_above() {
+        let world = SharedWorld::new();
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let ahead = here.pos + here.dir.unit();
+
+        let (_, route) = route_to_any(here, &[ahead], Approach::Vertical, None, &world).await.unwrap();
+        let end = route.last().unwrap();
+        assert!(Approach::Vertical.accepts(end, ahead));
+        assert!(!Approach::Vertical.accepts(&here, ahead));
+        assert!(Approach::Any.accepts(&here, ahead));
+    }
+
+    #[tokio::test]
+    async fn nearest_target() {
+        let world = SharedWorld::new();
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let near = Vec3::new(0, 0, -3);
+        let far = Vec3::new(6, 0, 6);
+
+        let (target, route) = route_to_any(here, &[far, near], Approach::Any, None, &world).await.unwrap();
+        assert_eq!(target, near);
+        assert!(Approach::Any.accepts(route.last().unwrap(), near));
+        assert!(route_to_any(here, &[], Approach::Any, None, &world).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn through_cleared() {
+        let world = SharedWorld::new();
+        // a dug out tunnel a block above the straight line through unknown space
+        for z in -7..=1 {
+            world.set(Block::new("minecraft:air", Vec3::new(0, 1, z))).await;
+        }
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let there = Position::new(Vec3::new(0, 0, -6), Direction::North);
+
+        let route = route(here, there, &world).await.unwrap();
+        assert!(route.iter().any(|p| p.pos.y == 1));
+    }
+
+    #[tokio::test]
+    async fn one_step() {
+        let world = SharedWorld::new();
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let there = Position::new(here.pos + here.dir.unit(), here.dir);
+
+        let route = route(here, there, &world).await.unwrap();
+        assert!(matches!(steps(&route).unwrap()[..], [TurtleCommand::Forward(1)]));
+    }
+
+    #[tokio::test]
+    async fn staircase() {
+        let world = SharedWorld::new();
+        for y in 0..=10 {
+            for z in -10..=0 {
+                world.set(Block::new("minecraft:air", Vec3::new(0, y, z))).await;
+            }
+        }
+        let here = Position::new(Vec3::new(0, 10, 0), Direction::North);
+        let there = Position::new(Vec3::new(0, 0, -10), Direction::North);
+
+        let steps = steps(&route(here, there, &world).await.unwrap()).unwrap();
+        // going across then down, or zig-zagging, takes one call per block
+        assert!(steps.len() < here.manhattan(there) as usize);
+        assert_eq!(steps.len(), 10);
+        assert!(steps.iter().all(|s| matches!(s, TurtleCommand::ForwardDown)));
+    }
+
+    #[tokio::test]
+    async fn without_digging() {
+        let world = SharedWorld::new();
+        // a stone wall across the way, open above
+        for x in -1..=1 {
+            for y in -1..=0 {
+                world.set(Block::new("minecraft:stone", Vec3::new(x, y, -2))).await;
+            }
+        }
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let there = Position::new(Vec3::new(0, 0, -4), Direction::North);
+
+        let through = route_with(here, there, true, None, &world).await.unwrap();
+        assert!(through.iter().any(|p| p.pos.z == -2 && p.pos.y == 0));
+        let around = route_with(here, there, false, None, &world).await.unwrap();
+        for p in around {
+            assert!(!world.occupied(p.pos).await, "dug through {p}");
+        }
+
+        // a wall that is to stay is gone around, even when digging
+        let avoid: Avoid = Arc::new(|p: Vec3| p.z == -2 && p.x.abs() <= 1 && p.y <= 0);
+        let kept = route_with(here, there, true, Some(avoid), &world).await.unwrap();
+        for p in kept {
+            assert!(!world.occupied(p.pos).await, "dug through {p}");
+        }
+
+        world.set(Block::new("minecraft:stone", there.pos)).await;
+        assert!(route_with(here, there, true, None, &world).await.is_some());
+        assert!(route_with(here, there, false, None, &world).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn cached_routes() {
+        let world = walled().await;
+        let (here, there) = ends();
+
+        let route = route_cached(here, there, &world).await.unwrap();
+        assert_eq!(world.routes().len(), 1);
+        assert_eq!(route_cached(here, there, &world).await.unwrap(), route);
+
+        // the same again is no change
+        world.set(Block::new("minecraft:air", route[1].pos)).await;
+        assert_eq!(world.routes().len(), 1);
+
+        world.set(Block::new("minecraft:bedrock", route[1].pos)).await;
+        assert_eq!(world.routes().len(), 0);
+        assert!(route_cached(here, there, &world).await.unwrap().iter().all(|p| p.pos != route[1].pos));
+
+        for x in 1..ROUTE_CACHE as i32 {
+            world.routes().insert(Position::new(Vec3::x() * x, Direction::North), there, Vec::new(), world.routes().generation());
+        }
+        assert_eq!(world.routes().len(), ROUTE_CACHE);
+        assert!(world.routes().get(here, there).is_some());
+        // the oldest goes to make room, not the one just used
+        world.routes().insert(here, here, Vec::new(), world.routes().generation());
+        assert_eq!(world.routes().len(), ROUTE_CACHE);
+        assert!(world.routes().get(here, there).is_some());
+        assert!(world.routes().get(Position::new(Vec3::x(), Direction::North), there).is_none());
+
+        // found before a change, so it may go through what changed
+        let generation = world.routes().generation();
+        world.set(Block::new("minecraft:stone", route[2].pos)).await;
+        world.routes().insert(Position::new(Vec3::zeros(), Direction::South), there, route.clone(), generation);
+        assert!(world.routes().get(Position::new(Vec3::zeros(), Direction::South), there).is_none());
+
+        // new block lists may open up or close off any route
+        world.set_block_config(BlockConfig::default());
+        assert_eq!(world.routes().len(), 0);
+    }
+
+    /// Known open space split by a wall with a gap at one end
+    async fn walled() -> SharedWorld {
+        let world = SharedWorld::new();
+        for x in -8..8 {
+            for y in 0..4 {
+                for z in -16..=0 {
+                    let name = match z == -8 && x < 7 {
+                        true => "minecraft:bedrock",
+                        false => "minecraft:air",
+                    };
+                    world.set(Block::new(name, Vec3::new(x, y, z))).await;
+                }
+            }
+        }
+        world
+    }
+
+    /// From one side of the wall to the other
+    fn ends() -> (Position, Position) {
+        (Position::new(Vec3::new(-6, 1, -1), Direction::North), Position::new(Vec3::new(-6, 1, -15), Direction::North))
+    }
+
+    fn repeated(b: &mut Bencher, cached: bool) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let world = runtime.block_on(walled());
+        let (here, there) = ends();
+
+        b.iter(|| runtime.block_on(async {
+            for _ in 0..100 {
+                let route = match cached {
+                    true => route_cached(here, there, &world).await,
+                    false => route(here, there, &world).await,
+                };
+                assert!(route.is_some());
+            }
+        }));
+    }
+
+    #[bench]
+    fn routes_uncached(b: &mut Bencher) {
+        repeated(b, false);
+    }
+
+    #[bench]
+    fn routes_cached(b: &mut Bencher) {
+        repeated(b, true);
+    }
+
+    #[test]
+    fn broken_route() {
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let far = Position::new(Vec3::new(0, 0, 5), Direction::North);
+        assert!(steps(&[here, far]).is_none());
+        assert!(steps(&[]).unwrap().is_empty());
+    }
+}
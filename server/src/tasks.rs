@@ -1,32 +1,270 @@
-use tracing::{info, trace, instrument};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use ron::ser::PrettyConfig;
+use tracing::{info, trace, instrument, warn, error};
 use serde::{Deserialize, Serialize};
-use tokio::sync::oneshot;
+use time::OffsetDateTime;
+use tokio::sync::{oneshot, mpsc};
 use tokio::task::{JoinHandle, AbortHandle};
+use tokio_util::sync::CancellationToken;
 
 use crate::names::Name;
 use crate::{turtle::TurtleCommander, blocks::Position};
 
 pub enum TaskState {
     Ready(Position),
+    /// Ready, best given to a turtle that meets the needs
+    ReadyFor(Position, Needs),
     Waiting,
     Complete,
 }
 
+/// What a piece of work asks of the turtle given it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Needs {
+    /// Fuel the work is expected to burn, turtles short of it would dock first
+    pub fuel: Option<usize>,
+    /// Narrower than the task's role, ignored if no free turtle has it
+    pub role: Option<Role>,
+}
+
 #[typetag::serde(tag = "task")]
 pub trait Task: Send + Sync {
     /// Execute the task
     fn run(&mut self, turtle: TurtleCommander) -> AbortHandle;
     /// Return Some if the task should be scheduled
     fn poll(&mut self) -> TaskState;
+    /// Kind of turtle the task needs
+    fn role(&self) -> Role {
+        Role::General
+    }
+    /// How far along the task is, if it keeps track
+    fn progress(&self) -> Option<Progress> {
+        None
+    }
+    /// Higher goes first, even ahead of tasks that have waited long for a turtle
+    fn priority(&self) -> i8 {
+        0
+    }
+    /// Only simulated, so run on a copy of a turtle without tying the turtle up
+    fn dry_run(&self) -> bool {
+        false
+    }
+}
+
+/// When a task was started and last moved forward
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct Timing {
+    /// First time a turtle was put to work on it
+    started: Option<OffsetDateTime>,
+    /// Last time a chunk, layer, or block was finished
+    last: Option<OffsetDateTime>,
+}
+
+impl Timing {
+    pub fn start(&mut self) {
+        self.started.get_or_insert_with(OffsetDateTime::now_utc);
+    }
+
+    pub fn step(&mut self) {
+        self.start();
+        self.last = Some(OffsetDateTime::now_utc());
+    }
+}
+
+/// How far along a task is
+#[derive(Serialize, Debug)]
+pub struct Progress {
+    /// Chunks, layers, or blocks finished
+    done: usize,
+    /// None if open ended
+    total: Option<usize>,
+    #[serde(flatten)]
+    timing: Timing,
+    /// Seconds since the task was started
+    elapsed: Option<i64>,
+    /// Anything the task won't get done
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<String>,
+}
+
+impl Progress {
+    pub fn new(done: usize, total: Option<usize>, timing: Timing) -> Self {
+        let elapsed = timing.started.map(|t| (OffsetDateTime::now_utc() - t).whole_seconds());
+        Self { done, total, timing, elapsed, notes: Vec::new() }
+    }
+
+    pub fn notes(mut self, notes: Vec<String>) -> Self {
+        self.notes = notes;
+        self
+    }
+}
+
+/// What a turtle is equipped for
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Role {
+    /// Takes any task
+    #[default]
+    General,
+    /// Has a pickaxe
+    Miner,
+    Builder,
+    Hauler,
+}
+
+impl Role {
+    /// True if a turtle with this role can be given a task needing `task`
+    pub fn fits(self, task: Role) -> bool {
+        self == Role::General || task == Role::General || self == task
+    }
+}
+
+/// Safety caps on the work tasks take on, read from `limits.ron` in the save
+///
+/// Anything past them has to be confirmed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Limits {
+    /// Largest quarry (in blocks)
+    pub quarry_volume: i64,
+    /// Most blocks a removal will dig
+    pub remove_blocks: usize,
+    /// Farthest (manhattan) a region can be from every depot
+    pub depot_distance: i32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            quarry_volume: 64 * 64 * 64,
+            remove_blocks: 4096,
+            depot_distance: 1024,
+        }
+    }
+}
+
+/// Time a turtle can sit idle before it is sent home
+const IDLE_TIME: Duration = Duration::from_secs(120);
+
+/// Time a cancelled task has to wrap up before it is aborted
+const CANCEL_GRACE: Duration = Duration::from_secs(30);
+
+/// Time a task can go without a turtle before it is considered first
+const STARVATION: Duration = Duration::from_secs(60);
+
+/// Finished tasks kept to look back on and run again
+const MAX_ARCHIVED: usize = 64;
+
+/// Largest task, serialized, kept to run again
+const MAX_ORIGINAL: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize)]
+struct Queued {
+    task: Box<dyn Task>,
+    /// Handed out when the task is added
+    id: u64,
+    /// When the task started waiting on a turtle
+    #[serde(skip)]
+    ready_since: Option<Instant>,
+    /// Last poll said there was nothing to do yet
+    #[serde(skip)]
+    waiting: bool,
+    /// The task as it was added, to run again once it is done
+    #[serde(default)]
+    original: Option<String>,
+    /// Why there is no original, if there isn't
+    #[serde(default)]
+    unkept: Option<String>,
+}
+
+/// A task just queued
+pub struct Added {
+    pub id: u64,
+    /// Why the task can't be run again once it is done, if it can't
+    pub unkept: Option<String>,
+}
+
+/// The task serialized to run again, or why it can't be
+fn keep(task: &dyn Task) -> Result<String, String> {
+    let original = ron::ser::to_string(&task)
+        .map_err(|e| format!("can't be archived: {e}"))?;
+    // builds carry their whole schematic, which would bloat every save and the archive
+    if original.len() > MAX_ORIGINAL {
+        return Err(format!("{} bytes, more than the {MAX_ORIGINAL} kept to run it again", original.len()));
+    }
+    Ok(original)
+}
+
+/// A finished task, with what it was asked to do
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Archived {
+    id: u64,
+    task: String,
+    finished: OffsetDateTime,
+    /// The task as it was added, None if it was too large to keep
+    /// or queued before originals were saved
+    original: Option<String>,
+    /// Why there is no original, if it is known
+    #[serde(default)]
+    unkept: Option<String>,
+}
+
+impl Archived {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// A new copy of the task, as it was before any work was done
+    pub fn fresh(&self) -> anyhow::Result<Box<dyn Task>> {
+        let original = self.original.as_ref().ok_or_else(|| match &self.unkept {
+            Some(why) => anyhow::anyhow!("task {} was not kept: {why}", self.id),
+            None => anyhow::anyhow!("task {} was queued before a restart, so what it was asked to do is lost", self.id),
+        })?;
+        Ok(ron::de::from_str(original)?)
+    }
+}
+
+impl Queued {
+    fn starving(&self) -> Option<Instant> {
+        self.ready_since.filter(|t| t.elapsed() >= STARVATION)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Scheduler {
     #[serde(skip)]
     turtles: Vec<(TurtleCommander, Option<AbortHandle>)>,
-    tasks: Vec<Box<dyn Task>>,
+    #[serde(rename = "queue", default)]
+    tasks: Vec<Queued>,
+    /// Tasks from saves made before ids were kept, numbered on load
+    #[serde(rename = "tasks", default, skip_serializing)]
+    legacy: Vec<Box<dyn Task>>,
     #[serde(skip)]
     shutdown: Option<oneshot::Sender<()>>,
+    /// Dry runs in progress by task id, which don't count against any turtle
+    #[serde(skip)]
+    simulations: HashMap<u64, (CancellationToken, Vec<AbortHandle>)>,
+    /// Stop handing out new work, kept across restarts
+    #[serde(default)]
+    paused: bool,
+    /// When each turtle last became free, by number
+    #[serde(skip)]
+    idle: HashMap<u32, Instant>,
+    /// Stops the task each turtle is running, by number
+    #[serde(skip)]
+    cancels: HashMap<u32, CancellationToken>,
+    /// Task id each turtle is running, by number
+    ///
+    /// Saved so turtles cut off by a restart are first in line to pick their task back up
+    #[serde(default)]
+    assigned: HashMap<u32, u64>,
+    /// Most recently finished tasks, oldest first
+    #[serde(default)]
+    archive: VecDeque<Archived>,
 }
 
 impl Default for Scheduler {
@@ -34,7 +272,14 @@ impl Default for Scheduler {
         Self {
             turtles: Vec::new(),
             tasks: Vec::new(),
+            legacy: Vec::new(),
             shutdown:None,
+            simulations: HashMap::new(),
+            paused: false,
+            idle: HashMap::new(),
+            cancels: HashMap::new(),
+            assigned: HashMap::new(),
+            archive: VecDeque::new(),
         }
     }
 }
@@ -47,15 +292,63 @@ impl Scheduler {
             return;
         }
         info!("registered {}", name.to_owned().to_str());
+        self.idle.insert(name.to_num(), Instant::now());
         self.turtles.push((
                 turtle.clone(),
                 None
         ));
     }
 
-    pub fn add_task(&mut self, task: Box<dyn Task>) {
-        trace!("new {} task", task.typetag_name());
-        self.tasks.push(task);
+    pub fn add_task(&mut self, id: u64, task: Box<dyn Task>) {
+        let kept = keep(task.as_ref());
+        self.queue(id, task, kept);
+    }
+
+    /// Queue a task along with what `keep` made of it
+    fn queue(&mut self, id: u64, task: Box<dyn Task>, kept: Result<String, String>) {
+        trace!("new {} task #{id}", task.typetag_name());
+        let (original, unkept) = match kept {
+            Ok(original) => (Some(original), None),
+            Err(why) => {
+                warn!("task #{id} can't be run again: {why}");
+                (None, Some(why))
+            },
+        };
+        self.tasks.push(Queued { task, id, ready_since: None, waiting: false, original, unkept });
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Counts as of the last poll
+    pub fn status(&self) -> SchedulerStatus {
+        SchedulerStatus {
+            turtles: self.turtles.len(),
+            busy: self.turtles.iter().filter(|t| t.1.as_ref().is_some_and(|h| !h.is_finished())).count(),
+            ready: self.tasks.iter().filter(|t| t.ready_since.is_some() && !t.waiting).count(),
+            waiting: self.tasks.iter().filter(|t| t.waiting).count(),
+            paused: self.paused,
+        }
+    }
+
+    /// True if the turtle is running a task, or something done on it
+    pub fn busy(&self, turtle: Name) -> bool {
+        self.turtles.iter()
+            .find(|(t, _)| t.name().to_num() == turtle.to_num())
+            .is_some_and(|(_, h)| h.as_ref().is_some_and(|h| !h.is_finished()))
+    }
+
+    /// Every queued task, in scheduling order
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        self.tasks.iter().map(|t| TaskInfo {
+            id: t.id,
+            task: t.task.typetag_name(),
+            role: t.task.role(),
+            priority: t.task.priority(),
+            waiting: t.waiting,
+            progress: t.task.progress(),
+        }).collect()
     }
 
     pub async fn poll(&mut self) {
@@ -64,11 +357,19 @@ impl Scheduler {
                 if join.is_finished() {
                     trace!("#{} completed task", turtle.0.name().to_num());
                     turtle.1 = None;
+                    self.idle.insert(turtle.0.name().to_num(), Instant::now());
+                    self.cancels.remove(&turtle.0.name().to_num());
+                    self.assigned.remove(&turtle.0.name().to_num());
                 }
             }
         }
 
         if self.shutdown.is_some() {
+            // nothing real rides on a simulation
+            let simulated: Vec<u64> = self.simulations.keys().copied().collect();
+            for id in simulated {
+                self.stop_simulating(id);
+            }
             trace!("checking remaining tasks");
             if !self.turtles.iter().any(|t| t.1.is_some()) {
                 trace!("all tasks complete");
@@ -82,80 +383,654 @@ impl Scheduler {
             return;
         }
 
+        if self.paused {
+            return;
+        }
+
+        let order = self.order();
+        self.simulations.retain(|_, (_, runs)| {
+            runs.retain(|r| !r.is_finished());
+            !runs.is_empty()
+        });
+        let templates: Vec<TurtleCommander> = self.turtles.iter()
+            .filter(|t| !t.0.powered_off())
+            .map(|t| t.0.clone()).collect();
+
         let mut free_turtles: Vec<&mut (TurtleCommander, Option<AbortHandle>)> = 
-            self.turtles.iter_mut().filter(|t| t.1.is_none()).collect();
+            self.turtles.iter_mut().filter(|t| t.1.is_none() && !t.0.powered_off()).collect();
 
         let mut turtle_positions = Vec::new();
         for turtle in &free_turtles {
             turtle_positions.push(turtle.0.pos().await);
         }
 
-        let mut done = vec![false; self.tasks.len()];
-        for (i, task) in self.tasks.iter_mut().enumerate() {
-            let poll = task.poll();
-            if let TaskState::Ready(position) = poll {
-                let closest_turtle = match free_turtles.iter_mut().zip(turtle_positions.iter())
-                    .filter(|t|t.0.1.is_none()) // Don't double-schedule
-                    .min_by_key( |(_,p)| {
-                    p.manhattan(position)
-                }) {
-                    Some(turtle) => turtle.0,
-                    None => break,
+        // by id, so removal doesn't depend on where tasks sit in the queue
+        let mut finished = HashSet::new();
+        for i in order {
+            let queued = &mut self.tasks[i];
+            let role = queued.task.role();
+            if queued.task.dry_run() {
+                // any turtle of the role will do for a starting point, busy or not
+                let Some(template) = templates.iter().find(|t| t.role().fits(role)) else {
+                    continue;
                 };
+                let poll = queued.task.poll();
+                match poll {
+                    TaskState::Ready(_) | TaskState::ReadyFor(..) => {
+                        trace!("simulating {} from #{}", queued.task.typetag_name(), template.name().to_num());
+                        let (cancel, runs) = self.simulations.entry(queued.id).or_default();
+                        runs.push(queued.task.run(template.cancellable(cancel.clone())));
+                    },
+                    TaskState::Complete => { finished.insert(queued.id); },
+                    TaskState::Waiting => {},
+                }
+                queued.waiting = matches!(poll, TaskState::Waiting);
+                continue;
+            }
+            if !free_turtles.iter().any(|t| t.1.is_none() && t.0.role().fits(role)) {
+                // polling would claim work nobody can take, so go by the last poll:
+                // only a task that had work then is kept waiting on a turtle
+                if queued.waiting {
+                    queued.ready_since = None;
+                } else {
+                    queued.ready_since.get_or_insert_with(Instant::now);
+                }
+                continue;
+            }
+
+            let poll = queued.task.poll();
+            let ready = match poll {
+                TaskState::Ready(position) => Some((position, Needs::default())),
+                TaskState::ReadyFor(position, needs) => Some((position, needs)),
+                _ => None,
+            };
+            if let Some((position, needs)) = ready {
+                // the work is already claimed, so settle for the task's role over leaving it
+                let role = needs.role
+                    .filter(|r| free_turtles.iter().any(|t| t.1.is_none() && t.0.role().fits(*r)))
+                    .unwrap_or(role);
+                let assigned = &self.assigned;
+                let closest_turtle = free_turtles.iter_mut().zip(turtle_positions.iter())
+                    .filter(|t|t.0.1.is_none()) // Don't double-schedule
+                    .filter(|t| t.0.0.role().fits(role))
+                    .min_by_key( |(t,p)| {
+                    let resuming = assigned.get(&t.0.name().to_num()) == Some(&queued.id);
+                    // turtles that would have to dock first go last
+                    (!resuming, needs.fuel.is_some_and(|f| t.0.fuel() < f), p.manhattan(position))
+                }).unwrap().0; // checked above
 
-                trace!("scheduling {} on #{}", task.typetag_name(), closest_turtle.0.name().to_num());
-                closest_turtle.1 = Some(task.run(closest_turtle.0.clone()));
+                trace!("scheduling {} on #{}", queued.task.typetag_name(), closest_turtle.0.name().to_num());
+                let cancel = CancellationToken::new();
+                self.cancels.insert(closest_turtle.0.name().to_num(), cancel.clone());
+                self.assigned.insert(closest_turtle.0.name().to_num(), queued.id);
+                closest_turtle.1 = Some(queued.task.run(closest_turtle.0.cancellable(cancel)));
             }
+            queued.ready_since = None;
+            queued.waiting = matches!(poll, TaskState::Waiting);
             if let TaskState::Complete = poll {
-                done[i] = true;
+                finished.insert(queued.id);
             }
         }
 
-        // this feels like a hack
-        let mut i = 0;
-        self.tasks.retain(|_| {
-            let cont = !done[i];
-            i+=1;
-            cont 
+        self.assigned.retain(|_, id| !finished.contains(id));
+        let (done, left) = std::mem::take(&mut self.tasks).into_iter().partition(|t| finished.contains(&t.id));
+        self.tasks = left;
+        for queued in done {
+            self.archive(queued);
+        }
+
+        self.send_home().await;
+    }
+
+    /// Indices of tasks in the order they get turtles
+    fn order(&self) -> Vec<usize> {
+        // within a priority, tasks that have gone without a turtle for a while get first pick,
+        // otherwise a cluster of nearby tasks can take every turtle forever
+        let mut order: Vec<usize> = (0..self.tasks.len()).collect();
+        order.sort_by_key(|&i| {
+            let queued = &self.tasks[i];
+            let starving = match queued.starving() {
+                Some(since) => (0, Some(since)),
+                None => (1, None),
+            };
+            (Reverse(queued.task.priority()), starving)
+        });
+        order
+    }
+
+    fn archive(&mut self, queued: Queued) {
+        info!("task #{} ({}) finished", queued.id, queued.task.typetag_name());
+        self.stop_simulating(queued.id);
+        if self.archive.len() >= MAX_ARCHIVED {
+            self.archive.pop_front();
+        }
+        self.archive.push_back(Archived {
+            id: queued.id,
+            task: queued.task.typetag_name().to_string(),
+            finished: OffsetDateTime::now_utc(),
+            original: queued.original,
+            unkept: queued.unkept,
         });
     }
 
-    // TODO: make awaiting this feasible
-    pub fn do_on<T>(&mut self, mut task: T, turtle: Name) -> Option<()> 
-        where T: FnMut(TurtleCommander) -> AbortHandle
+    /// Stop every dry run of a task, so none outlives it in the queue
+    fn stop_simulating(&mut self, id: u64) {
+        let Some((cancel, runs)) = self.simulations.remove(&id) else {
+            return;
+        };
+        cancel.cancel();
+        for run in runs {
+            run.abort();
+        }
+    }
+
+    pub fn do_on<T>(&mut self, task: T, turtle: Name) -> Option<()> 
+        where T: FnOnce(TurtleCommander) -> AbortHandle
     {
         let turtle = self.turtles.iter_mut().filter(|t| t.0.name() == turtle).next()?;
         if let Some(task) = turtle.1.take() { // this may be unsound
             task.abort(); 
         }
+        if let Some(cancel) = self.cancels.remove(&turtle.0.name().to_num()) {
+            cancel.cancel();
+        }
+        self.assigned.remove(&turtle.0.name().to_num());
         trace!("new adhoc task on {}", turtle.0.name().to_str());
         turtle.1 = Some(task(turtle.0.clone()));
         Some(())
     }
 
-    pub fn task_on(&mut self, mut task: Box<dyn Task>, turtle: Name) -> Option<()> {
-        trace!("new {} task on {}", task.typetag_name(), turtle.clone().to_str());
-        let turtle = self.turtles.iter_mut().filter(|t| t.0.name() == turtle).next()?;
-        match turtle.1 {
-            Some(_) => None,
+    /// Ask the turtle's task to stop, aborting it if it doesn't within CANCEL_GRACE
+    ///
+    /// Aborting can leave a task counting the turtle as still on it, so when it is
+    /// to be requeued it is given as long as it takes to hand its work back instead
+    pub async fn cancel(&mut self, turtle: Name, requeue: bool) -> Option<()> {
+        let Some(task) = self.turtles.iter().find(|t| t.0.name() == turtle)?.1.clone() else {
+            return Some(());
+        };
+        let num = turtle.to_num();
+        match self.cancels.remove(&num) {
+            Some(cancel) => {
+                cancel.cancel();
+                info!("cancelled task for #{num}");
+                tokio::spawn(async move {
+                    tokio::time::sleep(CANCEL_GRACE).await;
+                    if task.is_finished() {
+                        return;
+                    }
+                    match requeue {
+                        true => error!("task for #{num} ignored cancellation, leaving it to finish"),
+                        false => {
+                            error!("task for #{num} ignored cancellation, aborting");
+                            task.abort();
+                        },
+                    }
+                });
+            },
+            // nothing is checking for a token, such as a trip home
             None => {
-                turtle.1 = Some(task.run(turtle.0.clone()));
-                Some(())
+                task.abort();
+                info!("aborted task for #{num}");
             },
         }
+        Some(())
     }
 
-    pub async fn cancel(&mut self, turtle: Name) -> Option<()> {
-        if let Some(task) = self.turtles.iter_mut().find(|t| t.0.name() == turtle)?.1.as_ref() {
-            task.abort();
-            info!("aborted task for #{}", turtle.to_num());
+    pub fn set_home(&mut self, turtle: Name, home: Option<Position>) -> Option<()> {
+        let turtle = self.turtles.iter_mut().find(|t| t.0.name() == turtle)?;
+        turtle.0.set_home(home);
+        Some(())
+    }
+
+    /// Park turtles that have been idle a while, unless a task could use them
+    async fn send_home(&mut self) {
+        if self.tasks.iter().any(|t| t.ready_since.is_some()) {
+            return;
         }
+
+        for turtle in self.turtles.iter_mut().filter(|t| t.1.is_none() && !t.0.powered_off()) {
+            let Some(home) = turtle.0.home() else {
+                continue;
+            };
+            let name = turtle.0.name().to_num();
+            if self.idle.get(&name).is_some_and(|t| t.elapsed() < IDLE_TIME) || turtle.0.pos().await == home {
+                continue;
+            }
+
+            trace!("sending #{name} home to {home}");
+            // counts as busy until it gets there
+            let commander = turtle.0.clone();
+            turtle.1 = Some(tokio::spawn(async move {
+                commander.goto(home).await;
+            }).abort_handle());
+        }
+    }
+
+    pub fn set_role(&mut self, turtle: Name, role: Role) -> Option<()> {
+        let turtle = self.turtles.iter_mut().find(|t| t.0.name() == turtle)?;
+        info!("{} is now a {role:?} turtle", turtle.0.name().to_str());
+        turtle.0.set_role(role);
         Some(())
     }
 
-    pub fn shutdown(&mut self) -> oneshot::Receiver<()>{
+    /// Let running tasks finish but don't assign new ones
+    pub fn pause(&mut self) {
+        info!("scheduler paused");
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        info!("scheduler resumed");
+        self.paused = false;
+    }
+
+    /// Give tasks from older saves ids, returning the next free one
+    fn number_legacy(&mut self) -> u64 {
+        // new ids start after every one handed out so far
+        let mut next = self.tasks.iter().map(|t| t.id)
+            .chain(self.archive.iter().map(|a| a.id))
+            .max().map_or(0, |id| id + 1);
+        let legacy = std::mem::take(&mut self.legacy);
+        // older saves kept assignments by where the task sat in the queue
+        if !legacy.is_empty() {
+            for id in self.assigned.values_mut() {
+                *id += next;
+            }
+        }
+        for task in legacy {
+            self.add_task(next, task);
+            next += 1;
+        }
+        next
+    }
+
+    /// Forget saved assignments to tasks no longer queued, which there is nothing to resume of
+    fn drop_stale_assignments(&mut self) {
+        let queued: HashSet<u64> = self.tasks.iter().map(|t| t.id).collect();
+        self.assigned.retain(|turtle, id| {
+            let stale = !queued.contains(id);
+            if stale {
+                warn!("#{turtle} was on task #{id}, which is gone");
+            }
+            !stale
+        });
+    }
+
+    /// Move the scheduler into its own task, all changes go through the returned handle
+    pub fn spawn(mut self) -> SchedulerHandle {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let next_id = Arc::new(AtomicU64::new(self.number_legacy()));
+        self.drop_stale_assignments();
+        let count = Arc::new(AtomicUsize::new(self.task_count()));
+        let handle = SchedulerHandle { sender, count: count.clone(), next_id };
+
+        tokio::spawn(async move {
+            while let Some(command) = receiver.recv().await {
+                self.handle(command).await;
+                count.store(self.task_count(), Ordering::Release);
+            }
+        });
+
+        handle
+    }
+
+    async fn handle(&mut self, command: Command) {
+        match command {
+            Command::AddTurtle(turtle) => self.add_turtle(&turtle),
+            Command::AddTask(id, task, kept) => self.queue(id, task, kept),
+            Command::Poll(done) => {
+                self.poll().await;
+                done.send(()).ok();
+            },
+            Command::Cancel(turtle, requeue) => {
+                self.cancel(turtle, requeue).await;
+            },
+            Command::DoOn(turtle, task, done) => {
+                done.send(self.do_on(task, turtle)).ok();
+            },
+            Command::SetRole(turtle, role) => {
+                self.set_role(turtle, role);
+            },
+            Command::SetHome(turtle, home) => {
+                self.set_home(turtle, home);
+            },
+            Command::Status(done) => {
+                done.send(self.status()).ok();
+            },
+            Command::Busy(turtle, done) => {
+                done.send(self.busy(turtle)).ok();
+            },
+            Command::Tasks(done) => {
+                done.send(self.tasks()).ok();
+            },
+            Command::Archive(done) => {
+                done.send(self.archive.iter().cloned().collect()).ok();
+            },
+            Command::Pause => self.pause(),
+            Command::Resume => self.resume(),
+            Command::Shutdown(done) => {
+                self.shutdown = Some(done);
+                self.poll().await;
+            },
+            Command::Save(pretty, done) => {
+                done.send(ron::ser::to_string_pretty(self, pretty)).ok();
+            },
+        }
+    }
+}
+
+/// Whether turtles or tasks are the bottleneck
+#[derive(Serialize, Debug, Default)]
+pub struct SchedulerStatus {
+    turtles: usize,
+    /// Turtles running a task
+    busy: usize,
+    /// Tasks that could have used a turtle but none were free
+    ready: usize,
+    /// Tasks with nothing to do yet, such as tree farms between sweeps
+    waiting: usize,
+    paused: bool,
+}
+
+impl SchedulerStatus {
+    pub fn turtles(&self) -> usize {
+        self.turtles
+    }
+
+    pub fn busy(&self) -> usize {
+        self.busy
+    }
+}
+
+/// A queued task, for listing
+#[derive(Serialize, Debug)]
+pub struct TaskInfo {
+    id: u64,
+    task: &'static str,
+    role: Role,
+    priority: i8,
+    waiting: bool,
+    progress: Option<Progress>,
+}
+
+impl TaskInfo {
+    /// Kind of task, as it is saved
+    pub fn name(&self) -> &'static str {
+        self.task
+    }
+}
+
+type AdhocTask = Box<dyn FnOnce(TurtleCommander) -> AbortHandle + Send>;
+
+/// Requests to the scheduler task, handled one at a time
+enum Command {
+    AddTurtle(TurtleCommander),
+    AddTask(u64, Box<dyn Task>, Result<String, String>),
+    Poll(oneshot::Sender<()>),
+    Cancel(Name, bool),
+    DoOn(Name, AdhocTask, oneshot::Sender<Option<()>>),
+    SetRole(Name, Role),
+    SetHome(Name, Option<Position>),
+    Status(oneshot::Sender<SchedulerStatus>),
+    Busy(Name, oneshot::Sender<bool>),
+    Tasks(oneshot::Sender<Vec<TaskInfo>>),
+    Archive(oneshot::Sender<Vec<Archived>>),
+    Pause,
+    Resume,
+    Shutdown(oneshot::Sender<()>),
+    Save(PrettyConfig, oneshot::Sender<ron::Result<String>>),
+}
+
+/// Cheap to clone handle to the scheduler task
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    sender: mpsc::UnboundedSender<Command>,
+    /// Tasks as of the last command
+    count: Arc<AtomicUsize>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl SchedulerHandle {
+    fn send(&self, command: Command) {
+        if self.sender.send(command).is_err() {
+            error!("scheduler disappeared");
+        }
+    }
+
+    /// Add a new turtle to the scheduler
+    pub fn add_turtle(&self, turtle: &TurtleCommander) {
+        self.send(Command::AddTurtle(turtle.clone()));
+    }
+
+    /// Queue a task, returning its id and whether it can be run again
+    pub fn add_task(&self, task: Box<dyn Task>) -> Added {
+        let id = self.next_id.fetch_add(1, Ordering::AcqRel);
+        // serialized here so the caller hears about a task too large to keep
+        let kept = keep(task.as_ref());
+        let unkept = kept.as_ref().err().cloned();
+        self.send(Command::AddTask(id, task, kept));
+        Added { id, unkept }
+    }
+
+    pub fn task_count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Hand out work to idle turtles, returns once done
+    pub async fn poll(&self) {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::Poll(send));
+        recv.await.ok();
+    }
+
+    pub fn cancel(&self, turtle: Name) {
+        self.send(Command::Cancel(turtle, false));
+    }
+
+    /// Stop the turtle's task so that it picks up where it left off with the next free turtle
+    pub fn requeue(&self, turtle: Name) {
+        self.send(Command::Cancel(turtle, true));
+    }
+
+    /// Run a task on a specific turtle, replacing whatever it was doing
+    pub async fn do_on<T>(&self, task: T, turtle: Name) -> Option<()>
+        where T: FnOnce(TurtleCommander) -> AbortHandle + Send + 'static
+    {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::DoOn(turtle, Box::new(task), send));
+        recv.await.ok()?
+    }
+
+    /// Only give the turtle tasks fitting its role
+    pub fn set_role(&self, turtle: Name, role: Role) {
+        self.send(Command::SetRole(turtle, role));
+    }
+
+    /// Park the turtle here when it has nothing to do, or leave it where it stops
+    pub fn set_home(&self, turtle: Name, home: Option<Position>) {
+        self.send(Command::SetHome(turtle, home));
+    }
+
+    pub async fn status(&self) -> SchedulerStatus {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::Status(send));
+        recv.await.unwrap_or_default()
+    }
+
+    /// True if the turtle is running a task, or something done on it
+    pub async fn busy(&self, turtle: Name) -> bool {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::Busy(turtle, send));
+        recv.await.unwrap_or_default()
+    }
+
+    pub async fn tasks(&self) -> Vec<TaskInfo> {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::Tasks(send));
+        recv.await.unwrap_or_default()
+    }
+
+    /// Recently finished tasks, oldest first
+    pub async fn archive(&self) -> Vec<Archived> {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::Archive(send));
+        recv.await.unwrap_or_default()
+    }
+
+    pub fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// Stop assigning tasks and wait for running ones to finish
+    pub async fn shutdown(&self) {
+        let (send, recv) = oneshot::channel();
+        self.send(Command::Shutdown(send));
+        recv.await.ok();
+    }
+
+    /// Serialize the scheduler in its current state
+    pub async fn save(&self, pretty: PrettyConfig) -> anyhow::Result<String> {
         let (send, recv) = oneshot::channel();
-        self.shutdown =  Some(send);
-        recv
+        self.send(Command::Save(pretty, send));
+        Ok(recv.await??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{blocks::Vec3, mine::{Quarry, Remove}};
+
+    fn quarry(lower: Vec3, upper: Vec3) -> Box<dyn Task> {
+        Box::new(Quarry::new(lower, upper, &Limits::default(), false).unwrap())
+    }
+
+    #[tokio::test]
+    async fn archive() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_task(0, quarry(Vec3::new(0, 0, 0), Vec3::new(3, 3, 3)));
+        let queued = scheduler.tasks.pop().unwrap();
+        scheduler.archive(queued);
+
+        // ids outlive a restart, so a rerun can't pick up a new task with the same one
+        scheduler.add_task(7, quarry(Vec3::new(8, 0, 0), Vec3::new(11, 3, 3)));
+        let saved = ron::ser::to_string(&scheduler).unwrap();
+        let loaded: Scheduler = ron::de::from_str(&saved).unwrap();
+        assert_eq!(loaded.tasks().iter().map(|t| t.id).collect::<Vec<_>>(), [7]);
+        let handle = loaded.spawn();
+        assert_eq!(handle.add_task(quarry(Vec3::new(16, 0, 0), Vec3::new(19, 3, 3))).id, 8);
+
+        let archive = handle.archive().await;
+        assert_eq!(archive.len(), 1);
+        assert_eq!(archive[0].id(), 0);
+        assert_eq!(archive[0].fresh().unwrap().typetag_name(), "Quarry");
+
+        // queued tasks keep theirs across a restart too
+        let saved = handle.save(PrettyConfig::default()).await.unwrap();
+        let loaded: Scheduler = ron::de::from_str(&saved).unwrap();
+        assert!(loaded.tasks.iter().all(|t| t.original.is_some()));
+
+        let lost = Archived { original: None, ..archive[0].clone() };
+        assert!(lost.fresh().is_err_and(|e| e.to_string().contains("restart")));
+        let large = Archived { original: None, unkept: Some("too large".into()), ..archive[0].clone() };
+        assert!(large.fresh().is_err_and(|e| e.to_string().contains("too large")));
+    }
+
+    #[tokio::test]
+    async fn simulations_stop_with_task() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_task(0, quarry(Vec3::new(0, 0, 0), Vec3::new(3, 3, 3)));
+        scheduler.add_task(1, quarry(Vec3::new(8, 0, 0), Vec3::new(11, 3, 3)));
+        let simulate = || tokio::spawn(std::future::pending::<()>());
+        let (first, second) = (simulate(), simulate());
+        scheduler.simulations.insert(0, (CancellationToken::new(), vec![first.abort_handle()]));
+        scheduler.simulations.insert(1, (CancellationToken::new(), vec![second.abort_handle()]));
+        let cancel = scheduler.simulations[&0].0.clone();
+
+        let queued = scheduler.tasks.remove(0);
+        scheduler.archive(queued);
+        assert!(cancel.is_cancelled());
+        assert!(first.await.unwrap_err().is_cancelled());
+        // only the finished task's runs are stopped
+        assert!(scheduler.simulations.contains_key(&1));
+        assert!(!second.is_finished());
+        second.abort();
+    }
+
+    #[test]
+    fn assigned_saved() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_task(3, quarry(Vec3::new(0, 0, 0), Vec3::new(3, 3, 3)));
+        scheduler.add_task(9, quarry(Vec3::new(8, 0, 0), Vec3::new(11, 3, 3)));
+        scheduler.assigned.insert(2, 9);
+
+        // saved as is, no matter where the task sits in the queue
+        let mut loaded: Scheduler = ron::de::from_str(&ron::ser::to_string(&scheduler).unwrap()).unwrap();
+        assert_eq!(loaded.number_legacy(), 10);
+        assert_eq!(loaded.assigned, scheduler.assigned);
+
+        // a task that finished elsewhere since can't be resumed
+        loaded.assigned.insert(5, 4);
+        loaded.drop_stale_assignments();
+        assert_eq!(loaded.assigned, scheduler.assigned);
+    }
+
+    #[test]
+    fn legacy_save() {
+        #[derive(Serialize)]
+        struct Old {
+            tasks: Vec<Box<dyn Task>>,
+            assigned: HashMap<u32, u64>,
+            archive: VecDeque<Archived>,
+        }
+
+        let archived = Archived { id: 4, task: "Quarry".into(), finished: OffsetDateTime::now_utc(), original: None, unkept: None };
+        let old = Old {
+            tasks: vec![
+                quarry(Vec3::new(0, 0, 0), Vec3::new(3, 3, 3)),
+                quarry(Vec3::new(8, 0, 0), Vec3::new(11, 3, 3)),
+            ],
+            assigned: HashMap::from([(3, 1)]),
+            archive: VecDeque::from([archived]),
+        };
+        let mut loaded: Scheduler = ron::de::from_str(&ron::ser::to_string(&old).unwrap()).unwrap();
+
+        // numbered after the archive, in queue order
+        assert_eq!(loaded.number_legacy(), 7);
+        assert_eq!(loaded.tasks().iter().map(|t| t.id).collect::<Vec<_>>(), [5, 6]);
+        assert_eq!(loaded.assigned[&3], 6);
+    }
+
+    #[test]
+    fn priority() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_task(0, quarry(Vec3::new(0, 0, 0), Vec3::new(3, 3, 3)));
+        scheduler.add_task(1, quarry(Vec3::new(8, 0, 0), Vec3::new(11, 3, 3)));
+        scheduler.add_task(2, Box::new(Remove::new(Vec3::zeros(), "ore".into(), &Limits::default(), false).unwrap()));
+        scheduler.tasks[1].ready_since = Some(Instant::now() - STARVATION);
+
+        // starving only counts among tasks of the same priority
+        assert_eq!(scheduler.order(), [2, 1, 0]);
+    }
+
+    #[tokio::test]
+    async fn starving_needs_work() {
+        let mut scheduler = Scheduler::default();
+        scheduler.add_task(0, quarry(Vec3::new(0, 0, 0), Vec3::new(3, 3, 3)));
+        scheduler.add_task(1, quarry(Vec3::new(8, 0, 0), Vec3::new(11, 3, 3)));
+        scheduler.tasks[0].waiting = true;
+        scheduler.tasks[0].ready_since = Some(Instant::now() - STARVATION);
+
+        // no turtles, so neither is polled
+        scheduler.poll().await;
+        assert!(scheduler.tasks[0].ready_since.is_none());
+        assert!(scheduler.tasks[1].ready_since.is_some());
+
+        let status = scheduler.status();
+        assert_eq!((status.ready, status.waiting), (1, 1));
     }
 }
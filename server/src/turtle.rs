@@ -1,44 +1,55 @@
 use crate::blocks::Block;
+use crate::blocks::Coords;
 use crate::blocks::Direction;
 use crate::blocks::Position;
 use crate::blocks::Vec3;
 use crate::blocks::SharedWorld;
+use crate::blocks::Occupancy;
 use crate::depot::DepotGuard;
 use crate::depot::Depots;
-use crate::paths::route_facing;
-use crate::tasks::Scheduler;
+use crate::depot;
+use crate::paths::{route_to_any, Approach, Avoid};
+use crate::paths;
+use crate::tasks::{SchedulerHandle, Role};
 
 use anyhow::Ok;
 
 use anyhow;
 use anyhow::Context;
-use tokio::sync::OwnedMutexGuard;
 use tracing::error;
 use tracing::trace;
 use tracing::warn;
 use tracing::info;
-use tokio::sync::Mutex;
 use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 
 use tokio::time::timeout;
+use tokio::time::Instant;
 
 use super::LiveState;
 
 use core::fmt;
+use std::collections::{VecDeque, BTreeMap, HashMap};
 use std::sync::Arc;
 use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
 use std::time::Duration;
+use std::iter::Peekable;
 
 
 use super::names::Name;
 
 use serde::Deserialize;
 use serde::Serialize;
+use time::OffsetDateTime;
 
-use super::paths::route;
+use super::paths::route_with;
 
 /// Time (ms) to wait for a command before letting a turtle go idle
 const COMMAND_TIMEOUT:  u64 = 0o372;
@@ -46,22 +57,124 @@ const COMMAND_TIMEOUT:  u64 = 0o372;
 pub const IDLE_TIME: u32 = 3;
 /// Times to attempt a route before giving up 
 pub const RETRIES: usize = 42;
+/// Time (s) a requested goto gets before it is given up on
+pub const GOTO_TIMEOUT: u64 = 600;
+/// Time (s) without updates before a turtle is considered gone
+pub const STALE_TIME: u64 = 30;
+/// Time (s) to let another turtle by after stepping out of its way
+const GIVE_WAY_TIME: u32 = 2;
+/// Wear at which a pickaxe is traded in, before it breaks mid-chunk
+const TOOL_WORN: f64 = 0.9;
+/// Blocks dug between looks at the pickaxe, well under the uses left on one at TOOL_WORN
+const TOOL_CHECK_DIGS: u32 = 64;
+/// Slots in use at which a turtle heads back to unload, unless set per turtle
+pub const FULL_SLOTS: u32 = 12;
+/// Items in a full stack, some items stack to 16 but those are rarely mined
+const STACK_SIZE: u32 = 64;
+/// Inventory slots (from 0) that make up the crafting grid, row by row
+const CRAFTING_GRID: [usize; 9] = [0, 1, 2, 4, 5, 6, 8, 9, 10];
+/// Commands remembered per turtle for debugging
+const HISTORY_LENGTH: usize = 32;
+/// Time (s) between GPS fixes checking the tracked position
+const LOCATE_INTERVAL: u64 = 60;
+/// Blocks a GPS fix can be off from the tracked position before it is believed instead
+const DRIFT_TOLERANCE: i32 = 0;
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct Turtle {
     pub(crate) name: Name,
     pub(crate) fuel: usize,
     pub(crate) fuel_limit: usize,
+    /// Fuel burned over the turtle's lifetime, refueling doesn't count
+    #[serde(default)]
+    pub(crate) fuel_used: usize,
+    #[serde(default)]
+    pub(crate) role: Role,
+    /// Where to park when there is nothing to do
+    #[serde(default)]
+    pub(crate) home: Option<Position>,
+    /// Slots that can fill before unloading, lower for turtles that keep
+    /// fuel, blocks, or torches on hand. Shared with commanders
+    #[serde(default = "full_slots")]
+    pub(crate) usable_slots: Arc<AtomicU32>,
     /// movement vector of last given command
     pub(crate) queued_movement: Vec3,
     pub(crate) position: Position,
     pub(crate) pending_update: bool,
+    /// Time of the last update from the client
+    #[serde(skip)]
+    pub(crate) last_seen: Option<Instant>,
     #[serde(skip)]
     callback: Option<oneshot::Sender<TurtleInfo>>,
     #[serde(skip)]
     sender: Option<Arc<Sender>>,
     #[serde(skip)]
     receiver: Option<Receiver>,
+    /// Most recent commands, oldest first
+    #[serde(skip)]
+    pub(crate) history: VecDeque<HistoryEntry>,
+    /// Sent along with each command and echoed back with its result
+    #[serde(skip)]
+    pub(crate) token: u64,
+    /// Command sent with the current token that hasn't been answered
+    #[serde(skip)]
+    in_flight: Option<TurtleCommand>,
+    /// Sent Poweroff and hasn't been heard from since, shared with commanders
+    #[serde(skip)]
+    powered_off: Arc<AtomicBool>,
+    /// When the command in flight was handed out
+    #[serde(skip)]
+    sent_at: Option<Instant>,
+    /// When the turtle was last asked for a GPS fix
+    #[serde(skip)]
+    pub(crate) located: Option<Instant>,
+    /// The last GPS fix failed, such as for want of a modem, so no more are asked for until it registers again
+    #[serde(skip)]
+    pub(crate) no_gps: bool,
+    /// Shared with commanders, so any of them can use what another scanned
+    #[serde(skip)]
+    inventory: Arc<InventoryCache>,
+    /// Shared with commanders, as any of them may dig
+    #[serde(skip)]
+    tool: Arc<ToolWear>,
+    #[serde(skip)]
+    pub(crate) stats: LinkStats,
+}
+
+/// Health of the link to a turtle since the server started
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct LinkStats {
+    /// Commands handed to the turtle
+    sent: usize,
+    /// Commands the turtle reported failing
+    failures: usize,
+    /// Updates dropped for carrying an old token, usually resends
+    stale: usize,
+    /// Times the turtle registered again
+    reconnects: usize,
+    /// Mean time (ms) from handing out a command to hearing back
+    latency: Option<u128>,
+    #[serde(skip)]
+    answered: u128,
+    #[serde(skip)]
+    total_latency: Duration,
+}
+
+impl LinkStats {
+    fn answer(&mut self, latency: Duration) {
+        self.answered += 1;
+        self.total_latency += latency;
+        self.latency = Some(self.total_latency.as_millis() / self.answered);
+    }
+}
+
+/// A command sent to a turtle and what came of it
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct HistoryEntry {
+    command: TurtleCommand,
+    /// None until the turtle reports back
+    response: Option<TurtleCommandResponse>,
+    sent: OffsetDateTime,
 }
 
 #[derive(Debug)]
@@ -83,6 +196,42 @@ impl TurtleInfo {
     }
 }
 
+fn full_slots() -> Arc<AtomicU32> {
+    Arc::new(AtomicU32::new(FULL_SLOTS))
+}
+
+/// What is known of a turtle's pickaxe, so it is only looked at once it may have worn down
+#[derive(Default)]
+struct ToolWear {
+    /// Blocks dug since the tools were last looked at
+    dug: AtomicU32,
+    /// Side found worn at the last look, until it is traded in
+    worn: std::sync::Mutex<Option<TurtleCommand>>,
+}
+
+/// What a turtle is carrying, as of the last scan and the commands since
+#[derive(Default)]
+pub(crate) struct InventoryCache {
+    slots: RwLock<Option<Vec<Option<InventorySlot>>>>,
+    /// Scans started, for those waiting on one to tell if it began after they asked
+    scans: AtomicU64,
+    /// Selected slot, 0 if unknown
+    selected: AtomicU32,
+    /// Times the cache was thrown out, so a scan that was underway doesn't put back what it saw
+    resets: AtomicU64,
+}
+
+impl InventoryCache {
+    /// Forget everything, such as once the turtle reboots and may have been refilled by hand
+    fn invalidate(&self) {
+        self.resets.fetch_add(1, SeqCst);
+        self.selected.store(0, SeqCst);
+        if let Ok(mut slots) = self.slots.try_write() {
+            *slots = None;
+        }
+    }
+}
+
 pub type Sender = mpsc::Sender<(TurtleCommand, oneshot::Sender<TurtleInfo>)>;
 pub type Receiver = mpsc::Receiver<(TurtleCommand, oneshot::Sender<TurtleInfo>)>;
 
@@ -93,17 +242,89 @@ impl Default for Turtle {
             name: Name::from_num(0),
             fuel: Default::default(),
             fuel_limit: Default::default(),
+            fuel_used: Default::default(),
+            role: Default::default(),
+            home: None,
+            usable_slots: full_slots(),
             queued_movement: Default::default(),
             position: Position::new(Vec3::zeros(), Direction::North),
             pending_update: Default::default(),
+            last_seen: None,
             callback: None,
             sender: Some(Arc::new(sender)),
             receiver: Some(receiver),
+            history: VecDeque::new(),
+            token: 0,
+            in_flight: None,
+            powered_off: Default::default(),
+            sent_at: None,
+            located: None,
+            no_gps: false,
+            stats: Default::default(),
+            inventory: Default::default(),
+            tool: Default::default(),
         }
     }
 }
 
 impl Turtle {
+    /// Note down a command being handed to the client
+    fn dispatch(&mut self, cmd: TurtleCommand) -> TurtleCommand {
+        self.queued_movement = cmd.unit(self.position.dir);
+        self.token += 1;
+        self.in_flight = Some(cmd.clone());
+        self.sent_at = Some(Instant::now());
+        self.stats.sent += 1;
+        info!("{}: {cmd:?}", self.name.to_str());
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back(HistoryEntry {
+            command: cmd.clone(),
+            response: None,
+            sent: OffsetDateTime::now_utc(),
+        });
+        cmd
+    }
+
+    /// Follow the turtle by the fuel burned carrying out a command
+    fn moved(&mut self, command: Option<&TurtleCommand>, fuel: usize) {
+        if self.fuel > fuel {
+            let diff = self.fuel - fuel;
+            self.fuel_used += diff;
+
+            // a stair goes two ways, so follow the command if it is known
+            let delta = match command {
+                Some(cmd) => cmd.moves(self.position.dir).into_iter().take(diff).fold(Vec3::zeros(), |a, b| a + b),
+                None => self.queued_movement * diff as i32,
+            };
+
+            self.position.pos += delta;
+            self.queued_movement = Vec3::zeros();
+        }
+        self.fuel = fuel;
+    }
+
+    /// Hand out the next command from a commander, if one comes in time
+    async fn receive(&mut self, tasks: &SchedulerHandle, wait: Duration) -> Option<TurtleCommand> {
+        let recv = self.receiver.as_mut()?;
+        let (cmd, ret) = timeout(wait, recv.recv()).await.ok().flatten()?;
+        self.callback = Some(ret);
+
+        match cmd {
+            TurtleCommand::Left => self.position.dir = self.position.dir.left(),
+            TurtleCommand::Right => self.position.dir = self.position.dir.right(),
+            _ => {}
+        }
+        if let TurtleCommand::Poweroff = cmd {
+            // nothing will come back until someone turns it on again
+            info!("{} powering off", self.name.to_str());
+            self.powered_off.store(true, SeqCst);
+            tasks.cancel(self.name);
+        }
+        Some(self.dispatch(cmd))
+    }
+
     pub(crate) fn new(id: u32, position: Position, fuel: usize, fuel_limit: usize) -> Self {
         Self {
             name: Name::from_num(id),
@@ -123,6 +344,10 @@ impl Turtle {
             name: self.name,
             fuel: self.fuel,
             fuel_limit: self.fuel_limit,
+            fuel_used: self.fuel_used,
+            role: self.role,
+            home: self.home,
+            usable_slots: self.usable_slots.clone(),
             position: self.position,
             pending_update: self.pending_update,
             queued_movement: self.queued_movement,
@@ -144,6 +369,69 @@ impl Turtle {
 
         }
     }
+
+    /// True if the turtle hasn't been heard from in a while
+    pub fn stale(&self) -> bool {
+        self.last_seen.map_or(true, |t| t.elapsed() > Duration::from_secs(STALE_TIME))
+    }
+
+    /// True if the turtle said it was shutting down and hasn't come back since
+    pub fn powered_off(&self) -> bool {
+        self.powered_off.load(SeqCst)
+    }
+
+    /// Take over this entry for a turtle that registered again
+    ///
+    /// Any command still waiting on the old turtle is dropped
+    pub fn reregister(&mut self, position: Position, fuel: usize, fuel_limit: usize) {
+        self.position = position;
+        self.fuel = fuel;
+        self.fuel_limit = fuel_limit;
+        self.queued_movement = Vec3::zeros();
+        self.pending_update = true;
+        self.callback = None;
+        self.in_flight = None;
+        self.sent_at = None;
+        self.powered_off.store(false, SeqCst);
+        self.inventory.invalidate();
+        // it may have come back with a modem
+        self.no_gps = false;
+        self.stats.reconnects += 1;
+    }
+}
+
+/// Tally of what simulated turtles would have done
+///
+/// Simulated turtles share a copy of the world taken on first use,
+/// so their digs and placements don't leak into the real one
+#[derive(Default)]
+pub struct DryRun {
+    world: OnceCell<SharedWorld>,
+    commands: AtomicUsize,
+    moves: AtomicUsize,
+    digs: AtomicUsize,
+    places: AtomicUsize,
+    docks: AtomicUsize,
+    /// Places that couldn't be routed to
+    unreachable: std::sync::Mutex<Vec<Vec3>>,
+}
+
+impl fmt::Display for DryRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} commands, {} moves, {} digs, {} places, {} docks",
+            self.commands.load(SeqCst),
+            self.moves.load(SeqCst),
+            self.digs.load(SeqCst),
+            self.places.load(SeqCst),
+            self.docks.load(SeqCst),
+        )?;
+        let unreachable = self.unreachable.lock().unwrap();
+        if !unreachable.is_empty() {
+            let spots: Vec<String> = unreachable.iter().map(|p| Coords(*p).to_string()).collect();
+            write!(f, ", unreachable: {}", spots.join(" "))?;
+        }
+        std::fmt::Result::Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -151,14 +439,28 @@ pub struct TurtleCommander {
     sender: Arc<Sender>,
     world: SharedWorld,
     depots: Depots,
+    positions: Positions,
     // everything below is best-effort
     // TODO: make not bad
     pos: Arc<RwLock<Position>>,
     fuel: Arc<AtomicUsize>,
     max_fuel: Arc<AtomicUsize>,
     name: Arc<OnceCell<Name>>,
-    inventory: Arc<RwLock<Option<Vec<Option<InventorySlot>>>>>,
-    tasks: Arc<Mutex<Scheduler>>, // this feels subpar, I feel like a mpsc would do better
+    inventory: Arc<InventoryCache>,
+    tool: Arc<ToolWear>,
+    tasks: SchedulerHandle,
+    role: Role,
+    home: Option<Position>,
+    powered_off: Arc<AtomicBool>,
+    usable_slots: Arc<AtomicU32>,
+    /// Set if commands are only simulated
+    dry_run: Option<Arc<DryRun>>,
+    /// Asks the task holding this copy to wrap up
+    cancel: CancellationToken,
+    /// Depot to unload at, None for the nearest
+    output: Option<Vec3>,
+    /// Blocks never dug or routed through
+    avoid: Option<Avoid>,
 }
 
 impl fmt::Debug for TurtleCommander {
@@ -181,8 +483,18 @@ impl TurtleCommander {
             max_fuel: Arc::new(AtomicUsize::new(turtle.fuel_limit)),
             name: Arc::new(OnceCell::new_with(Some(turtle.name))),
             depots: state.depots.clone(),
-            inventory: Default::default(),
+            positions: state.positions.clone(),
+            inventory: turtle.inventory.clone(),
+            tool: turtle.tool.clone(),
             tasks: state.tasks.clone(),
+            role: turtle.role,
+            home: turtle.home,
+            powered_off: turtle.powered_off.clone(),
+            usable_slots: turtle.usable_slots.clone(),
+            dry_run: None,
+            cancel: CancellationToken::new(),
+            output: None,
+            avoid: None,
         })
     }
 
@@ -195,13 +507,65 @@ impl TurtleCommander {
             max_fuel: Arc::new(AtomicUsize::new(turtle.fuel_limit)),
             name: Arc::new(OnceCell::new_with(Some(turtle.name))),
             depots: state.depots.clone(),
-            inventory: Default::default(),
+            positions: state.positions.clone(),
+            inventory: turtle.inventory.clone(),
+            tool: turtle.tool.clone(),
             tasks: state.tasks.clone(),
+            role: turtle.role,
+            home: turtle.home,
+            powered_off: turtle.powered_off.clone(),
+            usable_slots: turtle.usable_slots.clone(),
+            dry_run: None,
+            cancel: CancellationToken::new(),
+            output: None,
+            avoid: None,
+        }
+    }
+
+    /// A copy of this turtle that only pretends to carry out commands,
+    /// tallying them in the report
+    pub async fn simulated(&self, report: Arc<DryRun>) -> TurtleCommander {
+        let world = report.world.get_or_init(|| async {
+            self.world.detached().await
+        }).await.clone();
+        let inventory = self.inventory.slots.read().await.clone().unwrap_or_else(|| vec![None; 16]);
+
+        TurtleCommander {
+            // detached, so nothing can reach the real turtle
+            sender: Arc::new(mpsc::channel(1).0),
+            world,
+            depots: self.depots.clone(),
+            positions: self.positions.clone(),
+            pos: Arc::new(RwLock::new(self.pos().await)),
+            fuel: Arc::new(AtomicUsize::new(self.fuel())),
+            max_fuel: self.max_fuel.clone(),
+            name: self.name.clone(),
+            inventory: Arc::new(InventoryCache {
+                slots: RwLock::new(Some(inventory)),
+                scans: Default::default(),
+                selected: AtomicU32::new(self.inventory.selected.load(std::sync::atomic::Ordering::SeqCst)),
+                resets: Default::default(),
+            }),
+            // dry runs dig nothing, so never look at the tools
+            tool: Default::default(),
+            tasks: self.tasks.clone(),
+            role: self.role,
+            home: self.home,
+            powered_off: self.powered_off.clone(),
+            usable_slots: self.usable_slots.clone(),
+            dry_run: Some(report),
+            cancel: self.cancel.clone(),
+            output: self.output,
+            avoid: self.avoid.clone(),
         }
     }
 
     #[tracing::instrument]
     pub async fn execute(&self, command: TurtleCommand) -> TurtleInfo {
+        if let Some(report) = &self.dry_run {
+            return self.simulate(report, command).await;
+        }
+
         let (send, recv) = oneshot::channel::<TurtleInfo>();
 
         if let Err(_) = self.sender.to_owned().send((command.clone(),send)).await {
@@ -211,24 +575,24 @@ impl TurtleCommander {
 
         let resp = recv.await.unwrap_or_else(|_| {
             error!("server disappearing"); 
-            TurtleInfo::from_update(TurtleUpdate { fuel: self.fuel(), ahead: "".into(), above: "".into(), below: "".into(), ret: TurtleCommandResponse::Failure }, self.name(), Position::new(Vec3::zeros(), Direction::North))
+            TurtleInfo::from_update(TurtleUpdate { fuel: self.fuel(), ahead: "".into(), above: "".into(), below: "".into(), ret: TurtleCommandResponse::Failure(FailureReason::Other("server disappearing".into())), token: None }, self.name(), Position::new(Vec3::zeros(), Direction::North))
         });
 
-        // invalidate inventory when we run commands that modify it
         // this is not safe if you make a second TurtleCommander
-        if let TurtleCommandResponse::Success = resp.ret {
-            if match command {
-                TurtleCommand::Wait(_) => false,
-                TurtleCommand::Forward(_) => false,
-                TurtleCommand::Backward(_) => false,
-                TurtleCommand::Up(_) => false,
-                TurtleCommand::Down(_) => false,
-                TurtleCommand::Left => false,
-                TurtleCommand::Right => false,
-                _ => true,
-            } {
-                *self.inventory.write().await = None;
-            }
+        match (&command, &resp.ret) {
+            (TurtleCommand::Batch(commands), TurtleCommandResponse::Batch(steps)) => {
+                for (command, step) in commands.iter().zip(steps) {
+                    self.track_wear(command, &step.ret);
+                    if let TurtleCommandResponse::Success = step.ret {
+                        self.track_inventory(command).await;
+                    }
+                }
+            },
+            (_, TurtleCommandResponse::Success) => {
+                self.track_wear(&command, &resp.ret);
+                self.track_inventory(&command).await;
+            },
+            (_, ret) => self.track_wear(&command, ret),
         }
 
         let mut pos = self.pos.write().await;
@@ -237,6 +601,342 @@ impl TurtleCommander {
         resp
     }
 
+    /// Run several commands in one round trip, stopping at the first to fail
+    ///
+    /// Returns what each of them that ran came back with
+    pub(crate) async fn execute_batch(&self, commands: Vec<TurtleCommand>) -> Vec<TurtleCommandResponse> {
+        match self.execute(TurtleCommand::Batch(commands)).await.ret {
+            TurtleCommandResponse::Batch(steps) => steps.into_iter().map(|s| s.ret).collect(),
+            // the server went away before any ran
+            other => vec![other],
+        }
+    }
+
+    /// Carry out a command against the simulated world
+    async fn simulate(&self, report: &DryRun, command: TurtleCommand) -> TurtleInfo {
+        use TurtleCommand::*;
+
+        let mut pos = self.pos().await;
+        let mut fuel = self.fuel();
+        let mut ret = TurtleCommandResponse::Success;
+        report.commands.fetch_add(1, SeqCst);
+        trace!("dry run at {pos}: {command:?}");
+
+        let target = match command {
+            Dig | Place | PlaceFrom(_) => Some(pos.pos + pos.dir.unit()),
+            DigUp | PlaceUp | PlaceUpFrom(_) => Some(pos.pos + Vec3::y()),
+            DigDown | PlaceDown | PlaceDownFrom(_) => Some(pos.pos - Vec3::y()),
+            _ => None,
+        };
+
+        match command {
+            Left => pos.dir = pos.dir.left(),
+            Right => pos.dir = pos.dir.right(),
+            Forward(_) | Backward(_) | Up(_) | Down(_) | ForwardUp | ForwardDown => {
+                for step in command.moves(pos.dir) {
+                    let next = pos.pos + step;
+                    if self.world.occupied(next).await {
+                        ret = TurtleCommandResponse::Failure(FailureReason::Obstructed);
+                        break;
+                    }
+                    if fuel == 0 {
+                        ret = TurtleCommandResponse::Failure(FailureReason::NoFuel);
+                        break;
+                    }
+                    pos.pos = next;
+                    fuel -= 1;
+                    report.moves.fetch_add(1, SeqCst);
+                }
+            },
+            Dig | DigUp | DigDown => {
+                let target = target.unwrap();
+                if self.world.occupied(target).await {
+                    self.world.set(Block::new("minecraft:air", target)).await;
+                    report.digs.fetch_add(1, SeqCst);
+                } else {
+                    ret = TurtleCommandResponse::Failure(FailureReason::NothingToDig);
+                }
+            },
+            Place | PlaceUp | PlaceDown | PlaceFrom(_) | PlaceUpFrom(_) | PlaceDownFrom(_) => {
+                let target = target.unwrap();
+                if self.world.occupied(target).await {
+                    ret = TurtleCommandResponse::Failure(FailureReason::Denied);
+                } else {
+                    self.world.set(Block::new("dry_run:placed", target)).await;
+                    report.places.fetch_add(1, SeqCst);
+                }
+            },
+            Refuel | RefuelFrom(..) => fuel = self.fuel_limit(),
+            Batch(commands) => {
+                let mut steps = Vec::new();
+                for command in commands {
+                    let step = Box::pin(self.simulate(report, command)).await;
+                    let failed = matches!(step.ret, TurtleCommandResponse::Failure(_));
+                    steps.push(TurtleUpdate { fuel: step.fuel, ahead: step.ahead, above: step.above, below: step.below, ret: step.ret, token: None });
+                    if failed {
+                        break;
+                    }
+                }
+                pos = self.pos().await;
+                fuel = self.fuel();
+                ret = TurtleCommandResponse::Batch(steps);
+            },
+            _ => {},
+        }
+
+        let name = |at: Vec3| async move {
+            self.world.get(at).await.map(|b| b.name).unwrap_or_default()
+        };
+        let update = TurtleUpdate {
+            fuel,
+            ahead: name(pos.pos + pos.dir.unit()).await,
+            above: name(pos.pos + Vec3::y()).await,
+            below: name(pos.pos - Vec3::y()).await,
+            ret,
+            token: None,
+        };
+
+        *self.pos.write().await = pos;
+        self.fuel.store(fuel, SeqCst);
+        TurtleInfo::from_update(update, self.name(), pos)
+    }
+
+    /// Record a place a simulated turtle couldn't get to
+    fn unreachable(&self, pos: Vec3) {
+        if let Some(report) = &self.dry_run {
+            report.unreachable.lock().unwrap().push(pos);
+        }
+    }
+
+    /// Update the cached inventory after a successful command
+    ///
+    /// Drops and places only take from the selected slot, anything else
+    /// that touches the inventory invalidates it for a rescan
+    async fn track_inventory(&self, command: &TurtleCommand) {
+        use TurtleCommand::*;
+
+        match command {
+            Wait(_) | WaitUntil(..) | Forward(_) | Backward(_) | Up(_) | Down(_) | ForwardUp | ForwardDown | Left | Right => return,
+            ItemInfo(_) | ItemDetail(_) => return, // inventory() holds the lock while scanning
+            EquippedLeft | EquippedRight | Inspect | InspectUp | InspectDown | Locate => return,
+            Select(slot) => {
+                self.inventory.selected.store(*slot, std::sync::atomic::Ordering::SeqCst);
+                return;
+            },
+            PlaceFrom(slot) | PlaceUpFrom(slot) | PlaceDownFrom(slot) | RefuelFrom(slot, _) => {
+                self.inventory.selected.store(*slot, std::sync::atomic::Ordering::SeqCst);
+            },
+            _ => {},
+        }
+
+        fn take(slot: &mut Option<InventorySlot>, count: u32) {
+            if let Some(item) = slot {
+                item.count = item.count.saturating_sub(count);
+                if item.count == 0 {
+                    *slot = None;
+                }
+            }
+        }
+
+        let selected = self.inventory.selected.load(std::sync::atomic::Ordering::SeqCst) as usize;
+        let mut inventory = self.inventory.slots.write().await;
+        let slot = inventory.as_mut()
+            .filter(|_| (1..=16).contains(&selected))
+            .map(|i| &mut i[selected - 1])
+            // buckets stay behind when emptied
+            .filter(|s| !s.as_ref().is_some_and(|s| s.name.contains("bucket")));
+
+        let known = match (command, slot) {
+            (DropFront(count) | DropUp(count) | DropDown(count), Some(slot)) => {
+                take(slot, *count);
+                true
+            },
+            (Place | PlaceUp | PlaceDown | PlaceFrom(_) | PlaceUpFrom(_) | PlaceDownFrom(_), Some(slot)) => {
+                take(slot, 1);
+                true
+            },
+            (RefuelFrom(_, count), Some(slot)) => {
+                take(slot, *count);
+                true
+            },
+            (Refuel, Some(slot)) => { // burns the whole stack
+                *slot = None;
+                true
+            },
+            _ => false,
+        };
+
+        if !known {
+            *inventory = None;
+        }
+    }
+
+    /// Count blocks dug towards the next look at the pickaxe
+    fn track_wear(&self, command: &TurtleCommand, ret: &TurtleCommandResponse) {
+        if !matches!(command, TurtleCommand::Dig | TurtleCommand::DigUp | TurtleCommand::DigDown) {
+            return;
+        }
+        match ret {
+            TurtleCommandResponse::Success => { self.tool.dug.fetch_add(1, SeqCst); },
+            // "No tool to dig with" and the like, so look at once
+            TurtleCommandResponse::Failure(FailureReason::Other(reason)) if reason.contains("tool") => {
+                self.tool.dug.store(TOOL_CHECK_DIGS, SeqCst);
+            },
+            _ => {},
+        }
+    }
+
+    /// Equip command for the side holding a pickaxe at or past TOOL_WORN
+    ///
+    /// Only looks every TOOL_CHECK_DIGS blocks or after a dig that wanted a tool,
+    /// otherwise this is what the last look found
+    pub async fn worn_tool(&self) -> Option<TurtleCommand> {
+        if self.tool.dug.load(SeqCst) >= TOOL_CHECK_DIGS {
+            self.tool.dug.store(0, SeqCst);
+            // never taken off to look at, as this runs in the middle of tasks
+            let equipped = self.equipped(false).await.unwrap_or_default();
+            let worn = [(TurtleCommand::EquipLeft, equipped.left), (TurtleCommand::EquipRight, equipped.right)].into_iter()
+                .find(|(_, tool)| tool.as_ref().is_some_and(|t| t.name.contains("pickaxe") && t.wear().is_some_and(|w| w >= TOOL_WORN)));
+            if let Some((_, Some(tool))) = &worn {
+                warn!("#{}'s {} is worn out", self.name().to_num(), tool.name);
+            }
+            *self.tool.worn.lock().unwrap() = worn.map(|(side, _)| side);
+        }
+        self.tool.worn.lock().unwrap().clone()
+    }
+
+    /// What is on each side of the turtle
+    ///
+    /// Clients too old to say have each side taken off to look at if `probe` is set, which needs
+    /// an empty slot and shouldn't be done in the middle of a task. None if that couldn't be done
+    pub(crate) async fn equipped(&self, probe: bool) -> Option<Equipped> {
+        let mut equipped = Equipped::default();
+        let sides = [
+            (TurtleCommand::EquippedLeft, TurtleCommand::EquipLeft, &mut equipped.left),
+            (TurtleCommand::EquippedRight, TurtleCommand::EquipRight, &mut equipped.right),
+        ];
+        for (ask, side, slot) in sides {
+            *slot = match self.execute(ask).await.ret {
+                TurtleCommandResponse::Item(item) => Some(item),
+                TurtleCommandResponse::None => None,
+                _ if !probe => return None,
+                _ => {
+                    let empty = self.inventory().await.iter().position(|s| s.is_none())? as u32 + 1;
+                    self.execute(TurtleCommand::Select(empty)).await;
+                    self.execute(side.clone()).await;
+                    let item = self.execute(TurtleCommand::ItemDetail(empty)).await.ret;
+                    // put it back
+                    self.execute(side).await;
+                    match item {
+                        TurtleCommandResponse::Item(item) => Some(item),
+                        _ => None,
+                    }
+                },
+            };
+        }
+        Some(equipped)
+    }
+
+    /// Trade the tool on `side` for a spare pickaxe, the old one goes in a chest below
+    ///
+    /// Spares come from the chest above at depots that keep a tool chest, otherwise from the
+    /// fuel chest ahead. Returns false if there was no spare, leaving the old tool on to use
+    /// until it breaks, and not looking for another until it has dug a while longer
+    pub async fn swap_tool(&self, side: TurtleCommand, tool_chest: bool) -> bool {
+        let pickaxe = |s: &Option<InventorySlot>| s.as_ref().is_some_and(|s| s.name.contains("pickaxe"));
+        let (take, give_back, chest) = match tool_chest {
+            true => (TurtleCommand::SuckUp(64), TurtleCommand::DropUp(64), depot::ChestRole::Tools),
+            false => (TurtleCommand::SuckFront(64), TurtleCommand::DropFront(64), depot::ChestRole::Fuel),
+        };
+
+        // chests hand out their slots in order, so take until a pickaxe turns up
+        let mut found = None;
+        for _ in 0..16 {
+            if let TurtleCommandResponse::Failure(_) = self.execute(take.clone()).await.ret {
+                break;
+            }
+            found = self.inventory().await.iter().position(pickaxe);
+            if found.is_some() {
+                break;
+            }
+        }
+
+        // anything else goes back
+        let pos = self.pos().await.pos;
+        for (i, _) in self.inventory().await.iter().enumerate().filter(|(i, s)| s.is_some() && Some(*i) != found) {
+            self.execute(TurtleCommand::Select(i as u32 + 1)).await;
+            if let TurtleCommandResponse::Success = self.execute(give_back.clone()).await.ret {
+                self.depots.dropped(pos, chest, 1).await;
+            }
+        }
+
+        *self.tool.worn.lock().unwrap() = None;
+        let Some(slot) = found else {
+            warn!("no spare pickaxe at {}, carrying on", self.pos().await);
+            return false;
+        };
+        self.execute(TurtleCommand::Select(slot as u32 + 1)).await;
+        self.execute(side).await;
+        if let TurtleCommandResponse::Success = self.execute(TurtleCommand::DropDown(64)).await.ret {
+            self.depots.dropped(pos, depot::ChestRole::Output, 1).await;
+        }
+        info!("#{} has a fresh pickaxe", self.name().to_num());
+        true
+    }
+
+    /// Nearest depot with spare pickaxes, by where it is docked at
+    pub async fn tool_depot(&self) -> Option<Vec3> {
+        self.depots.with_tools(self.pos().await.pos).await
+    }
+
+    /// Craft up to `count` times from a recipe laid out like a crafting table, row by row
+    ///
+    /// Needs a crafting table equipped. Nothing is moved unless there is enough of every
+    /// ingredient and nothing else is carried, so a failure leaves the inventory as it was
+    pub(crate) async fn craft(&self, recipe: &[Option<String>; 9], count: u32) -> TurtleCommandResponse {
+        let moves = match arrange(&self.inventory().await, recipe, count) {
+            Result::Ok(moves) => moves,
+            Err(reason) => return TurtleCommandResponse::Failure(reason),
+        };
+        for (from, to, moved) in moves {
+            self.execute(TurtleCommand::Select(from)).await;
+            if let TurtleCommandResponse::Failure(reason) = self.execute(TurtleCommand::TransferTo(to, moved)).await.ret {
+                error!("moving {moved} from slot {from} to {to} for crafting failed: {reason:?}");
+                return TurtleCommandResponse::Failure(reason);
+            }
+        }
+        self.execute(TurtleCommand::Craft(count)).await.ret
+    }
+
+    /// Merge partial stacks of the same item, returning the slots still in use
+    pub async fn compact_inventory(&self) -> u32 {
+        let mut inventory = self.inventory().await;
+
+        for to in 0..inventory.len() {
+            for from in to + 1..inventory.len() {
+                let (Some(dest), Some(src)) = (&inventory[to], &inventory[from]) else {
+                    continue;
+                };
+                if dest.name != src.name || dest.count >= STACK_SIZE {
+                    continue;
+                }
+                let moved = src.count.min(STACK_SIZE - dest.count);
+                self.execute(TurtleCommand::Select(from as u32 + 1)).await;
+                if let TurtleCommandResponse::Failure(_) = self.execute(TurtleCommand::TransferTo(to as u32 + 1, moved)).await.ret {
+                    continue;
+                }
+                inventory[to].as_mut().unwrap().count += moved;
+                let src = inventory[from].as_mut().unwrap();
+                src.count -= moved;
+                if src.count == 0 {
+                    inventory[from] = None;
+                }
+            }
+        }
+
+        inventory.iter().filter(|s| s.is_some()).count() as u32
+    }
+
     pub fn name(&self) -> Name {
         self.name.get().unwrap().clone()
     }
@@ -253,21 +953,100 @@ impl TurtleCommander {
         self.max_fuel.load(std::sync::atomic::Ordering::SeqCst)
     }
 
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn home(&self) -> Option<Position> {
+        self.home
+    }
+
+    /// Only changes this copy, go through the scheduler to change where the turtle parks
+    pub fn set_home(&mut self, home: Option<Position>) {
+        self.home = home;
+    }
+
+    /// True once `used` slots is enough to head back and unload
+    pub fn full(&self, used: u32) -> bool {
+        used > self.usable_slots.load(SeqCst)
+    }
+
+    /// True from sending Poweroff until the turtle is heard from again
+    pub fn powered_off(&self) -> bool {
+        self.powered_off.load(SeqCst)
+    }
+
+    /// A copy for a task that can be asked to stop through the token
+    pub fn cancellable(&self, cancel: CancellationToken) -> TurtleCommander {
+        TurtleCommander { cancel, ..self.clone() }
+    }
+
+    /// A copy that never digs or routes through the blocks `avoid` holds for,
+    /// such as the walls a quarry leaves standing
+    pub fn avoiding(&self, avoid: Option<Avoid>) -> TurtleCommander {
+        TurtleCommander { avoid, ..self.clone() }
+    }
+
+    /// True if the block is to be left alone
+    fn avoids(&self, block: Vec3) -> bool {
+        self.avoid.as_ref().is_some_and(|a| a(block))
+    }
+
+    /// A copy that unloads at the depot docked at `output` while it is registered
+    pub fn with_output(&self, output: Option<Vec3>) -> TurtleCommander {
+        TurtleCommander { output, ..self.clone() }
+    }
+
+    pub fn output(&self) -> Option<Vec3> {
+        self.output
+    }
+
+    /// True once the task has been asked to stop, checked between steps of long loops
+    pub fn cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Only changes this copy, go through the scheduler to change what the turtle is given
+    pub fn set_role(&mut self, role: Role) {
+        self.role = role;
+    }
+
     pub fn world(&self) -> SharedWorld {
         self.world.clone()
     }
 
-    pub async fn scheduler(&self) -> OwnedMutexGuard<Scheduler> {
-        self.tasks.clone().lock_owned().await
+    pub fn scheduler(&self) -> SchedulerHandle {
+        self.tasks.clone()
     }
 
     pub async fn inventory(&self) -> Vec<Option<InventorySlot>> {
-        let mut inventory = self.inventory.write().await;
+        let mut inventory = self.inventory.slots.write().await;
 
         if inventory.is_some() {
             return inventory.clone().unwrap();
         }
 
+        self.scan(&mut inventory).await
+    }
+
+    /// Scan the inventory again, even if it is cached
+    ///
+    /// Callers that pile up behind a scan started after they asked share its result
+    pub(crate) async fn refresh_inventory(&self) -> Vec<Option<InventorySlot>> {
+        let asked = self.inventory.scans.load(SeqCst);
+        let mut inventory = self.inventory.slots.write().await;
+
+        if let Some(scanned) = inventory.as_ref().filter(|_| self.inventory.scans.load(SeqCst) > asked) {
+            return scanned.clone();
+        }
+
+        self.scan(&mut inventory).await
+    }
+
+    /// Look in every slot, with the cache locked so that nobody else does
+    async fn scan(&self, inventory: &mut Option<Vec<Option<InventorySlot>>>) -> Vec<Option<InventorySlot>> {
+        self.inventory.scans.fetch_add(1, SeqCst);
+        let resets = self.inventory.resets.load(SeqCst);
         let mut scan = Vec::new();
 
         for i in 1..=16 {
@@ -285,13 +1064,15 @@ impl TurtleCommander {
             }
         };
 
-        *inventory = Some(scan.clone());
+        if self.inventory.resets.load(SeqCst) == resets {
+            *inventory = Some(scan.clone());
+        }
         scan
     }
 
     #[tracing::instrument(skip(self))]
     pub async fn dock(&self) -> usize {
-        let res = Depots::dock(&self.depots, self.to_owned()).await;
+        let res = self.try_dock().await;
         if let Some(fuel) = res {
             return fuel;
         };
@@ -299,16 +1080,111 @@ impl TurtleCommander {
         self.fuel()
     }
 
+    /// Top up from fuel ahead if there is any, otherwise at the nearest depot
+    ///
+    /// Only a depot has an output chest to take what doesn't burn
+    pub async fn refuel(&self) -> usize {
+        let ahead = self.execute(TurtleCommand::WaitUntil(Condition::ItemsAhead, 0)).await;
+        if let TurtleCommandResponse::Success = ahead.ret {
+            match self.depots.contains(ahead.pos.pos).await {
+                true => depot::refuel(self).await,
+                false => depot::refuel_from_ahead(self).await,
+            }
+            return self.fuel();
+        }
+        self.dock().await
+    }
+
     pub async fn try_dock(&self) -> Option<usize> {
+        if let Some(report) = &self.dry_run {
+            // leave the depots to real turtles
+            report.docks.fetch_add(1, SeqCst);
+            self.fuel.store(self.fuel_limit(), SeqCst);
+            return Some(self.fuel());
+        }
         self.depots.dock(self.clone()).await 
     }
 
-    pub async fn get_depot(&self) -> DepotGuard {
+    pub async fn get_depot(&self) -> Option<DepotGuard> {
         self.depots.nearest(self.pos().await).await
     }
 
-    #[tracing::instrument(skip(self))]
+    pub(crate) fn depots(&self) -> &Depots {
+        &self.depots
+    }
+
+    pub(crate) fn positions(&self) -> &Positions {
+        &self.positions
+    }
+
+    /// Depot at the given position, waiting for it to be free
+    pub async fn depot_at(&self, pos: Vec3) -> Option<DepotGuard> {
+        self.depots.at(pos).await
+    }
+
+    /// Sort out another turtle being in the way, returning where this one ended up
+    ///
+    /// None if nothing at `blocked` is known to be a turtle. Of two turtles
+    /// stuck on each other, the lower numbered one yields and the other waits
+    async fn make_way(&self, at: Position, blocked: Vec3, dig: bool) -> Option<Position> {
+        let me = self.name().to_num();
+        let other = self.positions.turtle_at(me, blocked)?;
+        if !yields(me, other) {
+            trace!("#{me} waiting on #{other}");
+            self.execute(TurtleCommand::Wait(GIVE_WAY_TIME)).await;
+            return Some(at);
+        }
+
+        info!("#{me} giving way to #{other}");
+        // up or down, digging through junk, otherwise back the way it came
+        let sides = [
+            (TurtleCommand::DigUp, TurtleCommand::Up(1), at.pos + Vec3::y()),
+            (TurtleCommand::DigDown, TurtleCommand::Down(1), at.pos - Vec3::y()),
+        ];
+        let mut moved = None;
+        for (clear, step, to) in sides {
+            if self.world.occupied(to).await {
+                if !dig || !self.world.garbage(to).await || self.avoids(to) {
+                    continue;
+                }
+                self.execute(clear).await;
+            }
+            let state = self.execute(step).await;
+            if let TurtleCommandResponse::Success = state.ret {
+                moved = Some(state.pos);
+                break;
+            }
+        }
+        let moved = match moved {
+            Some(pos) => pos,
+            None => self.execute(TurtleCommand::Backward(1)).await.pos,
+        };
+        self.execute(TurtleCommand::Wait(GIVE_WAY_TIME)).await;
+        Some(moved)
+    }
+
+    /// Goto that gives up after `limit`, wherever the turtle got to
+    pub async fn goto_within(&self, pos: Position, limit: Duration, careful: bool, dig: bool) -> Option<()> {
+        let reached = timeout(limit, self.goto_with(pos, careful, dig)).await;
+        if reached.is_err() {
+            warn!("#{} timed out after {}s heading to {pos}", self.name().to_num(), limit.as_secs());
+        }
+        reached.ok().flatten()
+    }
+
+    /// Follow a route, taking unseen blocks along it to be clear
     pub async fn goto(&self, pos: Position) -> Option<()> {
+        self.goto_with(pos, false, true).await
+    }
+
+    /// Goto that never digs, going around anything in the way or failing if it can't
+    pub async fn goto_nodig(&self, pos: Position) -> Option<()> {
+        self.goto_with(pos, false, false).await
+    }
+
+    #[tracing::instrument(skip(self))]
+    /// Careful gotos look at unseen blocks before moving into them, for unmapped or busy areas
+    async fn goto_with(&self, pos: Position, careful: bool, dig: bool) -> Option<()> {
         let mut recent = self.pos().await;
         let world = self.world.clone();
         let mut attempts = RETRIES + 1;
@@ -319,36 +1195,100 @@ impl TurtleCommander {
 
             attempts -= 1;
             if attempts == 0 {
-                error!("goto {pos:?} failed");
+                error!("goto {pos} failed");
+                self.unreachable(pos.pos);
                 break;
             }
 
-            let routing = route(recent, pos, &world);
-            let route = routing.await?;
+            // a route that just failed would come straight back out of the cache
+            // cached routes are shared by every turtle, so can't avoid anything
+            let routing = match dig && attempts == RETRIES && self.avoid.is_none() {
+                true => paths::route_cached(recent, pos, &world).await,
+                false => route_with(recent, pos, dig, self.avoid.clone(), &world).await,
+            };
+            let route = match routing {
+                Some(route) => route,
+                None => {
+                    self.unreachable(pos.pos);
+                    return None;
+                },
+            };
 
-            trace!("using route: {route:#?}");
+            trace!("using route: {}", route.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" "));
 
-            let steps: Vec<TurtleCommand> = route.iter().map_windows(|[from,to]| from.difference(**to).unwrap()).collect();
+            let steps = match paths::steps(&route) {
+                Some(steps) => steps,
+                None => {
+                    error!("route to {pos} skips a step");
+                    return None;
+                },
+            };
+            // routes that start at the goal have nothing to follow
+            if steps.is_empty() {
+                break;
+            }
+
+            let mut route = route.into_iter().skip(1).zip(steps).peekable();
+            'route: while let Some((next_position, command)) = route.next() {
+                // nothing can be dug out of the way partway up or down a stair
+                if let Some(corner) = recent.corner(next_position) {
+                    if world.occupancy(corner).await != Occupancy::Clear {
+                        break 'route;
+                    }
+                }
 
-            'route: for (next_position, command) in route.into_iter().skip(1).zip(steps) {
                 // reroute if the goal point is not empty before moving
                 // valid routes will explicitly tell you to break ground
 
+                if careful && world.occupancy(next_position.pos).await == Occupancy::Unknown {
+                    // every reply reports the blocks ahead, above, and below,
+                    // so this is usually the one behind
+                    if let TurtleCommand::Backward(_) = command {
+                        self.execute(TurtleCommand::Left).await;
+                        recent = self.execute(TurtleCommand::Left).await.pos;
+                        // facing the other way needs a new route, but nothing failed
+                        attempts += 1;
+                        break 'route;
+                    }
+                    // now known, so carry on as if it always had been
+                    let seen = self.execute(TurtleCommand::Update).await.pos;
+                    if seen != recent {
+                        recent = seen;
+                        break 'route;
+                    }
+                }
+
                 if world.occupied(next_position.pos).await {
-                    if world.garbage(next_position.pos).await {
+                    if dig && world.garbage(next_position.pos).await && !self.avoids(next_position.pos) {
                         match recent.dig(next_position.pos) {
                             Some(command) => self.execute(command).await,
                             None => break 'route,
                         };
                     } else {
+                        if let Some(moved) = self.make_way(recent, next_position.pos, dig).await {
+                            recent = moved;
+                        }
                         break 'route;
                     }
                 }
 
+                let command = straight_run(command, &mut route, &world).await;
                 let state = self.execute(command.clone()).await;
 
-                if let TurtleCommandResponse::Failure =  state.ret {
-                    if let TurtleCommand::Backward(_) = command {
+                if let TurtleCommandResponse::Failure(reason) = &state.ret {
+                    // a stair can stop halfway, and a run anywhere along it
+                    recent = state.pos;
+                    if let FailureReason::NoFuel = reason {
+                        error!("out of fuel on the way to {pos}");
+                        return None;
+                    }
+                    let blocked = match command {
+                        TurtleCommand::Forward(_) => recent.pos + recent.dir.unit(),
+                        _ => next_position.pos,
+                    };
+                    if let Some(moved) = self.make_way(recent, blocked, dig).await {
+                        recent = moved;
+                    } else if let TurtleCommand::Backward(_) = command {
                         // turn around if you bump your rear on something
                         self.execute(TurtleCommand::Left).await;
                         recent = self.execute(TurtleCommand::Left).await.pos;
@@ -362,34 +1302,81 @@ impl TurtleCommander {
         Some(())
     }
 
-    #[tracing::instrument]
     pub async fn goto_adjacent(&self, pos: Vec3) -> Option<Position> {
+        self.goto_adjacent_from(pos, Approach::Any).await
+    }
+
+    /// Go next to a block, from the preferred sides if they can be reached
+    pub async fn goto_adjacent_from(&self, pos: Vec3, approach: Approach) -> Option<Position> {
+        self.goto_adjacent_any(&[pos], approach).await.map(|(_, near)| near)
+    }
+
+    /// Go next to whichever block is closest to get to, returning it
+    #[tracing::instrument]
+    pub async fn goto_adjacent_any(&self, targets: &[Vec3], mut approach: Approach) -> Option<(Vec3, Position)> {
+        let pos = match targets {
+            [] => return None,
+            [pos] => Coords(*pos).to_string(),
+            _ => format!("{} blocks", targets.len()),
+        };
+        let mut target = targets[0];
         let mut recent = self.pos().await;
         let world = self.world.clone();
         let mut attempts = RETRIES +1;
         loop {
             
-            if pos == recent.dir.unit() + recent.pos 
-                || pos == recent.pos + Vec3::y()
-                || pos == recent.pos - Vec3::y()
-            {
+            if let Some(t) = targets.iter().find(|t| approach.accepts(&recent, **t)) {
+                target = *t;
                 break;
             }
 
             attempts -= 1;
             if attempts == 0 {
-                error!("adjacent {pos:?} failed");
+                error!("adjacent {pos} failed");
+                targets.iter().for_each(|t| self.unreachable(*t));
                 break;
             }
 
-            let routing = route_facing(recent, pos, &world);
-            let route = routing.await?;
+            let routing = route_to_any(recent, targets, approach, self.avoid.clone(), &world);
+            let route = match routing.await {
+                Some((t, route)) => {
+                    target = t;
+                    route
+                },
+                None if approach != Approach::Any => {
+                    trace!("no {approach:?} approach to {pos}");
+                    approach = Approach::Any;
+                    continue;
+                },
+                None => {
+                    targets.iter().for_each(|t| self.unreachable(*t));
+                    return None;
+                },
+            };
+
+            let steps = match paths::steps(&route) {
+                Some(steps) => steps,
+                None => {
+                    error!("route next to {pos} skips a step");
+                    return None;
+                },
+            };
+            // already facing it, nothing to follow
+            if steps.is_empty() {
+                break;
+            }
 
-            let steps: Vec<TurtleCommand> = route.iter().map_windows(|[from,to]| from.difference(**to).unwrap()).collect();
+            let mut route = route.into_iter().skip(1).zip(steps).peekable();
+            'route: while let Some((next_position, command)) = route.next() {
+                // nothing can be dug out of the way partway up or down a stair
+                if let Some(corner) = recent.corner(next_position) {
+                    if world.occupancy(corner).await != Occupancy::Clear {
+                        break 'route;
+                    }
+                }
 
-            'route: for (next_position, command) in route.into_iter().skip(1).zip(steps) {
                 if recent.pos != next_position.pos && world.occupied(next_position.pos).await {
-                    if world.garbage(next_position.pos).await {
+                    if world.garbage(next_position.pos).await && !self.avoids(next_position.pos) {
                         let command = recent.dig(next_position.pos);
                         match command {
                             Some(command) => self.execute(command).await,
@@ -399,113 +1386,329 @@ impl TurtleCommander {
                             },
                         };
                     } else {
-                        warn!("non destructible block on route: {} at {:?}", world.get(next_position.pos).await.unwrap().name, next_position);
+                        warn!("non destructible block on route: {} at {next_position}", world.get(next_position.pos).await.unwrap().name);
+                        if let Some(moved) = self.make_way(recent, next_position.pos, true).await {
+                            recent = moved;
+                        }
                         break 'route;
                     }
                 }
 
+                let command = straight_run(command, &mut route, &world).await;
                 let state = self.execute(command.clone()).await;
 
-                if let TurtleCommandResponse::Failure =  state.ret {
-                    if let TurtleCommand::Backward(_) = command {
+                if let TurtleCommandResponse::Failure(reason) = &state.ret {
+                    // a stair can stop halfway, and a run anywhere along it
+                    recent = state.pos;
+                    if let FailureReason::NoFuel = reason {
+                        error!("out of fuel on the way to {pos}");
+                        return None;
+                    }
+                    let blocked = match command {
+                        TurtleCommand::Forward(_) => recent.pos + recent.dir.unit(),
+                        _ => next_position.pos,
+                    };
+                    if let Some(moved) = self.make_way(recent, blocked, true).await {
+                        recent = moved;
+                    } else if let TurtleCommand::Backward(_) = command {
                         // turn around if you bump your rear on something
                         self.execute(TurtleCommand::Left).await;
                         recent = self.execute(TurtleCommand::Left).await.pos;
                     }
-                    warn!("command failed");
+                    warn!("command failed: {reason:?}");
                     break 'route;
                 }
 
                 recent = state.pos;
             }
         }
-        Some(recent)
+        Some((target, recent))
     }
 }
 
-pub(crate) async fn process_turtle_update(
-    id: u32,
-    state: &LiveState,
-    update: TurtleUpdate,
-) -> Option<TurtleCommand> {
-    let mut turtle =  match state
-        .turtles
-        .get(id as usize) {
-            Some(turtle) => turtle.write().await,
-            None => {
-                error!("nonexisting turtle {id}");
-                return Some(TurtleCommand::Poweroff);
-            },
-    };
-    
-    let world = &state.world;
+/// Where and when each turtle was last reported, by number, to tell them apart from blocks
+#[derive(Clone, Default)]
+pub struct Positions(Arc<std::sync::Mutex<BTreeMap<u32, (Vec3, Instant)>>>);
 
-    if turtle.pending_update {
-        turtle.pending_update = false;
-        return Some(TurtleCommand::Update);
+impl Positions {
+    fn track(&self, id: u32, pos: Vec3) {
+        self.track_at(id, pos, Instant::now());
     }
 
-    if turtle.fuel > update.fuel {
-        let diff = turtle.fuel - update.fuel;
+    fn track_at(&self, id: u32, pos: Vec3, at: Instant) {
+        self.0.lock().unwrap().insert(id, (pos, at));
+    }
 
-        let delta = turtle.queued_movement * diff as i32;
+    /// The other turtle at a position, if any
+    fn turtle_at(&self, me: u32, pos: Vec3) -> Option<u32> {
+        self.0.lock().unwrap().iter()
+            .find(|(id, (p, _))| **id != me && *p == pos)
+            .map(|(id, _)| *id)
+    }
 
-        turtle.position.pos += delta;
-        turtle.queued_movement = Vec3::zeros();
+    /// Whether a turtle has checked in since the given time, which one sitting in an inventory can't have
+    pub(crate) fn reported_since(&self, id: u32, since: Instant) -> bool {
+        self.0.lock().unwrap().get(&id).is_some_and(|(_, at)| *at >= since)
     }
-    turtle.fuel = update.fuel;
+}
 
-    let above = Block {
-        name: update.above.clone(),
-        pos: turtle.position.pos + Vec3::y(),
-    };
-    world.set(above.clone()).await;
+/// Whether a turtle steps aside for another that is in its way
+fn yields(me: u32, other: u32) -> bool {
+    me < other
+}
 
-    let ahead = Block {
-        name: update.ahead.clone(),
-        pos: turtle.position.pos + turtle.position.dir.unit(),
+/// A step forward lengthened over the run of known open space straight after it,
+/// so the whole run takes one round trip
+async fn straight_run<I: Iterator<Item = (Position, TurtleCommand)>>(
+    command: TurtleCommand,
+    route: &mut Peekable<I>,
+    world: &SharedWorld,
+) -> TurtleCommand {
+    let TurtleCommand::Forward(1) = command else {
+        return command;
     };
-    world.set(ahead.clone()).await;
+    let mut run = 1;
+    while let Some(&(ahead, TurtleCommand::Forward(1))) = route.peek() {
+        if world.occupancy(ahead.pos).await != Occupancy::Clear {
+            break;
+        }
+        route.next();
+        run += 1;
+    }
+    TurtleCommand::Forward(run)
+}
 
-    let below = Block {
-        name: update.below.clone(),
-        pos: turtle.position.pos - Vec3::y(),
+/// The blocks above, ahead of, and below a turtle as of an update
+///
+/// An inspection fills in the properties of the block it looked at
+fn surroundings(position: Position, update: &TurtleUpdate, command: Option<&TurtleCommand>) -> [Block; 3] {
+    let inspected = match &update.ret {
+        TurtleCommandResponse::BlockState { properties, .. } => Some(properties.clone().into_iter().collect::<BTreeMap<_, _>>()),
+        _ => None,
     };
-    world.set(below.clone()).await;
+    let (above, ahead, below) = match command {
+        Some(TurtleCommand::InspectUp) => (inspected, None, None),
+        Some(TurtleCommand::Inspect) => (None, inspected, None),
+        Some(TurtleCommand::InspectDown) => (None, None, inspected),
+        _ => (None, None, None),
+    };
+
+    [
+        Block { name: update.above.clone(), pos: position.pos + Vec3::y(), state: above },
+        Block { name: update.ahead.clone(), pos: position.pos + position.dir.unit(), state: ahead },
+        Block { name: update.below.clone(), pos: position.pos - Vec3::y(), state: below },
+    ]
+}
+
+/// Takes handles rather than the whole state, as waiting on the next command
+/// shouldn't hold up handlers that need to lock it
+pub(crate) async fn process_turtle_update(
+    turtle: &RwLock<Turtle>,
+    world: &SharedWorld,
+    tasks: &SchedulerHandle,
+    positions: &Positions,
+    update: TurtleUpdate,
+) -> Option<TurtleCommand> {
+    let mut turtle = turtle.write().await;
+    let id = turtle.name.to_num();
+
+    turtle.last_seen = Some(Instant::now());
+
+    if turtle.powered_off.swap(false, SeqCst) {
+        info!("{} is back on", turtle.name.to_str());
+    }
+
+    // clients that don't send tokens get no protection from retries
+    if update.token.is_some_and(|t| t != turtle.token) {
+        warn!("{}: dropping stale update {:?}", turtle.name.to_str(), update.token);
+        turtle.stats.stale += 1;
+        // most likely the reply carrying the command was lost
+        return turtle.in_flight.clone();
+    }
+    let answered = turtle.in_flight.take();
+    if let Some(sent) = turtle.sent_at.take() {
+        turtle.stats.answer(sent.elapsed());
+    }
+
+    if turtle.pending_update {
+        turtle.pending_update = false;
+        return Some(TurtleCommand::Update);
+    }
+
+    // each step of a batch is followed on its own, as turns change where the next goes
+    if let (Some(TurtleCommand::Batch(commands)), TurtleCommandResponse::Batch(steps)) = (&answered, &update.ret) {
+        for (command, step) in commands.iter().zip(steps) {
+            turtle.moved(Some(command), step.fuel);
+            match (command, &step.ret) {
+                (_, TurtleCommandResponse::Failure(_)) => {},
+                (TurtleCommand::Left, _) => turtle.position.dir = turtle.position.dir.left(),
+                (TurtleCommand::Right, _) => turtle.position.dir = turtle.position.dir.right(),
+                _ => {},
+            }
+            for block in surroundings(turtle.position, step, Some(command)) {
+                world.set(block).await;
+            }
+        }
+    }
+    turtle.moved(answered.as_ref(), update.fuel);
+
+    // pistons, falling, and the like move turtles without the server knowing
+    if let TurtleCommandResponse::Location(fix) = &update.ret {
+        let drift = fix - turtle.position.pos;
+        if drift.abs().max() > DRIFT_TOLERANCE {
+            warn!("{}: tracked at {} but GPS puts it at {}", turtle.name.to_str(), Coords(turtle.position.pos), Coords(*fix));
+            turtle.position.pos = *fix;
+        }
+    }
+    positions.track(id, turtle.position.pos);
+    world.updates().turtle(id, turtle.position);
+
+    for block in surroundings(turtle.position, &update, answered.as_ref()) {
+        world.set(block).await;
+    }
 
     let info = TurtleInfo::from_update(update, turtle.name.clone(), turtle.position.clone());
 
-    if let TurtleCommandResponse::Failure = info.ret {
-        info!("{}: command failure", turtle.name.to_str());
+    match (&info.ret, &answered) {
+        // no modem or no GPS in range, which says nothing about the link
+        (TurtleCommandResponse::Failure(reason), Some(TurtleCommand::Locate)) => {
+            warn!("{}: no GPS fix, not asking again: {reason:?}", turtle.name.to_str());
+            turtle.no_gps = true;
+        },
+        (TurtleCommandResponse::Failure(reason), _) => {
+            info!("{}: command failure: {reason:?}", turtle.name.to_str());
+            turtle.stats.failures += 1;
+        },
+        _ => {},
     }
 
+    if let Some(entry) = turtle.history.back_mut().filter(|e| e.response.is_none()) {
+        entry.response = Some(info.ret.clone());
+    }
     if let Some(send) = turtle.callback.take() {
         send.send(info).unwrap_or_else(|_| warn!("task cancelled"));
     }
 
-    if let Some(recv) = turtle.receiver.as_mut() {
-        let next = timeout(Duration::from_millis(COMMAND_TIMEOUT), recv.recv());
-        if let Some((cmd, ret)) = next.await.ok().flatten() {
-            turtle.callback = Some(ret);
+    // asked between commands, so nobody waits on the answer
+    if !turtle.no_gps && turtle.located.map_or(true, |t| t.elapsed() > Duration::from_secs(LOCATE_INTERVAL)) {
+        turtle.located = Some(Instant::now());
+        return Some(turtle.dispatch(TurtleCommand::Locate));
+    }
 
-            match cmd {
-                TurtleCommand::Left => turtle.position.dir = turtle.position.dir.left(),
-                TurtleCommand::Right => turtle.position.dir = turtle.position.dir.right(),
-                _ => {}
-            }
-            turtle.queued_movement = cmd.unit(turtle.position.dir);
-            info!("{}: {cmd:?}", turtle.name.to_str());
-            return Some(cmd);
-        }
+    if let Some(cmd) = turtle.receive(tasks, Duration::from_millis(COMMAND_TIMEOUT)).await {
+        return Some(cmd);
     }
 
     trace!("{} idle, connected", turtle.name.to_str());
     None
 }
 
+/// Wait on the next command for an idle turtle, for links that can hand it over unasked
+///
+/// Returns the command's token along with it. The turtle is only locked a
+/// moment at a time, so it can be looked at meanwhile
+pub(crate) async fn push_command(turtle: &RwLock<Turtle>, tasks: &SchedulerHandle, wait: Duration) -> Option<(u64, TurtleCommand)> {
+    let until = Instant::now() + wait;
+    while Instant::now() < until {
+        let mut turtle = turtle.write().await;
+        if let Some(cmd) = turtle.receive(tasks, Duration::from_millis(COMMAND_TIMEOUT)).await {
+            return Some((turtle.token, cmd));
+        }
+    }
+    None
+}
+
+/// Transfers that lay a recipe out in the crafting grid, as (from, to, count) with slots from 1
+///
+/// Crafting needs every other slot empty, so all of each ingredient is spread over its
+/// places in the recipe. Fails before planning any moves if there isn't enough for `count`
+/// crafts, too much to fit, or something carried isn't in the recipe
+fn arrange(inventory: &[Option<InventorySlot>], recipe: &[Option<String>; 9], count: u32) -> Result<Vec<(u32, u32, u32)>, FailureReason> {
+    let mut places: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (slot, name) in CRAFTING_GRID.iter().zip(recipe) {
+        if let Some(name) = name {
+            places.entry(name.as_str()).or_default().push(*slot);
+        }
+    }
+    let mut carried: BTreeMap<&str, u32> = BTreeMap::new();
+    for item in inventory.iter().flatten() {
+        *carried.entry(item.name.as_str()).or_default() += item.count;
+    }
+    if let Some(stray) = carried.keys().find(|n| !places.contains_key(*n)) {
+        return Err(FailureReason::Other(format!("{stray} is not in the recipe")));
+    }
+
+    let mut wanted: Vec<Option<(&str, u32)>> = vec![None; inventory.len()];
+    for (name, slots) in &places {
+        let total = carried.get(name).copied().unwrap_or_default();
+        let n = slots.len() as u32;
+        if total < n * count {
+            return Err(FailureReason::NoItems);
+        }
+        if total > n * STACK_SIZE {
+            return Err(FailureReason::InventoryFull);
+        }
+        for (i, slot) in slots.iter().enumerate() {
+            // the remainder goes to the first few
+            wanted[*slot] = Some((name, total / n + ((i as u32) < total % n) as u32));
+        }
+    }
+
+    let mut held: Vec<Option<(&str, u32)>> = inventory.iter()
+        .map(|s| s.as_ref().map(|s| (s.name.as_str(), s.count)))
+        .collect();
+    let surplus = |held: &[Option<(&str, u32)>], slot: usize| match (held[slot], wanted[slot]) {
+        (Some((name, count)), Some((want, target))) if name == want => count.saturating_sub(target),
+        (Some((_, count)), _) => count,
+        (None, _) => 0,
+    };
+
+    let mut moves = Vec::new();
+    // each transfer fills a place or clears a slot, short of shuffling in circles
+    for _ in 0..inventory.len() * inventory.len() {
+        let extra: Vec<usize> = (0..held.len()).filter(|s| surplus(&held, *s) > 0).collect();
+        if extra.is_empty() {
+            return Result::Ok(moves);
+        }
+
+        let fill = extra.iter().find_map(|&from| {
+            let name = held[from]?.0;
+            (0..held.len()).find_map(|to| {
+                let (want, target) = wanted[to]?;
+                let have = match held[to] {
+                    Some((n, c)) if n == name => c,
+                    None => 0,
+                    Some(_) => return None,
+                };
+                (want == name && have < target).then(|| (from, to, surplus(&held, from).min(target - have)))
+            })
+        });
+
+        // everything is in the way of something else, so set one aside
+        let (from, to, moved) = match fill {
+            Some(fill) => fill,
+            None => {
+                let from = extra[0];
+                let to = (0..held.len()).filter(|s| held[*s].is_none())
+                    .min_by_key(|s| wanted[*s].is_some())
+                    .ok_or(FailureReason::InventoryFull)?;
+                (from, to, surplus(&held, from))
+            },
+        };
+
+        let (name, count) = held[from].unwrap();
+        held[from] = (count > moved).then(|| (name, count - moved));
+        held[to] = Some((name, held[to].map_or(0, |h| h.1) + moved));
+        moves.push((from as u32 + 1, to as u32 + 1, moved));
+    }
+    Err(FailureReason::InventoryFull)
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum TurtleCommand {
     Wait(u32),
+    /// Checked by the turtle until it holds, or fails once the timeout (s) passes
+    WaitUntil(Condition, u32),
     Forward(u32),
     Backward(u32),
     Up(u32),
@@ -518,6 +1721,10 @@ pub enum TurtleCommand {
     PlaceUp,
     Place,
     PlaceDown,
+    /// Select the slot and place from it, in one round trip
+    PlaceFrom(u32),
+    PlaceUpFrom(u32),
+    PlaceDownFrom(u32),
     /// Count
     DropFront(u32),
     DropUp(u32),
@@ -528,20 +1735,65 @@ pub enum TurtleCommand {
     Select(u32),
     /// Slot in inventory
     ItemInfo(u32),
+    /// Slot in inventory, with damage, enchantments, and the like
+    ItemDetail(u32),
+    /// Swap the selected slot with the tool on that side
+    EquipLeft,
+    EquipRight,
+    /// What is on that side, for clients new enough to tell without taking it off
+    EquippedLeft,
+    EquippedRight,
     Update,
     Poweroff,
     Refuel,
+    /// Select the slot and burn up to count items from it
+    RefuelFrom(u32, u32),
+    /// Move up to count items from the selected slot to the given one
+    TransferTo(u32, u32),
+    /// Craft up to count times from the grid, needs a crafting table equipped
+    Craft(u32),
     CycleFront,
     /// Name of the computer in front of the one commanded
     NameFront,
     /// Name of the current computer
     Name,
+    /// Name and properties (facing, half, waterlogged, ...) of the block on that side
+    Inspect,
+    InspectUp,
+    InspectDown,
+    /// Coordinates from GPS, needs a wireless modem and GPS hosts in range
+    Locate,
+    /// Forward then up, a step of a staircase in one round trip
+    ForwardUp,
+    /// Forward then down
+    ForwardDown,
+    /// Carried out one after another in one round trip, stopping at the first to fail
+    Batch(Vec<TurtleCommand>),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) struct InventorySlot {
     pub(crate) name: String,
     pub(crate) count: u32,
+    /// Anything else reported, such as damage and enchantments from ItemDetail
+    #[serde(default, flatten)]
+    pub(crate) detail: BTreeMap<String, serde_json::Value>,
+}
+
+impl InventorySlot {
+    /// How used up a tool is, 0 when new and 1 when about to break
+    pub(crate) fn wear(&self) -> Option<f64> {
+        let damage = self.detail.get("damage")?.as_f64()?;
+        let max = self.detail.get("maxDamage")?.as_f64()?;
+        (max > 0.0).then(|| damage / max)
+    }
+}
+
+/// Upgrades on each side of a turtle, such as a pickaxe, modem, or crafting table
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct Equipped {
+    pub(crate) left: Option<InventorySlot>,
+    pub(crate) right: Option<InventorySlot>,
 }
 
 // bodge
@@ -550,25 +1802,68 @@ pub struct TurtleString {
     pub string: String,
 }
 
+/// Why a command failed, translated from the client's error message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FailureReason {
+    /// A block or entity is in the way
+    Obstructed,
+    NoFuel,
+    /// No room to pick up items
+    InventoryFull,
+    /// Nothing to drop, place, burn, or pick up
+    NoItems,
+    NothingToDig,
+    /// Unbreakable or protected blocks, or nowhere to place
+    Denied,
+    /// A condition didn't hold in time
+    TimedOut,
+    /// Any message the client doesn't recognize
+    Other(String),
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) enum TurtleCommandResponse {
     None,
     Success,
-    Failure,
+    Failure(FailureReason),
     Item(InventorySlot),
     Inventory(Vec<InventorySlot>),
     Name(TurtleString),
+    BlockState {
+        name: String,
+        properties: HashMap<String, String>,
+    },
+    Location(Vec3),
+    /// What each command of a batch left behind, up to and including any that failed
+    Batch(Vec<TurtleUpdate>),
 }
 
 impl TurtleCommand {
+    /// The same command, selecting the slot first as part of it
+    ///
+    /// None if there is no such command
+    pub fn from_slot(self, slot: u32) -> Option<Self> {
+        Some(match self {
+            TurtleCommand::Place => TurtleCommand::PlaceFrom(slot),
+            TurtleCommand::PlaceUp => TurtleCommand::PlaceUpFrom(slot),
+            TurtleCommand::PlaceDown => TurtleCommand::PlaceDownFrom(slot),
+            TurtleCommand::Refuel => TurtleCommand::RefuelFrom(slot, 64),
+            _ => None?,
+        })
+    }
+
     pub(crate) fn delta(&self, direction: Direction) -> Vec3 {
-        let dir = direction.unit();
+        self.moves(direction).into_iter().fold(Vec3::zeros(), |a, b| a + b)
+    }
+
+    /// Each block moved, in order, if nothing gets in the way
+    pub(crate) fn moves(&self, direction: Direction) -> Vec<Vec3> {
         match self {
-            TurtleCommand::Forward(count) => dir * *count as i32,
-            TurtleCommand::Backward(count) => -dir * *count as i32,
-            TurtleCommand::Up(count) => Vec3::y() * *count as i32,
-            TurtleCommand::Down(count) => -Vec3::y() * *count as i32,
-            _ => Vec3::zeros(),
+            TurtleCommand::Forward(count) | TurtleCommand::Backward(count)
+                | TurtleCommand::Up(count) | TurtleCommand::Down(count) => vec![self.unit(direction); *count as usize],
+            TurtleCommand::ForwardUp => vec![direction.unit(), Vec3::y()],
+            TurtleCommand::ForwardDown => vec![direction.unit(), -Vec3::y()],
+            _ => Vec::new(),
         }
     }
 
@@ -584,7 +1879,21 @@ impl TurtleCommand {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Something a turtle can wait for without asking the server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Condition {
+    /// No block ahead
+    FrontClear,
+    /// The block ahead, if any, doesn't have this in its name
+    FrontNot(String),
+    /// The inventory ahead has something in it
+    ItemsAhead,
+}
+
+/// Longest a turtle is told to wait on a condition, as it can't report in meanwhile
+pub const MAX_WAIT: u32 = STALE_TIME as u32 / 2;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub(crate) struct TurtleUpdate {
     pub(crate) fuel: usize,
     /// Block name
@@ -592,14 +1901,22 @@ pub(crate) struct TurtleUpdate {
     pub(crate) above: String,
     pub(crate) below: String,
     pub(crate) ret: TurtleCommandResponse,
+    /// Token of the command this is the result of
+    #[serde(default)]
+    pub(crate) token: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct TurtleRegister {
+    /// Name given at a previous registration
+    #[serde(default)]
+    pub(crate) label: Option<String>,
     pub(crate) fuel: usize,
     pub(crate) fuellimit: usize,
     pub(crate) position: Vec3,
     pub(crate) facing: Direction,
+    #[serde(default)]
+    pub(crate) role: Option<Role>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -608,3 +1925,99 @@ pub(crate) struct TurtleResponse {
     pub(crate) id: u32,
     pub(crate) command: TurtleCommand,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(name: &str, count: u32) -> Option<InventorySlot> {
+        Some(InventorySlot { name: name.into(), count, detail: BTreeMap::new() })
+    }
+
+    /// Carry out transfers the way a turtle would, refusing any it would fail
+    fn transfer(inventory: &mut [Option<InventorySlot>], moves: &[(u32, u32, u32)]) {
+        for &(from, to, count) in moves {
+            let (from, to) = (from as usize - 1, to as usize - 1);
+            let item = inventory[from].clone().unwrap();
+            assert!(item.count >= count);
+            match &mut inventory[to] {
+                Some(dest) => { assert_eq!(dest.name, item.name); dest.count += count; },
+                dest => *dest = items(&item.name, count),
+            }
+            inventory[from] = (item.count > count).then(|| items(&item.name, item.count - count)).flatten();
+        }
+    }
+
+    #[test]
+    fn crafting_layout() {
+        let planks = Some("minecraft:oak_planks".to_string());
+        let sticks = [planks.clone(), None, None, planks.clone(), None, None, None, None, None];
+        let mut inventory = vec![None; 16];
+        inventory[1] = items("minecraft:oak_planks", 3);
+        inventory[15] = items("minecraft:oak_planks", 5);
+
+        let moves = arrange(&inventory, &sticks, 4).unwrap();
+        transfer(&mut inventory, &moves);
+        assert_eq!(inventory[0].as_ref().unwrap().count, 4);
+        assert_eq!(inventory[4].as_ref().unwrap().count, 4);
+        assert_eq!(inventory.iter().flatten().count(), 2);
+
+        assert_eq!(arrange(&inventory, &sticks, 5), Err(FailureReason::NoItems));
+        inventory[7] = items("minecraft:cobblestone", 1);
+        assert!(matches!(arrange(&inventory, &sticks, 1), Err(FailureReason::Other(_))));
+
+        // each in the other's place
+        let mut inventory = vec![None; 16];
+        inventory[0] = items("b", 1);
+        inventory[1] = items("a", 1);
+        let moves = arrange(&inventory, &[Some("a".into()), Some("b".into()), None, None, None, None, None, None, None], 1).unwrap();
+        transfer(&mut inventory, &moves);
+        assert_eq!(inventory[0].as_ref().unwrap().name, "a");
+        assert_eq!(inventory[1].as_ref().unwrap().name, "b");
+        assert_eq!(inventory.iter().flatten().count(), 2);
+    }
+
+    #[test]
+    fn tool_wear() {
+        let fresh: InventorySlot = serde_json::from_str(r#"{"name":"minecraft:diamond_pickaxe","count":1}"#).unwrap();
+        assert_eq!(fresh.wear(), None);
+
+        let worn: InventorySlot = serde_json::from_str(r#"{"name":"minecraft:diamond_pickaxe","count":1,"damage":1500,"maxDamage":1561,"enchantments":[]}"#).unwrap();
+        assert!(worn.wear().is_some_and(|w| w > TOOL_WORN));
+        assert!(worn.detail.contains_key("enchantments"));
+    }
+
+    #[test]
+    fn reporting() {
+        let positions = Positions::default();
+        let (carried, working) = (1, 2);
+        let picked_up = Instant::now();
+        positions.track_at(carried, Vec3::new(0, 64, 0), picked_up - Duration::from_secs(1));
+        positions.track_at(working, Vec3::new(8, 64, 0), picked_up + Duration::from_secs(1));
+        assert!(!positions.reported_since(carried, picked_up));
+        assert!(positions.reported_since(working, picked_up));
+        assert!(!positions.reported_since(3, picked_up));
+        assert_eq!(positions.turtle_at(carried, Vec3::new(8, 64, 0)), Some(working));
+    }
+
+    #[test]
+    fn usable_slots() {
+        // saves from before the setting
+        let saved = ron::ser::to_string(&Turtle::default().info()).unwrap().replace("usable_slots:12,", "");
+        assert!(!saved.contains("usable_slots"));
+        let turtle: Turtle = ron::de::from_str(&saved).unwrap();
+        assert_eq!(turtle.usable_slots.load(SeqCst), FULL_SLOTS);
+    }
+
+    #[tokio::test]
+    async fn reregister_forgets_inventory() {
+        let mut turtle = Turtle::default();
+        *turtle.inventory.slots.write().await = Some(vec![items("minecraft:coal", 64); 16]);
+        turtle.inventory.selected.store(3, SeqCst);
+
+        // a reboot may have come with a refill by hand
+        turtle.reregister(Position::new(Vec3::zeros(), Direction::North), 0, 0);
+        assert!(turtle.inventory.slots.read().await.is_none());
+        assert_eq!(turtle.inventory.selected.load(SeqCst), 0);
+    }
+}
@@ -2,26 +2,33 @@ use tracing::error;
 use tracing::trace;
 use tokio;
 use blocks::Vec3;
-use tokio::time::Instant;
 use crate::blocks::Direction;
 use crate::construct::BuildSimple;
-use crate::fell::TreeFarm;
+use crate::fell::{TreeFarm, TreeKinds};
 use crate::mine::Mine;
 use crate::mine::Quarry;
+use crate::mine::{WhenFull, Keep, BlockFilter, Pattern};
+use crate::paths::Approach;
 use crate::turtle::IDLE_TIME;
+use crate::turtle::GOTO_TIMEOUT;
 use crate::turtle::TurtleCommandResponse;
 use crate::turtle::TurtleCommander;
 use crate::turtle::TurtleInfo;
 use crate::vendored::schematic::Schematic;
-use axum::extract::Path;
+use axum::extract::{Path, Query};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use crate::turtle::TurtleCommand;
+use crate::tasks::{Added, Archived, Role, SchedulerStatus, TaskInfo};
 use crate::names::Name;
 use tracing::info;
+use tracing::warn;
 use std::collections::VecDeque;
+use std::time::Duration;
 use blocks::Position;
 use crate::turtle::Turtle;
 use tokio::sync::RwLock;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use tokio::sync::mpsc;
 use crate::turtle;
 use axum::Json;
@@ -31,31 +38,99 @@ use axum::routing::post;
 use crate::blocks;
 use crate::mine;
 use super::SharedControl;
+use super::LiveState;
 use axum::Router;
+use axum::response::Response;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use crate::depot::{Chest, ChestRole, Depot, Depots, Haul};
+use crate::deliver::Deliver;
+use anyhow::anyhow;
+use crate::blocks::Coords;
 use indoc::formatdoc;
 use crate::PORT;
+use crate::CLIENT;
 use tokio::fs;
 
 /// Time (s) after boot to start allocating turtles to tasks
 /// too short of a time could make fast-booting turtles do far away tasks over closer ones
-const STARTUP_ALLOWANCE: f64 = 4.0;
+pub(crate) const STARTUP_ALLOWANCE: Duration = Duration::from_secs(4);
+
+/// Outcome of a request that changes something
+#[derive(Serialize)]
+pub(crate) struct Ack {
+    ok: bool,
+    message: String,
+    /// Task created by the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+}
+
+pub(crate) type AckResponse = (StatusCode, Json<Ack>);
+
+impl Ack {
+    pub(crate) fn ok(message: impl Into<String>) -> AckResponse {
+        (StatusCode::OK, Json(Ack { ok: true, message: message.into(), id: None }))
+    }
+
+    pub(crate) fn task(added: Added) -> AckResponse {
+        Self::task_with(added, &[])
+    }
+
+    /// A created task, and what it will leave undone
+    pub(crate) fn task_with(added: Added, notes: &[String]) -> AckResponse {
+        let id = added.id;
+        let mut ack = Ack { ok: true, message: format!("created task {id}"), id: Some(id) };
+        if let Some(why) = added.unkept {
+            ack.message += &format!("; it can't be run again from the archive: {why}");
+        }
+        for note in notes {
+            ack.message += &format!("; {note}");
+        }
+        (StatusCode::OK, Json(ack))
+    }
+
+    pub(crate) fn err(status: StatusCode, message: impl Into<String>) -> AckResponse {
+        let message = message.into();
+        error!("{message}");
+        (status, Json(Ack { ok: false, message, id: None }))
+    }
+}
+
+/// Response header with the token to echo back in the next update
+const TOKEN_HEADER: &str = "x-command-token";
 
 pub fn turtle_api() -> Router<SharedControl> {
     Router::new()
         .route("/new", post(create_turtle))
         .route("/:id/update", post(command))
+        .route("/:id/ws", get(websocket))
         .route("/:id/setPosition", post(update_position))
         .route("/client.lua", get(client))
         .route("/:id/setGoal", post(set_goal))
         .route("/:id/cancelTask", post(cancel))
         .route("/:id/manual", post(run_command))
         .route("/:id/dock", post(dock))
+        .route("/:id/refuel", post(refuel))
+        .route("/:id/setRole", post(set_role))
+        .route("/:id/setHome", post(set_home))
+        .route("/:id/setUsableSlots", post(set_usable_slots))
+        .route("/:id/equip", post(equip))
+        .route("/:id/equipped", get(equipped).post(probe_equipped))
+        .route("/:id/craft", post(craft))
+        .route("/:id/inventory", get(inventory))
+        .route("/:id/refreshInventory", post(refresh_inventory))
         .route("/:id/info", get(turtle_info))
+        .route("/:id/history", get(history))
+        .route("/:id/stats", get(stats))
         .route("/:id/register", get(register_turtle))
         .route("/createTreeFarm", post(fell))
         .route("/createMine", post(dig))
+        .route("/createDelivery", post(deliver))
         .route("/build", post(build))
         .route("/registerDepot", post(new_depot))
+        .route("/:id/registerDepotHere", post(new_depot_here))
+        .route("/haul", post(haul))
         .route("/pollScheduler", get(poll))
         .route("/shutdown", get(shutdown)) // probably tramples the rfc
         .route("/updateAll", get(update_turtles))
@@ -65,44 +140,104 @@ pub(crate) async fn update_position(
     Path(id): Path<u32>,
     State(state): State<SharedControl>,
     Json(req): Json<Position>,
-) -> &'static str {
+) -> AckResponse {
     let state = &mut state.read().await;
     let turtle = state.turtles.get(id as usize);
     if let Some(turtle) = turtle {
         turtle.write().await.position = req;
         info!("updated position");
+        Ack::ok(format!("turtle {id} is at {req}"))
     } else {
-        error!("position update failed");
+        Ack::err(StatusCode::NOT_FOUND, format!("position update failed: no turtle {id}"))
     }
-
-    "ACK"
 }
 pub(crate) async fn register_turtle(
     Path(id): Path<u32>,
     State(state): State<SharedControl>,
-) -> &'static str {
-    let state = &mut state.write().await;
-    let commander = state.get_turtle(id).await.unwrap().clone();
-    state.tasks.lock().await.add_turtle(&commander);
+) -> AckResponse {
+    let state = state.read().await;
+    let Some(commander) = state.get_turtle(id).await else {
+        return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}"));
+    };
+    state.tasks.add_turtle(&commander);
     info!("registered turtle: {id}");
 
-    "ACK"
+    Ack::ok(format!("turtle {id} can be given tasks"))
+}
+
+/// Find the existing entry of a turtle that is registering again
+///
+/// The label it was given before is trusted first, as long as that turtle isn't
+/// still reporting in (a copied label on a new turtle), otherwise a silent turtle
+/// last seen in the same spot is taken to be the same one, as two can't share a block
+async fn reregistering(state: &LiveState, req: &turtle::TurtleRegister) -> Option<u32> {
+    if let Some(label) = &req.label {
+        if let Ok(name) = Name::from_str(label) {
+            let id = name.to_num();
+            if let Some(turtle) = state.turtles.get(id as usize) {
+                let turtle = turtle.read().await;
+                if turtle.stale() || turtle.powered_off() {
+                    return Some(id);
+                }
+            }
+        }
+    }
+
+    for (id, turtle) in state.turtles.iter().enumerate() {
+        let turtle = turtle.read().await;
+        if turtle.position.pos == req.position && turtle.stale() {
+            return Some(id as u32);
+        }
+    }
+
+    None
 }
 
 pub(crate) async fn create_turtle(
     State(state): State<SharedControl>,
     Json(req): Json<turtle::TurtleRegister>,
 ) -> Json<turtle::TurtleResponse> {
-    let state = &mut state.write().await;
+    let existing = {
+        let state = state.read().await;
+        match reregistering(&state, &req).await {
+            Some(id) => Some((id, state.turtles[id as usize].clone(), state.tasks.clone())),
+            None => None,
+        }
+    };
+
+    if let Some((id, turtle, tasks)) = existing {
+        let mut turtle = turtle.write().await;
+        turtle.reregister(Position::new(req.position, req.facing), req.fuel, req.fuellimit);
+        if let Some(role) = req.role {
+            turtle.role = role;
+            tasks.set_role(Name::from_num(id), role);
+        }
+        drop(turtle);
+        tasks.cancel(Name::from_num(id));
+
+        info!("turtle {id} registered again");
+
+        return Json(turtle::TurtleResponse {
+            name: Name::from_num(id).to_str(),
+            id,
+            command: turtle::TurtleCommand::Update,
+        });
+    }
+
+    // only held long enough to claim the next id
+    let mut state = state.write().await;
     let id = state.turtles.len() as u32;
     let (send, receive) = mpsc::channel(1);
-    let turtle = turtle::Turtle::with_channel(id, Position::new(req.position, req.facing), req.fuel, req.fuellimit, send,receive);
-    let commander = TurtleCommander::with_turtle(&turtle, state);
-    state.tasks.lock().await.add_turtle(&commander);
+    let mut turtle = turtle::Turtle::with_channel(id, Position::new(req.position, req.facing), req.fuel, req.fuellimit, send,receive);
+    turtle.role = req.role.unwrap_or_default();
+    let commander = TurtleCommander::with_turtle(&turtle, &state);
     state.turtles.push(
         Arc::new(RwLock::new(
             turtle
     )));
+    let tasks = state.tasks.clone();
+    drop(state);
+    tasks.add_turtle(&commander);
 
     info!("new turtle: {id}");
 
@@ -133,6 +268,143 @@ pub(crate) async fn dock(
     Json(commander.dock().await)
 }
 
+pub(crate) async fn refuel(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<usize>, AckResponse> {
+    let Some(commander) = state.read().await.get_turtle(id).await else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+    Ok(Json(commander.refuel().await))
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct EquipRequest {
+    side: Side,
+    /// Inventory slot of the upgrade, whatever was on that side ends up here
+    slot: u32,
+}
+
+/// Put an upgrade such as a pickaxe or modem on a turtle, returning what it has on afterwards
+pub(crate) async fn equip(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+    Json(req): Json<EquipRequest>,
+) -> Result<Json<turtle::Equipped>, AckResponse> {
+    if !(1..=16).contains(&req.slot) {
+        return Err(Ack::err(StatusCode::BAD_REQUEST, format!("slot {} isn't between 1 and 16", req.slot)));
+    }
+    let commander = idle(id, &state).await?;
+    let side = match req.side {
+        Side::Left => TurtleCommand::EquipLeft,
+        Side::Right => TurtleCommand::EquipRight,
+    };
+    commander.execute(TurtleCommand::Select(req.slot)).await;
+    if let TurtleCommandResponse::Failure(reason) = commander.execute(side).await.ret {
+        return Err(Ack::err(StatusCode::BAD_REQUEST, format!("#{id} couldn't equip slot {}: {reason:?}", req.slot)));
+    }
+    info!("#{id} equipped slot {} on the {:?}", req.slot, req.side);
+    look(id, &commander).await
+}
+
+/// The turtle, as long as it isn't running a task that swapping its tools around would upset
+async fn idle(id: u32, state: &SharedControl) -> Result<TurtleCommander, AckResponse> {
+    let Some(commander) = state.read().await.get_turtle(id).await else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+    if commander.scheduler().busy(commander.name()).await {
+        return Err(Ack::err(StatusCode::CONFLICT, format!("#{id} is running a task, cancel it first")));
+    }
+    Ok(commander)
+}
+
+/// What a turtle has on each side, taking each off to look at on clients too old to say
+async fn look(id: u32, commander: &TurtleCommander) -> Result<Json<turtle::Equipped>, AckResponse> {
+    match commander.equipped(true).await {
+        Some(equipped) => Result::Ok(Json(equipped)),
+        None => Err(Ack::err(StatusCode::CONFLICT, format!("#{id} needs an empty slot to look at what it has equipped"))),
+    }
+}
+
+/// What a turtle has on each side, for clients new enough to say without taking anything off
+pub(crate) async fn equipped(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<turtle::Equipped>, AckResponse> {
+    let Some(commander) = state.read().await.get_turtle(id).await else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+    match commander.equipped(false).await {
+        Some(equipped) => Result::Ok(Json(equipped)),
+        None => Err(Ack::err(StatusCode::NOT_IMPLEMENTED, format!("#{id} is too old to say what it has equipped, POST to take each side off to look"))),
+    }
+}
+
+/// What a turtle has on each side, taking each off to look at if it can't say otherwise
+pub(crate) async fn probe_equipped(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<turtle::Equipped>, AckResponse> {
+    let commander = idle(id, &state).await?;
+    look(id, &commander).await
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct CraftRequest {
+    /// Item names, row by row like a crafting table
+    recipe: [Option<String>; 9],
+    /// Most times to craft
+    #[serde(default = "one")]
+    count: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// Craft from what a turtle is carrying, which needs a crafting table equipped
+pub(crate) async fn craft(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+    Json(req): Json<CraftRequest>,
+) -> AckResponse {
+    let Some(commander) = state.read().await.get_turtle(id).await else {
+        return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}"));
+    };
+    match commander.craft(&req.recipe, req.count).await {
+        TurtleCommandResponse::Failure(reason) => Ack::err(StatusCode::CONFLICT, format!("#{id} couldn't craft: {reason:?}")),
+        _ => Ack::ok(format!("#{id} crafted")),
+    }
+}
+
+/// What a turtle is carrying, scanned only if nothing is known
+pub(crate) async fn inventory(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<Vec<Option<turtle::InventorySlot>>>, AckResponse> {
+    let Some(commander) = state.read().await.get_turtle(id).await else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+    Result::Ok(Json(commander.inventory().await))
+}
+
+/// Look through a turtle's inventory again, for when it has changed behind the server's back
+pub(crate) async fn refresh_inventory(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<Vec<Option<turtle::InventorySlot>>>, AckResponse> {
+    let Some(commander) = state.read().await.get_turtle(id).await else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+    Result::Ok(Json(commander.refresh_inventory().await))
+}
+
 pub(crate) async fn run_command(
     Path(id): Path<u32>,
     State(state): State<SharedControl>,
@@ -144,98 +416,445 @@ pub(crate) async fn run_command(
     Json(commander.execute(req).await.ret)
 }
 
+#[derive(Deserialize)]
+pub(crate) struct TaskOptions {
+    /// Simulate the task and log a report instead of running it
+    #[serde(default)]
+    dry_run: bool,
+    /// For quarries, whether to trash junk or bring it all back
+    #[serde(default)]
+    when_full: WhenFull,
+    /// For quarries, leave the outer layer standing
+    #[serde(default)]
+    shell: bool,
+    /// For quarries, leave columns this far apart standing
+    #[serde(default)]
+    pillars: Option<i32>,
+    /// For quarries, the side to dig from
+    #[serde(default)]
+    approach: Approach,
+    /// For quarries, the order to dig each chunk in
+    #[serde(default)]
+    pattern: Pattern,
+    /// For quarries, comma separated blocks to dig, leaving everything else
+    #[serde(default)]
+    only: Option<String>,
+    /// For quarries, comma separated blocks to leave
+    #[serde(default)]
+    except: Option<String>,
+    /// For quarries, "x,y,z" of the depot to unload at instead of the nearest
+    #[serde(default)]
+    depot: Option<String>,
+    /// Go ahead with work far from every depot
+    #[serde(default)]
+    confirm: bool,
+}
+
+impl TaskOptions {
+    fn keep(&self) -> Keep {
+        match (self.shell, self.pillars) {
+            (true, _) => Keep::Shell,
+            (false, Some(spacing)) => Keep::Pillars(spacing),
+            (false, None) => Keep::Nothing,
+        }
+    }
+
+    /// The depot to unload at, which has to be registered
+    async fn depot(&self, depots: &Depots) -> anyhow::Result<Option<Vec3>> {
+        let Some(depot) = self.depot.as_deref() else {
+            return Ok(None);
+        };
+        let coords = depot.split(',').map(|n| n.trim().parse::<i32>()).collect::<Result<Vec<_>, _>>()?;
+        let [x, y, z] = coords[..] else {
+            return Err(anyhow!("depot should be x,y,z, got {depot}"));
+        };
+        let depot = Vec3::new(x, y, z);
+        if !depots.contains(depot).await {
+            return Err(anyhow!("no depot at {}", Coords(depot)));
+        }
+        Ok(Some(depot))
+    }
+}
+
 pub(crate) async fn dig(
     State(state): State<SharedControl>,
+    Query(options): Query<TaskOptions>,
     Json(req): Json<Vec3>,
-) -> &'static str {
+) -> AckResponse {
     let state = state.read().await;
-    let mut schedule = state.tasks.lock().await;
+    let schedule = &state.tasks;
     let size = Vec3::new(16,16,16);
-    schedule.add_task(Box::new(Quarry::new(req,req+size)));
+    let chunk = state.chunk_size().await;
+    let depot = options.depot(&state.depots).await;
+    let warnings = match mine::check_region(req, req+size, &state.depots, &state.world, &state.limits, options.confirm).await {
+        Ok(warnings) => warnings,
+        Err(e) => return Ack::err(StatusCode::BAD_REQUEST, format!("mine request failed: {e}")),
+    };
+    let quarry = Quarry::new(req, req+size, &state.limits, false)
+        .map(|q| match chunk { Some(chunk) => q.chunk_size(chunk), None => q })
+        .and_then(|q| q.when_full(options.when_full).approach(options.approach).pattern(options.pattern).keep(options.keep()))
+        .and_then(|q| Ok(q.filter(BlockFilter::from_lists(options.only.as_deref(), options.except.as_deref())?)))
+        .and_then(|q| Ok(q.output(depot?)));
+    match quarry {
+        Ok(quarry) if options.dry_run => Ack::task_with(schedule.add_task(Box::new(quarry.dry_run())), &warnings),
+        Ok(quarry) => Ack::task_with(schedule.add_task(Box::new(quarry)), &warnings),
+        Err(e) => Ack::err(StatusCode::BAD_REQUEST, format!("mine request failed: {e}")),
+    }
+}
 
-    "ACK"
+#[derive(Deserialize)]
+pub(crate) struct NewDepot {
+    #[serde(flatten)]
+    position: Position,
+    /// Defaults to directly behind the depot
+    staging: Option<Position>,
+    /// Has a chest of spare pickaxes above
+    #[serde(default)]
+    tools: bool,
+    /// Stacks the output chest holds, unlimited if not given
+    capacity: Option<u32>,
 }
 
 pub(crate) async fn new_depot(
     State(state): State<SharedControl>,
-    Json(req): Json<Position>,
-) -> &'static str {
-    let depots = &state.read().await.depots;
-    depots.add(req).await;
+    Json(req): Json<NewDepot>,
+) -> AckResponse {
+    let state = state.read().await;
+    let depot = match req.staging {
+        Some(staging) => Depot::with_staging(req.position, staging),
+        None => Depot::new(req.position),
+    };
+    let depot = Depot { tools: req.tools, output: Chest { capacity: req.capacity, filled: 0 }, ..depot };
+
+    if let Err(e) = depot.validate(&state.world).await {
+        return Ack::err(StatusCode::BAD_REQUEST, format!("depot registration failed: {e}"));
+    }
+
+    state.depots.add(depot).await;
 
-    "ACK"
+    Ack::ok(format!("depot registered at {}", depot.position))
 }
 
-pub(crate) async fn poll(
+/// Register the chests a turtle is sitting at as a depot
+pub(crate) async fn new_depot_here(
+    Path(id): Path<u32>,
     State(state): State<SharedControl>,
-) -> &'static str {
+) -> AckResponse {
+    let turtle = match state.read().await.get_turtle(id).await {
+        Some(turtle) => turtle,
+        None => return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")),
+    };
+
+    let depot = match Depot::here(&turtle).await {
+        Ok(depot) => depot,
+        Err(e) => return Ack::err(StatusCode::BAD_REQUEST, format!("depot registration failed: {e}")),
+    };
+
     let state = state.read().await;
-    let mut schedule = state.tasks.lock().await;
+    if state.depots.contains(depot.position.pos).await {
+        return Ack::err(StatusCode::CONFLICT, format!("depot already registered at {}", depot.position));
+    }
+
+    if let Err(e) = depot.validate(&state.world).await {
+        return Ack::err(StatusCode::BAD_REQUEST, format!("depot registration failed: {e}"));
+    }
+
+    state.depots.add(depot).await;
+
+    Ack::ok(format!("depot registered at {}", depot.position))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EmptyDepot {
+    /// Where the depot is docked at, which unlike its place in the list doesn't change
+    at: Vec3,
+    /// Chest that was emptied, the output chest if not given
+    #[serde(default)]
+    chest: ChestRole,
+}
+
+/// Note that a player emptied one of a depot's chests, so turtles use it again
+pub(crate) async fn empty_depot(
+    State(state): State<SharedControl>,
+    Json(req): Json<EmptyDepot>,
+) -> AckResponse {
+    match state.read().await.depots.emptied(req.at, req.chest).await {
+        Some(depot) => Ack::ok(format!("{:?} chest at {} marked empty", req.chest, depot.position)),
+        None => Ack::err(StatusCode::NOT_FOUND, format!("no depot with a {:?} chest at {}", req.chest, Coords(req.at))),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct HaulRequest {
+    /// Depot to empty
+    from: Vec3,
+    /// Depot to fill
+    to: Vec3,
+}
+
+pub(crate) async fn haul(
+    State(state): State<SharedControl>,
+    Json(req): Json<HaulRequest>,
+) -> AckResponse {
+    let state = state.read().await;
+    let depots = state.depots.clone().to_vec().await;
+    for end in [req.from, req.to] {
+        if !depots.iter().any(|d| d.position.pos == end) {
+            return Ack::err(StatusCode::BAD_REQUEST, format!("no depot at {}", Coords(end)));
+        }
+    }
+
+    Ack::task(state.tasks.add_task(Box::new(Haul::new(req.from, req.to))))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct DeliveryRequest {
+    /// Where to stand to take from the chest ahead
+    source: Position,
+    /// Where to stand to put into the chest ahead
+    dest: Position,
+    /// Item name, such as minecraft:cobblestone
+    item: String,
+    count: u32,
+}
+
+pub(crate) async fn deliver(
+    State(state): State<SharedControl>,
+    Json(req): Json<DeliveryRequest>,
+) -> AckResponse {
+    if req.item.is_empty() || req.count == 0 {
+        return Ack::err(StatusCode::BAD_REQUEST, "nothing to deliver");
+    }
+
+    let delivery = Deliver::new(req.source, req.dest, req.item, req.count);
+    Ack::task(state.read().await.tasks.add_task(Box::new(delivery)))
+}
+
+pub(crate) async fn poll(
+    State(state): State<SharedControl>,
+) -> AckResponse {
+    let schedule = state.read().await.tasks.clone();
     schedule.poll().await;
 
-    "ACK"
+    Ack::ok(format!("polled, {} tasks queued", schedule.task_count()))
 }
 
-pub(crate) async fn shutdown(
+pub(crate) async fn pause(
     State(state): State<SharedControl>,
-) -> &'static str {
-    let signal = {
-        let state = state.read().await;
-        let scheduler = &mut state.tasks.lock().await;
-        let signal = scheduler.shutdown();
-        scheduler.poll().await;
-        signal
+) -> AckResponse {
+    state.read().await.tasks.pause();
+
+    Ack::ok("scheduler paused")
+}
+
+pub(crate) async fn scheduler_status(
+    State(state): State<SharedControl>,
+) -> Json<SchedulerStatus> {
+    let scheduler = state.read().await.tasks.clone();
+    Json(scheduler.status().await)
+}
+
+/// Queued tasks with their progress
+pub(crate) async fn tasks(
+    State(state): State<SharedControl>,
+) -> Json<Vec<TaskInfo>> {
+    let scheduler = state.read().await.tasks.clone();
+    Json(scheduler.tasks().await)
+}
+
+/// Recently finished tasks, with what each was asked to do
+pub(crate) async fn archive(
+    State(state): State<SharedControl>,
+) -> Json<Vec<Archived>> {
+    let scheduler = state.read().await.tasks.clone();
+    Json(scheduler.archive().await)
+}
+
+/// Queue a fresh copy of a finished task
+pub(crate) async fn rerun(
+    Path(id): Path<u64>,
+    State(state): State<SharedControl>,
+) -> AckResponse {
+    let scheduler = state.read().await.tasks.clone();
+    let Some(archived) = scheduler.archive().await.into_iter().find(|a| a.id() == id) else {
+        return Ack::err(StatusCode::NOT_FOUND, format!("no finished task {id}"));
     };
+    match archived.fresh() {
+        Result::Ok(task) => Ack::task(scheduler.add_task(task)),
+        Err(e) => Ack::err(StatusCode::CONFLICT, format!("can't run task {id} again: {e}")),
+    }
+}
+
+pub(crate) async fn resume(
+    State(state): State<SharedControl>,
+) -> AckResponse {
+    state.read().await.tasks.resume();
+
+    Ack::ok("scheduler resumed")
+}
+
+pub(crate) async fn shutdown(
+    State(state): State<SharedControl>,
+) -> AckResponse {
+    let scheduler = state.read().await.tasks.clone();
 
     info!("waiting for tasks to finish");
-    signal.await.unwrap();
+    scheduler.shutdown().await;
 
+    // the write lock only waits out handlers already running
     info!("waiting for lock");
     let state = state.write().await;
     info!("waiting for connections to finish");
     state.kill.send(true).unwrap();
 
-    "ACK"
+    Ack::ok("tasks stopped, shutting down")
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct FarmOptions {
+    /// Comma separated substrings of log names, "log" if unset
+    logs: Option<String>,
+    /// Comma separated substrings of sapling names, "sapling" if unset
+    saplings: Option<String>,
+    /// Comma separated substrings of soil names, "dirt" if unset
+    soils: Option<String>,
 }
 
 pub(crate) async fn fell(
     State(state): State<SharedControl>,
+    Query(options): Query<FarmOptions>,
     Json(req): Json<Vec3>,
-) -> &'static str {
-    let schedule = &mut state.write().await.tasks;
-    schedule.lock().await.add_task(Box::new(TreeFarm::new(req)));
+) -> AckResponse {
+    let kinds = TreeKinds::from_lists(options.logs.as_deref(), options.saplings.as_deref(), options.soils.as_deref());
+    Ack::task(state.read().await.tasks.add_task(Box::new(TreeFarm::new(req).kinds(kinds))))
+}
 
-    "ACK"
+#[derive(Deserialize, Debug)]
+pub(crate) struct GoalOptions {
+    /// Seconds to keep trying, GOTO_TIMEOUT if unset
+    timeout: Option<u64>,
+    /// Look at unseen blocks before moving into them
+    #[serde(default)]
+    careful: bool,
+    /// Tunnel through junk in the way rather than only going around it, unless turned off
+    #[serde(default = "yes")]
+    dig: bool,
+}
+
+fn yes() -> bool {
+    true
 }
 
 #[tracing::instrument(skip(state))]
 pub(crate) async fn set_goal(
     Path(id): Path<u32>,
     State(state): State<SharedControl>,
+    Query(options): Query<GoalOptions>,
     Json(req): Json<Position>,
-) -> &'static str {
-    let turtle = state.read().await.get_turtle(id).await.unwrap().clone();
+) -> AckResponse {
+    let turtle = match state.read().await.get_turtle(id).await {
+        Some(turtle) => turtle,
+        None => return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")),
+    };
     drop(state);
-    tokio::spawn(async move {turtle.goto(req).await.expect("route failed")});
+    let limit = Duration::from_secs(options.timeout.unwrap_or(GOTO_TIMEOUT));
+    tokio::spawn(async move {
+        if turtle.goto_within(req, limit, options.careful, options.dig).await.is_none() {
+            error!("turtle {id} could not reach {req}");
+        }
+    });
 
-    "ACK"
+    Ack::ok(format!("turtle {id} heading to {req} for up to {}s", limit.as_secs()))
+}
+
+/// Restrict the tasks a turtle is given
+pub(crate) async fn set_role(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+    Json(req): Json<Role>,
+) -> AckResponse {
+    let state = state.read().await;
+    let turtle = match state.turtles.get(id as usize) {
+        Some(turtle) => turtle,
+        None => return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")),
+    };
+    turtle.write().await.role = req;
+    state.tasks.set_role(Name::from_num(id), req);
+
+    Ack::ok(format!("turtle {id} is now a {req:?} turtle"))
+}
+
+/// Where to park the turtle when idle, null to leave it where it stops
+pub(crate) async fn set_home(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+    Json(req): Json<Option<Position>>,
+) -> AckResponse {
+    let state = state.read().await;
+    let turtle = match state.turtles.get(id as usize) {
+        Some(turtle) => turtle,
+        None => return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")),
+    };
+    turtle.write().await.home = req;
+    state.tasks.set_home(Name::from_num(id), req);
+
+    match req {
+        Some(home) => Ack::ok(format!("turtle {id} now parks at {home}")),
+        None => Ack::ok(format!("turtle {id} no longer has a home")),
+    }
+}
+
+/// Slots the turtle can fill before unloading, fewer if it keeps some for itself
+pub(crate) async fn set_usable_slots(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+    Json(req): Json<u32>,
+) -> AckResponse {
+    if !(1..16).contains(&req) {
+        return Ack::err(StatusCode::BAD_REQUEST, format!("usable slots should be 1 to 15, not {req}"));
+    }
+    let state = state.read().await;
+    let turtle = match state.turtles.get(id as usize) {
+        Some(turtle) => turtle,
+        None => return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")),
+    };
+    turtle.read().await.usable_slots.store(req, Ordering::SeqCst);
+
+    Ack::ok(format!("turtle {id} now unloads with more than {req} slots full"))
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct CancelOptions {
+    /// Let the task hand its work back to be picked up later, never aborting it
+    #[serde(default)]
+    requeue: bool,
 }
 
 pub(crate) async fn cancel(
     Path(id): Path<u32>,
     State(state): State<SharedControl>,
-) -> &'static str {
-    state.read().await.tasks.lock().await.cancel(Name::from_num(id)).await;
+    Query(options): Query<CancelOptions>,
+) -> AckResponse {
+    let state = state.read().await;
+    if id as usize >= state.turtles.len() {
+        return Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}"));
+    }
+    if options.requeue {
+        state.tasks.requeue(Name::from_num(id));
+        return Ack::ok(format!("requeued task of turtle {id}"));
+    }
+    state.tasks.cancel(Name::from_num(id));
 
-    "ACK"
+    Ack::ok(format!("canceled task of turtle {id}"))
 }
 
-pub(crate) async fn update_turtles(State(state): State<SharedControl>) -> &'static str {
-    for turtle in state.read().await.turtles.iter() {
+pub(crate) async fn update_turtles(State(state): State<SharedControl>) -> AckResponse {
+    let state = state.read().await;
+    for turtle in state.turtles.iter() {
             turtle.write().await.pending_update = true;
     }
 
-    "ACK"
+    Ack::ok(format!("{} turtles will update", state.turtles.len()))
 }
 
 pub(crate) async fn turtle_info(
@@ -245,39 +864,74 @@ pub(crate) async fn turtle_info(
     let state = &mut state.read().await;
     let turtle = &state.turtles[id as usize].read().await;
 
-    let cloned = Turtle::new( 
+    let mut cloned = Turtle::new( 
         turtle.name.to_num(),
         turtle.position,
         turtle.fuel,
         turtle.fuel_limit,
     );
+    cloned.fuel_used = turtle.fuel_used;
 
     Json(cloned)
 }
 
+/// Last few commands sent to a turtle and their responses
+pub(crate) async fn history(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<VecDeque<turtle::HistoryEntry>>, AckResponse> {
+    let state = &mut state.read().await;
+    let Some(turtle) = state.turtles.get(id as usize) else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+
+    let history = turtle.read().await.history.clone();
+    Ok(Json(history))
+}
+
+/// How the link to the turtle is holding up
+pub(crate) async fn stats(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+) -> Result<Json<turtle::LinkStats>, AckResponse> {
+    let state = &mut state.read().await;
+    let Some(turtle) = state.turtles.get(id as usize) else {
+        return Err(Ack::err(StatusCode::NOT_FOUND, format!("no turtle {id}")));
+    };
+
+    let stats = turtle.read().await.stats.clone();
+    Ok(Json(stats))
+}
+
 pub(crate) async fn command(
     Path(id): Path<u32>,
     State(state): State<SharedControl>,
     Json(req): Json<turtle::TurtleUpdate>,
-) -> Json<turtle::TurtleCommand> {
-    trace!("reply from turtle {id}: {req:?}");
-    let state_guard = state.clone().read_owned().await;
-    let turtle_commander = state_guard.get_turtle(id).await;
+) -> ([(&'static str, String); 1], Json<turtle::TurtleCommand>) {
+    let (token, command) = reply(id, &state, req).await;
+    ([(TOKEN_HEADER, token.to_string())], Json(command))
+}
 
-    if id as usize > state_guard.turtles.len() {
-        return Json(turtle::TurtleCommand::Update);
-    }
+/// Next command for the turtle and its token, after taking in its update
+async fn reply(id: u32, state: &SharedControl, req: turtle::TurtleUpdate) -> (u64, turtle::TurtleCommand) {
+    trace!("reply from turtle {id}: {req:?}");
+    // this waits on the next command, so only handles are kept
+    let (turtle, world, schedule, positions, ready, turtle_commander) = {
+        let state = state.read().await;
+        let Some(turtle) = state.turtles.get(id as usize).cloned() else {
+            return (0, turtle::TurtleCommand::Update);
+        };
+        (turtle, state.world.clone(), state.tasks.clone(), state.positions.clone(), state.ready.clone(), state.get_turtle(id).await)
+    };
 
-    let command = turtle::process_turtle_update(id, &state_guard, req).await;
+    let command = turtle::process_turtle_update(&turtle, &world, &schedule, &positions, req).await;
+    let token = turtle.read().await.token;
 
     let command = match command {
         Some(command) => command,
         None => {
             tokio::spawn(async move {
-                let state = &state.clone();
-                if Instant::elapsed(&state.clone().read().await.started).as_secs_f64() > STARTUP_ALLOWANCE {
-                    let state = state.read().await;
-                    let mut schedule = state.tasks.lock().await;
+                if ready.load(Ordering::Relaxed) {
                     trace!("idle, polling");
                     schedule.add_turtle(&turtle_commander.unwrap());
                     schedule.poll().await;
@@ -287,16 +941,96 @@ pub(crate) async fn command(
         },
     };
 
-    Json(command)
+    (token, command)
+}
+
+/// A command sent over a websocket, which has no headers to carry the token
+#[derive(Serialize)]
+struct Pushed {
+    token: u64,
+    command: turtle::TurtleCommand,
+}
+
+/// Updates and commands as with `/update`, but over a websocket
+///
+/// A turtle told to wait is sent whatever comes up meanwhile, rather than
+/// finding out once it asks again. Clients that can't open one keep polling
+pub(crate) async fn websocket(
+    Path(id): Path<u32>,
+    State(state): State<SharedControl>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| connected(socket, id, state))
+}
+
+async fn connected(mut socket: WebSocket, id: u32, state: SharedControl) {
+    info!("turtle {id} connected over websocket");
+    let (turtle, tasks) = {
+        let state = state.read().await;
+        let Some(turtle) = state.turtles.get(id as usize).cloned() else {
+            warn!("websocket for unknown turtle {id}");
+            return;
+        };
+        (turtle, state.tasks.clone())
+    };
+
+    let mut waiting = false;
+    loop {
+        let message = match waiting {
+            true => tokio::select! {
+                message = socket.recv() => message,
+                Some((token, command)) = turtle::push_command(&turtle, &tasks, Duration::from_secs(IDLE_TIME as u64)) => {
+                    waiting = false;
+                    match send(&mut socket, token, command).await {
+                        true => continue,
+                        false => break,
+                    }
+                },
+            },
+            false => socket.recv().await,
+        };
+
+        let update = match message {
+            Some(Ok(Message::Text(text))) => match serde_json::from_str::<turtle::TurtleUpdate>(&text) {
+                Ok(update) => update,
+                Err(e) => {
+                    warn!("bad update from turtle {id}: {e}");
+                    continue;
+                },
+            },
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(_)) => continue,
+        };
+
+        let (token, command) = reply(id, &state, update).await;
+        waiting = matches!(command, turtle::TurtleCommand::Wait(IDLE_TIME));
+        if !send(&mut socket, token, command).await {
+            break;
+        }
+    }
+    info!("turtle {id} disconnected from websocket");
+}
+
+/// False once the socket is gone
+async fn send(socket: &mut WebSocket, token: u64, command: turtle::TurtleCommand) -> bool {
+    let pushed = serde_json::to_string(&Pushed { token, command }).unwrap();
+    socket.send(Message::Text(pushed)).await.is_ok()
 }
 
 pub(crate) async fn build(
     State(state): State<SharedControl>,
+    Query(options): Query<TaskOptions>,
     Json(req): Json<Vec3>,
-) -> &'static str {
-    let state = state.read().await;
-    let mut schedule = state.tasks.lock().await;
-    let schematic = Schematic::load(&mut fs::File::open("schematics/greek-athelete1.schematic").await.unwrap().into_std().await).unwrap();
+) -> AckResponse {
+    let schedule = state.read().await.tasks.clone();
+    let file = match fs::File::open("schematics/greek-athelete1.schematic").await {
+        Ok(file) => file,
+        Err(e) => return Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("no schematic: {e}")),
+    };
+    let schematic = match Schematic::load(&mut file.into_std().await) {
+        Ok(schematic) => schematic,
+        Err(e) => return Ack::err(StatusCode::INTERNAL_SERVER_ERROR, format!("{e}")),
+    };
 
     let input = Position::new(
         Vec3::new(53,73,77),
@@ -308,18 +1042,766 @@ pub(crate) async fn build(
         BuildSimple::new(req, &schematic, input)
     }).await.unwrap();
 
-    schedule.add_task(Box::new(builder));
-
-    "ACK"
+    let builder = if options.dry_run { builder.dry_run() } else { builder };
+    let omitted = builder.omitted();
+    Ack::task_with(schedule.add_task(Box::new(builder)), &omitted)
 }
 
-pub(crate) async fn client() -> String {
-    formatdoc!(r#"
+pub(crate) async fn client() -> Result<String, (StatusCode, String)> {
+    let path = CLIENT.get().unwrap();
+    // TODO: cache handle if bottleneck
+    let client = match fs::read_to_string(path).await {
+        Ok(client) => client,
+        Err(e) => {
+            error!("could not read client from {}, set AVARUS_CLIENT to its path: {e}", path.display());
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("client unavailable: {e}")));
+        },
+    };
+    Ok(formatdoc!(r#"
     local ipaddr = {}
     local port = "{}"
     {}"#,
         include_str!("../ipaddr.txt"),
         PORT.get().unwrap(),
-        fs::read_to_string("../client/client.lua").await.unwrap(), // TODO: cache handle if bottleneck
-    )
+        client,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::{Block, SharedWorld, World};
+    use crate::tasks::{Scheduler, Task, TaskState, Limits};
+    use crate::turtle::{FailureReason, TurtleUpdate, TurtleRegister, InventorySlot, RETRIES};
+    use std::collections::HashMap;
+    use std::sync::atomic::AtomicBool;
+    use tokio::sync::watch;
+
+    /// Stands in for client.lua, carrying out commands in its own world
+    struct FakeTurtle {
+        pos: Position,
+        fuel: usize,
+        world: SharedWorld,
+        inventory: Vec<Option<InventorySlot>>,
+        selected: usize,
+        /// Slots of the chests in the world, by where they are
+        chests: HashMap<Vec3, Vec<Option<InventorySlot>>>,
+        /// Where each fake turtle sharing the world is, so they get in each other's way
+        fleet: Arc<std::sync::Mutex<Vec<Vec3>>>,
+        /// This turtle's place in the fleet
+        index: usize,
+    }
+
+    fn stack(name: &str, count: u32) -> Option<InventorySlot> {
+        Some(InventorySlot { name: name.into(), count, detail: Default::default() })
+    }
+
+    impl FakeTurtle {
+        fn new(pos: Position, fuel: usize, world: SharedWorld) -> Self {
+            let fleet = Arc::new(std::sync::Mutex::new(vec![pos.pos]));
+            Self { pos, fuel, world, inventory: vec![None; 16], selected: 1, chests: HashMap::new(), fleet, index: 0 }
+        }
+
+        /// Another turtle in the same world
+        fn another(&self, pos: Position) -> Self {
+            let mut fleet = self.fleet.lock().unwrap();
+            fleet.push(pos.pos);
+            Self { pos, index: fleet.len() - 1, fleet: self.fleet.clone(), ..FakeTurtle::new(pos, self.fuel, self.world.clone()) }
+        }
+
+        /// Whether one of the other turtles is at a position
+        fn turtle_at(&self, at: Vec3) -> bool {
+            self.fleet.lock().unwrap().iter().enumerate().any(|(i, p)| i != self.index && *p == at)
+        }
+
+        /// Put a chest in the world holding these slots
+        async fn chest(&mut self, at: Vec3, slots: Vec<Option<InventorySlot>>) {
+            self.world.set(Block::new("minecraft:chest", at)).await;
+            self.chests.insert(at, slots);
+        }
+
+        /// Take from the first filled slot of the chest, as the game does
+        fn suck(&mut self, at: Vec3, count: u32) -> TurtleCommandResponse {
+            let held = self.inventory[self.selected - 1].clone();
+            let Some(chest) = self.chests.get_mut(&at) else {
+                return TurtleCommandResponse::Failure(FailureReason::NoItems);
+            };
+            let Some(from) = chest.iter_mut().find(|s| s.is_some()) else {
+                return TurtleCommandResponse::Failure(FailureReason::NoItems);
+            };
+            let item = from.as_mut().unwrap();
+            let room = match &held {
+                None => 64,
+                Some(held) if held.name == item.name => 64 - held.count,
+                Some(_) => 0,
+            };
+            let taken = count.min(item.count).min(room);
+            if taken == 0 {
+                return TurtleCommandResponse::Failure(FailureReason::InventoryFull);
+            }
+            let name = item.name.clone();
+            item.count -= taken;
+            if item.count == 0 {
+                *from = None;
+            }
+            let held = &mut self.inventory[self.selected - 1];
+            let before = held.as_ref().map_or(0, |h| h.count);
+            *held = stack(&name, before + taken);
+            TurtleCommandResponse::Success
+        }
+
+        /// Fill matching stacks then the first empty slot, failing only if nothing fit
+        fn drop_into(&mut self, at: Vec3, count: u32) -> TurtleCommandResponse {
+            let Some(held) = self.inventory[self.selected - 1].clone() else {
+                return TurtleCommandResponse::Failure(FailureReason::NoItems);
+            };
+            let Some(chest) = self.chests.get_mut(&at) else {
+                return TurtleCommandResponse::Failure(FailureReason::Other("no chest".into()));
+            };
+            let mut left = count.min(held.count);
+            for slot in chest.iter_mut().filter(|s| s.as_ref().is_some_and(|s| s.name == held.name)) {
+                let item = slot.as_mut().unwrap();
+                let moved = left.min(64 - item.count);
+                item.count += moved;
+                left -= moved;
+            }
+            if let Some(slot) = chest.iter_mut().find(|s| s.is_none()).filter(|_| left > 0) {
+                *slot = stack(&held.name, left);
+                left = 0;
+            }
+            let dropped = count.min(held.count) - left;
+            if dropped == 0 {
+                return TurtleCommandResponse::Failure(FailureReason::Other("No space for items".into()));
+            }
+            let remaining = held.count - dropped;
+            self.inventory[self.selected - 1] = (remaining > 0).then(|| stack(&held.name, remaining)).flatten();
+            TurtleCommandResponse::Success
+        }
+
+        async fn name(&self, at: Vec3) -> String {
+            if self.turtle_at(at) {
+                return "computercraft:turtle_normal".into();
+            }
+            self.world.get(at).await.map(|b| b.name).unwrap_or("minecraft:air".into())
+        }
+
+        async fn run(&mut self, command: TurtleCommand) -> TurtleUpdate {
+            use TurtleCommand::*;
+            let pos = self.pos;
+            let ret = match command {
+                Left => { self.pos.dir = pos.dir.left(); TurtleCommandResponse::Success },
+                Right => { self.pos.dir = pos.dir.right(); TurtleCommandResponse::Success },
+                Forward(_) | Backward(_) | Up(_) | Down(_) | ForwardUp | ForwardDown => {
+                    let mut ret = TurtleCommandResponse::Success;
+                    for step in command.moves(pos.dir) {
+                        let next = self.pos.pos + step;
+                        if self.world.occupied(next).await || self.turtle_at(next) {
+                            ret = TurtleCommandResponse::Failure(FailureReason::Obstructed);
+                            break;
+                        }
+                        self.pos.pos = next;
+                        self.fuel -= 1;
+                    }
+                    self.fleet.lock().unwrap()[self.index] = self.pos.pos;
+                    ret
+                },
+                Dig | DigUp | DigDown => {
+                    let target = match command {
+                        Dig => pos.pos + pos.dir.unit(),
+                        DigUp => pos.pos + Vec3::y(),
+                        _ => pos.pos - Vec3::y(),
+                    };
+                    match self.world.occupied(target).await {
+                        true => {
+                            self.world.set(Block::new("minecraft:air", target)).await;
+                            TurtleCommandResponse::Success
+                        },
+                        false => TurtleCommandResponse::Failure(FailureReason::NothingToDig),
+                    }
+                },
+                ItemInfo(slot) | ItemDetail(slot) => match &self.inventory[slot as usize - 1] {
+                    Some(item) => TurtleCommandResponse::Item(item.clone()),
+                    None => TurtleCommandResponse::None,
+                },
+                Select(slot) => { self.selected = slot as usize; TurtleCommandResponse::Success },
+                SuckFront(count) => self.suck(pos.pos + pos.dir.unit(), count),
+                DropFront(count) => self.drop_into(pos.pos + pos.dir.unit(), count),
+                Locate => TurtleCommandResponse::Location(self.pos.pos),
+                Batch(commands) => {
+                    let mut steps = Vec::new();
+                    for command in commands {
+                        let step = Box::pin(self.run(command)).await;
+                        let failed = matches!(step.ret, TurtleCommandResponse::Failure(_));
+                        steps.push(step);
+                        if failed {
+                            break;
+                        }
+                    }
+                    TurtleCommandResponse::Batch(steps)
+                },
+                _ => TurtleCommandResponse::Success,
+            };
+
+            TurtleUpdate {
+                fuel: self.fuel,
+                ahead: self.name(self.pos.pos + self.pos.dir.unit()).await,
+                above: self.name(self.pos.pos + Vec3::y()).await,
+                below: self.name(self.pos.pos - Vec3::y()).await,
+                ret,
+                token: None,
+            }
+        }
+    }
+
+    fn live_state() -> SharedControl {
+        let (kill, _) = watch::channel(false);
+        SharedControl::new(RwLock::new(LiveState {
+            turtles: Vec::new(),
+            tasks: Scheduler::default().spawn(),
+            world: SharedWorld::new(),
+            depots: Depots::from_vec(Vec::new()),
+            positions: Default::default(),
+            started: tokio::time::Instant::now(),
+            boots: 1,
+            ready: Arc::new(AtomicBool::new(false)),
+            kill,
+            restore: None,
+            limits: Limits::default(),
+            forms: Default::default(),
+        }))
+    }
+
+    /// Register a turtle standing where `turtle` is, with a full tank
+    async fn register(state: &SharedControl, turtle: &FakeTurtle, role: Option<Role>) -> turtle::TurtleResponse {
+        let Json(registered) = create_turtle(State(state.clone()), Json(TurtleRegister {
+            label: None,
+            fuel: turtle.fuel,
+            fuellimit: turtle.fuel,
+            position: turtle.pos.pos,
+            facing: turtle.pos.dir,
+            role,
+        })).await;
+        registered
+    }
+
+    /// Answer commands until the turtle is asked for a GPS fix
+    async fn until_locate(turtle: &mut FakeTurtle, state: &SharedControl, id: u32, next: &mut TurtleCommand) {
+        for _ in 0..3 {
+            if matches!(next, TurtleCommand::Locate) {
+                break;
+            }
+            let update = turtle.run(next.clone()).await;
+            (_, Json(*next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        }
+        assert!(matches!(next, TurtleCommand::Locate));
+    }
+
+    /// Answer commands until `task` is done, counting the inventory lookups
+    async fn serve<T>(turtle: &mut FakeTurtle, state: &SharedControl, id: u32, next: &mut TurtleCommand, task: tokio::task::JoinHandle<T>) -> (T, usize) {
+        let mut lookups = 0;
+        while !task.is_finished() {
+            if matches!(next, TurtleCommand::ItemInfo(_)) {
+                lookups += 1;
+            }
+            let update = turtle.run(next.clone()).await;
+            (_, Json(*next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        }
+        (task.await.unwrap(), lookups)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn register_and_quarry() {
+        let state = live_state();
+
+        let lower = Vec3::new(0, 0, 0);
+        let upper = Vec3::new(4, 4, 4);
+        let world = SharedWorld::new();
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    world.set(Block::new("minecraft:stone", Vec3::new(x, y, z))).await;
+                }
+            }
+        }
+        let start = Position::new(Vec3::new(2, 8, 2), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100_000, world.clone());
+
+        let registered = register(&state, &turtle, Some(Role::Miner)).await;
+        let id = registered.id;
+
+        let tasks = state.read().await.tasks.clone();
+        tasks.add_task(Box::new(Quarry::new(lower, upper, &Limits::default(), false).unwrap()));
+
+        let mut next = registered.command;
+        // nothing is handed out until the other turtles have had a chance to check in
+        for _ in 0..4 {
+            let update = turtle.run(next).await;
+            (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+            assert!(matches!(next, TurtleCommand::Wait(IDLE_TIME) | TurtleCommand::Update | TurtleCommand::Locate), "{next:?} before startup");
+        }
+        assert_eq!(turtle.pos, start);
+
+        state.read().await.ready.store(true, Ordering::Relaxed);
+        let finished = tokio::time::timeout(Duration::from_secs(120), async {
+            while tasks.task_count() > 0 {
+                let update = turtle.run(next).await;
+                (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+            }
+        }).await;
+        assert!(finished.is_ok(), "quarry still running at {}", turtle.pos);
+
+        for x in 0..4 {
+            for y in 0..4 {
+                for z in 0..4 {
+                    assert!(!world.occupied(Vec3::new(x, y, z)).await, "{} left standing", Coords(Vec3::new(x, y, z)));
+                }
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn detached_dry_run() {
+        let state = live_state();
+        let start = Position::new(Vec3::new(2, 8, 2), Direction::North);
+        let turtle = FakeTurtle::new(start, 100_000, SharedWorld::new());
+        let registered = register(&state, &turtle, Some(Role::Miner)).await;
+        let world = state.read().await.world.clone();
+        world.set(Block::new("minecraft:stone", Vec3::new(1, 1, 1))).await;
+
+        let tasks = state.read().await.tasks.clone();
+        tasks.add_task(Box::new(Quarry::new(Vec3::zeros(), Vec3::new(4, 4, 4), &Limits::default(), false).unwrap().dry_run()));
+        state.read().await.ready.store(true, Ordering::Relaxed);
+
+        // the turtle never answers, so this only finishes if it was left alone
+        let finished = tokio::time::timeout(Duration::from_secs(60), async {
+            loop {
+                tasks.poll().await;
+                if tasks.task_count() == 0 {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await;
+        assert!(finished.is_ok(), "dry run still going");
+        assert!(world.occupied(Vec3::new(1, 1, 1)).await);
+        assert_eq!(state.read().await.get_turtle(registered.id).await.unwrap().pos().await, start);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chunk_size() {
+        let state = live_state();
+        assert_eq!(state.read().await.chunk_size().await, None);
+
+        let here = Position::new(Vec3::zeros(), Direction::North);
+        let miner = register(&state, &FakeTurtle::new(here, 100_000, SharedWorld::new()), Some(Role::Miner)).await;
+        // never given a quarry, so its small tank doesn't count
+        register(&state, &FakeTurtle::new(here, 100, SharedWorld::new()), Some(Role::Builder)).await;
+        assert_eq!(state.read().await.chunk_size().await, Some(Vec3::new(6, 4, 6)));
+
+        set_usable_slots(Path(miner.id), State(state.clone()), Json(4)).await;
+        assert_eq!(state.read().await.chunk_size().await, Some(Vec3::new(4, 4, 4)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn copied_label() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100, SharedWorld::new());
+        let labelled = |label: Option<String>, pos: Vec3| TurtleRegister {
+            label,
+            fuel: 100,
+            fuellimit: 100,
+            position: pos,
+            facing: Direction::North,
+            role: None,
+        };
+        let Json(registered) = create_turtle(State(state.clone()), Json(labelled(None, start.pos))).await;
+        let id = registered.id;
+
+        // silent so far, so a restart under its label takes the entry back
+        let Json(again) = create_turtle(State(state.clone()), Json(labelled(Some(registered.name.clone()), start.pos))).await;
+        assert_eq!(again.id, id);
+
+        let update = turtle.run(again.command).await;
+        let _ = command(Path(id), State(state.clone()), Json(update)).await;
+
+        // still reporting in, so this is another turtle carrying a copied label
+        let Json(copy) = create_turtle(State(state.clone()), Json(labelled(Some(registered.name), Vec3::new(5, 0, 0)))).await;
+        assert_ne!(copy.id, id);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cached_inventory() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let id = registered.id;
+        let mut next = registered.command;
+
+        let first = tokio::spawn(inventory(Path(id), State(state.clone())));
+        let (scanned, lookups) = serve(&mut turtle, &state, id, &mut next, first).await;
+        assert!(scanned.is_ok_and(|Json(slots)| slots.len() == 16));
+        assert_eq!(lookups, 16);
+
+        // nobody is answering, so this only returns if nothing was asked
+        let cached = tokio::time::timeout(Duration::from_secs(1), inventory(Path(id), State(state.clone()))).await;
+        assert!(cached.is_ok_and(|c| c.is_ok()));
+
+        let refresh = tokio::spawn(refresh_inventory(Path(id), State(state.clone())));
+        let (_, lookups) = serve(&mut turtle, &state, id, &mut next, refresh).await;
+        assert_eq!(lookups, 16);
+
+        assert!(inventory(Path(id + 1), State(state.clone())).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn every_layer_built() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let turtle = FakeTurtle::new(start, 100_000, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let commander = state.read().await.get_turtle(registered.id).await.unwrap();
+
+        let mut column = World::new();
+        for y in 0..3 {
+            column.set(Block::new("minecraft:stone", Vec3::new(0, y, 0)));
+        }
+        let mut build = BuildSimple::from_region(Vec3::new(4, 0, 4), Vec3::new(1, 3, 1), column, start).dry_run();
+
+        let mut layers = 0;
+        loop {
+            match build.poll() {
+                TaskState::Complete => break,
+                TaskState::ReadyFor(..) | TaskState::Ready(..) => {
+                    layers += 1;
+                    assert!(layers <= 3, "built past the top layer");
+                    let layer = build.run(commander.clone());
+                    while !layer.is_finished() {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                },
+                TaskState::Waiting => panic!("waiting with no layer being built"),
+            }
+        }
+        assert_eq!(layers, 3);
+
+        let progress = serde_json::to_value(build.progress().unwrap()).unwrap();
+        assert_eq!(progress["done"], 3);
+        assert_eq!(progress["total"], 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn gps_correction() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let id = registered.id;
+        let mut next = registered.command;
+
+        // shoved by a piston, which costs no fuel
+        turtle.pos.pos += Vec3::new(2, 0, 0);
+
+        until_locate(&mut turtle, &state, id, &mut next).await;
+
+        let update = turtle.run(next).await;
+        let (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        assert!(!matches!(next, TurtleCommand::Locate));
+        let tracked = state.read().await.turtles[id as usize].read().await.position.pos;
+        assert_eq!(tracked, turtle.pos.pos);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn no_gps() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let id = registered.id;
+        let mut next = registered.command;
+
+        until_locate(&mut turtle, &state, id, &mut next).await;
+
+        // no modem to get a fix with
+        let mut update = turtle.run(next).await;
+        update.ret = TurtleCommandResponse::Failure(FailureReason::Other("No wireless modem".into()));
+        command(Path(id), State(state.clone()), Json(update)).await;
+        let tracked = state.read().await.turtles[id as usize].clone();
+        assert!(tracked.read().await.no_gps);
+        assert_eq!(serde_json::to_value(&tracked.read().await.stats).unwrap()["failures"], 0);
+
+        // long past when it would be asked again
+        tracked.write().await.located = None;
+        let update = turtle.run(TurtleCommand::Update).await;
+        let (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        assert!(!matches!(next, TurtleCommand::Locate));
+    }
+
+    #[tokio::test]
+    async fn full_depots() {
+        let near = Position::new(Vec3::zeros(), Direction::North);
+        let far = Position::new(Vec3::new(10, 0, 0), Direction::North);
+        let depots = Depots::from_vec(vec![
+            Depot { output: Chest { capacity: Some(27), filled: 0 }, ..Depot::new(near) },
+            Depot::new(far),
+        ]);
+        let nearest = || async { depots.nearest(near).await.unwrap().position().pos };
+        assert_eq!(nearest().await, near.pos);
+
+        depots.dropped(near.pos, ChestRole::Output, 12).await;
+        assert_eq!(nearest().await, far.pos);
+        // worn pickaxes going back ahead don't fill the output
+        depots.dropped(far.pos, ChestRole::Fuel, 3).await;
+        assert_eq!(depots.clone().to_vec().await[1].fuel.filled, 3);
+        assert_eq!(depots.clone().to_vec().await[1].output.filled, 0);
+
+        // the far chest turns out to be small too, so neither is better
+        depots.dropped(far.pos, ChestRole::Output, 20).await;
+        depots.overflowed(far.pos, ChestRole::Output).await;
+        assert_eq!(depots.clone().to_vec().await[1].output.capacity, Some(20));
+        assert_eq!(nearest().await, near.pos);
+
+        assert!(depots.emptied(far.pos, ChestRole::Output).await.is_some());
+        assert_eq!(nearest().await, far.pos);
+        assert!(depots.emptied(near.pos, ChestRole::Output).await.is_some());
+        assert_eq!(nearest().await, near.pos);
+        assert!(depots.emptied(Vec3::new(5, 0, 0), ChestRole::Output).await.is_none());
+        // neither has a tool chest
+        assert!(depots.emptied(near.pos, ChestRole::Tools).await.is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn pushed_commands() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let id = registered.id;
+        let mut next = registered.command;
+
+        for _ in 0..4 {
+            if matches!(next, TurtleCommand::Wait(IDLE_TIME)) {
+                break;
+            }
+            let update = turtle.run(next.clone()).await;
+            (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        }
+        assert!(matches!(next, TurtleCommand::Wait(IDLE_TIME)));
+
+        let (handle, tasks) = {
+            let state = state.read().await;
+            (state.turtles[id as usize].clone(), state.tasks.clone())
+        };
+        assert!(turtle::push_command(&handle, &tasks, Duration::from_millis(500)).await.is_none());
+
+        // handed over while the turtle is still waiting, not once it next asks
+        let commander = state.read().await.get_turtle(id).await.unwrap();
+        let turned = tokio::spawn(async move { commander.execute(TurtleCommand::Left).await });
+        let (token, pushed) = turtle::push_command(&handle, &tasks, Duration::from_secs(5)).await.unwrap();
+        assert!(matches!(pushed, TurtleCommand::Left));
+
+        let update = TurtleUpdate { token: Some(token), ..turtle.run(pushed).await };
+        let _ = command(Path(id), State(state.clone()), Json(update)).await;
+        assert_eq!(turned.await.unwrap().pos.dir, start.dir.left());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn careful_through_unmapped() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 1000, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let id = registered.id;
+        let mut next = registered.command;
+
+        // farther than there are retries, with every block unseen
+        let there = Position::new(Vec3::new(0, 0, -(RETRIES as i32 + 20)), Direction::North);
+        let commander = state.read().await.get_turtle(id).await.unwrap();
+        let goto = tokio::spawn(async move { commander.goto_within(there, Duration::from_secs(60), true, false).await });
+        let (reached, _) = serve(&mut turtle, &state, id, &mut next, goto).await;
+        assert!(reached.is_some());
+        assert_eq!(turtle.pos, there);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn converging() {
+        let state = live_state();
+        // a tunnel along x through stone, one turtle at each end
+        let world = SharedWorld::new();
+        for x in -1..=7 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    if y != 0 || z != 0 || !(0..=6).contains(&x) {
+                        world.set(Block::new("minecraft:stone", Vec3::new(x, y, z))).await;
+                    }
+                }
+            }
+        }
+        let west = Position::new(Vec3::new(0, 0, 0), Direction::East);
+        let east = Position::new(Vec3::new(6, 0, 0), Direction::West);
+        let mut left = FakeTurtle::new(west, 1000, world);
+        let mut right = left.another(east);
+
+        let mut turtles = Vec::new();
+        for turtle in [&left, &right] {
+            let registered = register(&state, turtle, None).await;
+            turtles.push((registered.id, registered.command));
+        }
+        let [(left_id, mut left_next), (right_id, mut right_next)] = <[_; 2]>::try_from(turtles).unwrap();
+
+        // each heading for where the other started
+        let goto = |id, to: Position| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                let commander = state.read().await.get_turtle(id).await.unwrap();
+                commander.goto_within(to, Duration::from_secs(60), false, true).await
+            })
+        };
+        let to_east = Position::new(east.pos, Direction::East);
+        let to_west = Position::new(west.pos, Direction::West);
+        let left_goto = goto(left_id, to_east);
+        let right_goto = goto(right_id, to_west);
+        let ((left_reached, _), (right_reached, _)) = tokio::join!(
+            serve(&mut left, &state, left_id, &mut left_next, left_goto),
+            serve(&mut right, &state, right_id, &mut right_next, right_goto),
+        );
+        assert!(left_reached.is_some(), "#{left_id} stuck at {}", left.pos);
+        assert!(right_reached.is_some(), "#{right_id} stuck at {}", right.pos);
+        assert_eq!(left.pos, to_east);
+        assert_eq!(right.pos, to_west);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn batched_commands() {
+        let state = live_state();
+        let start = Position::new(Vec3::zeros(), Direction::North);
+        let mut turtle = FakeTurtle::new(start, 100, SharedWorld::new());
+        let registered = register(&state, &turtle, None).await;
+        let id = registered.id;
+        let mut next = registered.command;
+
+        // known open ground ahead, then a wall
+        let world = state.read().await.world.clone();
+        for i in 1..=6 {
+            world.set(Block::new("minecraft:air", start.pos + start.dir.unit() * i)).await;
+        }
+        let wall = start.pos + start.dir.unit() * 6 + start.dir.right().unit();
+        for world in [&world, &turtle.world] {
+            world.set(Block::new("minecraft:bedrock", wall)).await;
+        }
+
+        let commander = state.read().await.get_turtle(id).await.unwrap();
+        let goal = Position::new(start.pos + start.dir.unit() * 6, start.dir);
+        let task = tokio::spawn(async move {
+            commander.goto(goal).await;
+            // the turn goes through, the move doesn't, and the rest is never tried
+            commander.execute_batch(vec![TurtleCommand::Right, TurtleCommand::Forward(1), TurtleCommand::Left]).await
+        });
+        let mut sent = Vec::new();
+        while !task.is_finished() {
+            sent.push(next.clone());
+            let update = turtle.run(next.clone()).await;
+            (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        }
+        let results = task.await.unwrap();
+
+        assert!(sent.iter().any(|c| matches!(c, TurtleCommand::Forward(6))));
+        assert!(!sent.iter().any(|c| matches!(c, TurtleCommand::Forward(1))));
+        assert_eq!(results.len(), 2);
+        assert!(matches!(results[1], TurtleCommandResponse::Failure(FailureReason::Obstructed)));
+
+        let tracked = state.read().await.turtles[id as usize].read().await.position;
+        assert_eq!(tracked, turtle.pos);
+        assert_eq!(tracked, Position::new(goal.pos, start.dir.right()));
+    }
+
+    /// Run a delivery of `count` iron between two chests until it is done, or `steps` commands go by
+    async fn delivery(turtle: &mut FakeTurtle, count: u32, steps: usize) -> Deliver {
+        let state = live_state();
+        let registered = register(&state, turtle, Some(Role::Hauler)).await;
+        let id = registered.id;
+
+        let source = Position::new(Vec3::new(0, 0, -2), Direction::North);
+        let dest = Position::new(Vec3::new(2, 0, -2), Direction::North);
+        let deliver = Deliver::new(source, dest, "minecraft:iron_ingot".into(), count);
+        let tasks = state.read().await.tasks.clone();
+        tasks.add_task(Box::new(deliver.clone()));
+        state.read().await.ready.store(true, Ordering::Relaxed);
+
+        let mut next = registered.command;
+        for _ in 0..steps {
+            if tasks.task_count() == 0 {
+                break;
+            }
+            let update = turtle.run(next).await;
+            (_, Json(next)) = command(Path(id), State(state.clone()), Json(update)).await;
+        }
+        deliver
+    }
+
+    /// Turtle with a source chest holding `source` and a destination chest holding `dest`
+    async fn courier(source: Vec<Option<InventorySlot>>, dest: Vec<Option<InventorySlot>>) -> FakeTurtle {
+        let mut turtle = FakeTurtle::new(Position::new(Vec3::zeros(), Direction::North), 100_000, SharedWorld::new());
+        turtle.chest(Vec3::new(0, 0, -3), source).await;
+        turtle.chest(Vec3::new(2, 0, -3), dest).await;
+        turtle
+    }
+
+    fn done(deliver: &Deliver) -> bool {
+        matches!(deliver.clone().poll(), TaskState::Complete)
+    }
+
+    fn delivered(deliver: &Deliver) -> u64 {
+        serde_json::to_value(deliver.progress().unwrap()).unwrap()["done"].as_u64().unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivery_behind_other_stacks() {
+        let source = vec![stack("minecraft:dirt", 64), stack("minecraft:cobblestone", 64), stack("minecraft:iron_ingot", 10)];
+        let mut turtle = courier(source, vec![None; 27]).await;
+
+        let deliver = delivery(&mut turtle, 10, 2000).await;
+        assert!(done(&deliver));
+        assert_eq!(delivered(&deliver), 10);
+        let source = &turtle.chests[&Vec3::new(0, 0, -3)];
+        assert!(source.iter().flatten().all(|s| s.name != "minecraft:iron_ingot"));
+        assert_eq!(source.iter().flatten().count(), 2);
+        assert_eq!(turtle.chests[&Vec3::new(2, 0, -3)][0].as_ref().unwrap().count, 10);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivery_runs_out() {
+        let mut turtle = courier(vec![stack("minecraft:dirt", 64), None], vec![None; 27]).await;
+
+        let deliver = delivery(&mut turtle, 10, 2000).await;
+        // everything in the chest was looked at, so it really is out
+        assert!(done(&deliver));
+        assert_eq!(delivered(&deliver), 0);
+        assert!(turtle.chests[&Vec3::new(0, 0, -3)][0].is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivery_with_full_inventory() {
+        let mut turtle = courier(vec![stack("minecraft:iron_ingot", 10)], vec![None; 27]).await;
+        turtle.inventory = vec![stack("minecraft:dirt", 64); 16];
+
+        let deliver = delivery(&mut turtle, 10, 500).await;
+        // given up on rather than handed out again, without touching the source
+        assert!(done(&deliver));
+        assert_eq!(delivered(&deliver), 0);
+        assert!(turtle.chests[&Vec3::new(0, 0, -3)][0].is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn delivery_into_nearly_full_chest() {
+        let mut turtle = courier(vec![stack("minecraft:iron_ingot", 64)], vec![stack("minecraft:iron_ingot", 60)]).await;
+
+        let deliver = delivery(&mut turtle, 10, 500).await;
+        // the chest won't empty by trying again
+        assert!(done(&deliver));
+        // only what fit counts
+        assert_eq!(delivered(&deliver), 4);
+        assert_eq!(turtle.chests[&Vec3::new(2, 0, -3)][0].as_ref().unwrap().count, 64);
+    }
 }
@@ -0,0 +1,159 @@
+//! Changes for live viewers such as a map, sent over a broadcast channel
+//!
+//! A turtle update sets three blocks, so block changes are collected and sent
+//! as one delta every [`BLOCK_BATCH`], while turtle moves go out as they happen.
+//! Viewers connect to `/world/updates` and get each update as a JSON message
+
+use std::{collections::HashMap, sync::{Arc, Mutex}, time::Duration};
+
+use axum::{extract::{State, ws::{Message, WebSocket, WebSocketUpgrade}}, response::Response};
+use serde::Serialize;
+use tokio::{sync::broadcast::{self, error::RecvError}, time::MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::{SharedControl, blocks::{Block, Position, Vec3}};
+
+/// How often collected block changes are sent
+pub const BLOCK_BATCH: Duration = Duration::from_millis(250);
+/// Updates a subscriber can fall behind by before it starts missing them
+const BACKLOG: usize = 256;
+
+#[derive(Serialize, Clone, Debug)]
+pub enum Update {
+    /// Blocks changed since the last batch, only the latest for each position
+    Blocks(Vec<Block>),
+    /// A turtle moved or turned
+    Turtle { id: u32, position: Position },
+}
+
+#[derive(Clone)]
+pub struct Publisher {
+    sender: broadcast::Sender<Update>,
+    pending: Arc<Mutex<HashMap<Vec3, Block>>>,
+    /// Last position sent for each turtle, so idle ones stay quiet
+    turtles: Arc<Mutex<HashMap<u32, Position>>>,
+}
+
+impl Publisher {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BACKLOG);
+        Self { sender, pending: Default::default(), turtles: Default::default() }
+    }
+
+    /// Updates from the next one on
+    pub fn subscribe(&self) -> broadcast::Receiver<Update> {
+        self.sender.subscribe()
+    }
+
+    /// Queue a block change for the next batch, nothing is kept while no one listens
+    pub fn block(&self, block: &Block) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        self.pending.lock().unwrap().insert(block.pos, block.clone());
+    }
+
+    /// Send a turtle's position right away if it changed, nothing is kept while no one listens
+    ///
+    /// So a viewer sees each turtle once it next moves
+    pub fn turtle(&self, id: u32, position: Position) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+        if self.turtles.lock().unwrap().insert(id, position) == Some(position) {
+            return;
+        }
+        // fails only when no one is listening
+        let _ = self.sender.send(Update::Turtle { id, position });
+    }
+
+    /// Send the blocks changed since the last flush as one update
+    pub fn flush(&self) {
+        let blocks: Vec<Block> = self.pending.lock().unwrap().drain().map(|(_, b)| b).collect();
+        if !blocks.is_empty() {
+            let _ = self.sender.send(Update::Blocks(blocks));
+        }
+    }
+
+    /// Flush every [`BLOCK_BATCH`], forever
+    pub async fn publish(self) {
+        let mut interval = tokio::time::interval(BLOCK_BATCH);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            self.flush();
+        }
+    }
+}
+
+/// Send updates to a live viewer, such as a map, until it disconnects
+pub(crate) async fn viewer(
+    State(state): State<SharedControl>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let updates = state.read().await.world.updates().subscribe();
+    upgrade.on_upgrade(move |socket| forward(socket, updates))
+}
+
+async fn forward(mut socket: WebSocket, mut updates: broadcast::Receiver<Update>) {
+    info!("live viewer connected");
+    loop {
+        tokio::select! {
+            update = updates.recv() => match update {
+                Ok(update) => {
+                    let update = serde_json::to_string(&update).unwrap();
+                    if socket.send(Message::Text(update)).await.is_err() {
+                        break;
+                    }
+                },
+                Err(RecvError::Lagged(missed)) => warn!("live viewer fell {missed} updates behind"),
+                Err(RecvError::Closed) => break,
+            },
+            // viewers only listen, but hanging up still has to end this
+            message = socket.recv() => if let Some(Ok(Message::Close(_)) | Err(_)) | None = message {
+                break;
+            },
+        }
+    }
+    info!("live viewer disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocks::{Direction, SharedWorld};
+
+    #[tokio::test]
+    async fn batched() {
+        let world = SharedWorld::new();
+        let mut updates = world.updates().subscribe();
+
+        world.set(Block::new("minecraft:stone", Vec3::new(0, 0, 0))).await;
+        world.set(Block::new("minecraft:air", Vec3::new(0, 0, 0))).await;
+        world.set(Block::new("minecraft:dirt", Vec3::new(0, 1, 0))).await;
+        let here = Position::new(Vec3::new(0, 2, 0), Direction::North);
+        world.updates().turtle(1, here);
+        world.updates().turtle(1, here);
+
+        // turtles don't wait for the batch
+        assert!(matches!(updates.try_recv(), Ok(Update::Turtle { id: 1, .. })));
+        assert!(updates.try_recv().is_err());
+
+        world.updates().flush();
+        let Ok(Update::Blocks(mut blocks)) = updates.try_recv() else { panic!("no batch") };
+        blocks.sort_by_key(|b| b.pos.y);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].name, "minecraft:air");
+        assert!(updates.try_recv().is_err());
+
+        world.updates().flush();
+        assert!(updates.try_recv().is_err());
+
+        // nothing is collected without listeners
+        drop(updates);
+        world.set(Block::new("minecraft:stone", Vec3::new(0, 3, 0))).await;
+        world.updates().turtle(2, here);
+        assert!(world.updates().pending.lock().unwrap().is_empty());
+        assert!(!world.updates().turtles.lock().unwrap().contains_key(&2));
+    }
+}
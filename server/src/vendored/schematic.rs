@@ -69,7 +69,7 @@ impl Schematic {
 
     #[allow(unused, clippy::unwrap_used)]
     pub fn height(&self) -> u64 {
-        u64::try_from(self.width).unwrap()
+        u64::try_from(self.height).unwrap()
     }
 
     #[allow(unused, clippy::unwrap_used)]